@@ -6,15 +6,21 @@ import kotlinx.coroutines.flow.MutableStateFlow
 import kotlinx.coroutines.flow.SharingStarted
 import kotlinx.coroutines.flow.asSharedFlow
 import kotlinx.coroutines.flow.asStateFlow
+import kotlinx.coroutines.flow.collectLatest
 import kotlinx.coroutines.flow.combine
+import kotlinx.coroutines.flow.distinctUntilChanged
+import kotlinx.coroutines.flow.dropWhile
 import kotlinx.coroutines.flow.stateIn
 import kotlinx.coroutines.launch
+import uniffi.ease_client_backend.ArgPrefetchNextMusics
 import uniffi.ease_client_backend.ArgRemoveMusicFromPlaylist
 import uniffi.ease_client_backend.ArgUpdateMusicLyric
 import uniffi.ease_client_backend.Music
 import uniffi.ease_client_backend.Playlist
+import uniffi.ease_client_backend.ctPrefetchNextMusics
 import uniffi.ease_client_backend.ctRemoveMusicFromPlaylist
 import uniffi.ease_client_backend.ctUpdateMusicLyric
+import uniffi.ease_client_backend.ctsCancelPrefetch
 import uniffi.ease_client_backend.ctsGetPreferencePlaymode
 import uniffi.ease_client_backend.ctsSavePreferencePlaymode
 import uniffi.ease_client_schema.PlayMode
@@ -95,6 +101,29 @@ class PlayerRepository @Inject constructor(
         }
     }.stateIn(_scope, SharingStarted.Eagerly, null)
 
+    init {
+        _scope.launch {
+            combine(_music, _playlist, _playMode) { music, playlist, playMode ->
+                if (music == null || playlist == null) {
+                    null
+                } else {
+                    ArgPrefetchNextMusics(
+                        playlistId = playlist.abstr.meta.id,
+                        musicId = music.meta.id,
+                        playMode = playMode
+                    ) to playlist.musics.map { it.meta.id }
+                }
+            }.distinctUntilChanged().dropWhile { it == null }.collectLatest { arg ->
+                // A newer queue stops the prefetch of the previous one
+                if (arg == null) {
+                    bridge.run { ctsCancelPrefetch(it) }
+                } else {
+                    bridge.run { ctPrefetchNextMusics(it, arg.first) }
+                }
+            }
+        }
+    }
+
     fun setIsPlaying(playing: Boolean) {
         _playing.value = playing
     }
@@ -18,10 +18,16 @@ import kotlinx.coroutines.flow.map
 import kotlinx.coroutines.flow.stateIn
 import kotlinx.coroutines.launch
 import uniffi.ease_client_backend.ArgUpsertStorage
+import uniffi.ease_client_backend.OneDriveDriveEntry
 import uniffi.ease_client_backend.StorageConnectionTestResult
+import uniffi.ease_client_backend.ctListOnedriveDrives
+import uniffi.ease_client_backend.ctOnedriveOauthUrl
 import uniffi.ease_client_backend.ctRemoveStorage
 import uniffi.ease_client_backend.ctTestStorage
 import uniffi.ease_client_schema.StorageId
+import uniffi.ease_client_schema.StorageNetworkSettings
+import uniffi.ease_client_schema.StorageOneDriveSettings
+import uniffi.ease_client_schema.StorageSftpSettings
 import uniffi.ease_client_schema.StorageType
 import javax.inject.Inject
 
@@ -37,6 +43,35 @@ data class Validated(
     }
 }
 
+private fun defaultStorageNetworkSettings(): StorageNetworkSettings {
+    return StorageNetworkSettings(
+        rootCaPem = null,
+        pinnedCertSha256 = null,
+        acceptInvalidCerts = false,
+        proxy = null,
+        headers = listOf(),
+        userAgent = null,
+        connectTimeoutMs = null,
+        readTimeoutMs = null,
+    )
+}
+
+private fun defaultStorageSftpSettings(): StorageSftpSettings {
+    return StorageSftpSettings(
+        hostKeyFingerprint = null,
+        keyPassphrase = null,
+    )
+}
+
+private fun defaultStorageOneDriveSettings(): StorageOneDriveSettings {
+    return StorageOneDriveSettings(
+        graphApi = null,
+        loginApi = null,
+        clientId = null,
+        redirectUri = null,
+    )
+}
+
 private fun defaultArgUpsertStorage(): ArgUpsertStorage {
     return ArgUpsertStorage(
         id = null,
@@ -46,6 +81,9 @@ private fun defaultArgUpsertStorage(): ArgUpsertStorage {
         password = "",
         isAnonymous = true,
         typ = StorageType.WEBDAV,
+        network = defaultStorageNetworkSettings(),
+        sftp = defaultStorageSftpSettings(),
+        onedrive = defaultStorageOneDriveSettings(),
     )
 }
 
@@ -67,6 +105,7 @@ class EditStorageVM @Inject constructor(
     private val _removeModalOpen = MutableStateFlow(false)
     private val _testResult = MutableStateFlow(StorageConnectionTestResult.NONE)
     private var _testJob: Job? = null
+    private val _onedriveDrives = MutableStateFlow(listOf<OneDriveDriveEntry>())
 
     val form = _form.asStateFlow()
     val musicCount = _musicCount.asStateFlow()
@@ -77,6 +116,7 @@ class EditStorageVM @Inject constructor(
     val isCreated = form.map { form -> form.id == null }
         .stateIn(viewModelScope, SharingStarted.Lazily, true)
     val testResult = _testResult.asStateFlow()
+    val onedriveDrives = _onedriveDrives.asStateFlow()
 
     init {
         viewModelScope.launch {
@@ -88,6 +128,7 @@ class EditStorageVM @Inject constructor(
                     }
                     storage
                 }
+                loadOnedriveDrives()
             }
         }
 
@@ -105,11 +146,31 @@ class EditStorageVM @Inject constructor(
                 username = storage.username,
                 password = storage.password,
                 isAnonymous = storage.isAnonymous,
-                typ = storage.typ
+                typ = storage.typ,
+                network = storage.network,
+                sftp = storage.sftp,
+                onedrive = storage.onedrive,
             )
             _title.value = VImportStorageEntry(storage).name
             _musicCount.value = storage.musicCount
+            loadOnedriveDrives()
+        }
+    }
+
+    fun loadOnedriveDrives() {
+        val f = _form.value
+        if (f.typ != StorageType.ONE_DRIVE || f.password.isEmpty()) {
+            _onedriveDrives.value = listOf()
+            return
         }
+        viewModelScope.launch {
+            _onedriveDrives.value = bridge.run { ctListOnedriveDrives(it, f) } ?: listOf()
+        }
+    }
+
+    fun onedriveOauthUrl(): String {
+        val f = _form.value
+        return bridge.runSync { ctOnedriveOauthUrl(it, f) } ?: ""
     }
 
     fun test() {
@@ -144,6 +205,33 @@ class EditStorageVM @Inject constructor(
             StorageConnectionTestResult.UNAUTHORIZED -> {
                 toastRepository.emitToastRes(R.string.storage_edit_testing_toast_unauth)
             }
+            StorageConnectionTestResult.FORBIDDEN -> {
+                toastRepository.emitToastRes(R.string.storage_edit_testing_toast_forbidden)
+            }
+            StorageConnectionTestResult.RATE_LIMITED -> {
+                toastRepository.emitToastRes(R.string.storage_edit_testing_toast_rate_limited)
+            }
+            StorageConnectionTestResult.SERVER_ERROR -> {
+                toastRepository.emitToastRes(R.string.storage_edit_testing_toast_server_error)
+            }
+            StorageConnectionTestResult.TLS_ERROR -> {
+                toastRepository.emitToastRes(R.string.storage_edit_testing_toast_tls)
+            }
+            StorageConnectionTestResult.DNS_ERROR -> {
+                toastRepository.emitToastRes(R.string.storage_edit_testing_toast_dns)
+            }
+            StorageConnectionTestResult.CONNECTION_REFUSED -> {
+                toastRepository.emitToastRes(R.string.storage_edit_testing_toast_connection_refused)
+            }
+            StorageConnectionTestResult.INSUFFICIENT_STORAGE -> {
+                toastRepository.emitToastRes(R.string.storage_edit_testing_toast_insufficient_storage)
+            }
+            StorageConnectionTestResult.METHOD_NOT_ALLOWED -> {
+                toastRepository.emitToastRes(R.string.storage_edit_testing_toast_method_not_allowed)
+            }
+            StorageConnectionTestResult.HOST_KEY_MISMATCH -> {
+                toastRepository.emitToastRes(R.string.storage_edit_testing_toast_host_key_mismatch)
+            }
             StorageConnectionTestResult.OTHER_ERROR -> {
                 toastRepository.emitToastRes(R.string.storage_edit_testing_toast_other_error)
             }
@@ -178,11 +266,15 @@ class EditStorageVM @Inject constructor(
                 username = "",
                 password = "",
                 isAnonymous = false,
-                typ = typ
+                typ = typ,
+                network = _form.value.network,
+                sftp = defaultStorageSftpSettings(),
+                onedrive = defaultStorageOneDriveSettings(),
             )
             _form.value = newForm
         }
         _validated.value = Validated()
+        loadOnedriveDrives()
     }
 
     private fun validate(): Boolean {
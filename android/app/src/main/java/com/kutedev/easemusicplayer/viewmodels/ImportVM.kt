@@ -201,6 +201,42 @@ class ImportVM @Inject constructor(
                     _loadState.value = CurrentStorageStateType.TIMEOUT
                 }
 
+                ListStorageEntryChildrenResp.Forbidden -> {
+                    _loadState.value = CurrentStorageStateType.FORBIDDEN
+                }
+
+                is ListStorageEntryChildrenResp.RateLimited -> {
+                    _loadState.value = CurrentStorageStateType.RATE_LIMITED
+                }
+
+                is ListStorageEntryChildrenResp.ServerError -> {
+                    _loadState.value = CurrentStorageStateType.SERVER_ERROR
+                }
+
+                ListStorageEntryChildrenResp.TlsError -> {
+                    _loadState.value = CurrentStorageStateType.TLS_ERROR
+                }
+
+                ListStorageEntryChildrenResp.DnsError -> {
+                    _loadState.value = CurrentStorageStateType.DNS_ERROR
+                }
+
+                ListStorageEntryChildrenResp.ConnectionRefused -> {
+                    _loadState.value = CurrentStorageStateType.CONNECTION_REFUSED
+                }
+
+                ListStorageEntryChildrenResp.InsufficientStorage -> {
+                    _loadState.value = CurrentStorageStateType.INSUFFICIENT_STORAGE
+                }
+
+                ListStorageEntryChildrenResp.MethodNotAllowed -> {
+                    _loadState.value = CurrentStorageStateType.METHOD_NOT_ALLOWED
+                }
+
+                ListStorageEntryChildrenResp.HostKeyMismatch -> {
+                    _loadState.value = CurrentStorageStateType.HOST_KEY_MISMATCH
+                }
+
                 ListStorageEntryChildrenResp.Unknown -> {
                     _loadState.value = CurrentStorageStateType.UNKNOWN_ERROR
                 }
@@ -60,8 +60,8 @@ import com.kutedev.easemusicplayer.components.FormWidget
 import com.kutedev.easemusicplayer.viewmodels.EditStorageVM
 import com.kutedev.easemusicplayer.core.LocalNavController
 import kotlinx.coroutines.flow.update
+import uniffi.ease_client_backend.OneDriveDriveKind
 import uniffi.ease_client_backend.StorageConnectionTestResult
-import uniffi.ease_client_backend.ctOnedriveOauthUrl
 import uniffi.ease_client_schema.StorageType
 import androidx.core.net.toUri
 import androidx.hilt.navigation.compose.hiltViewModel
@@ -239,6 +239,7 @@ private fun OneDriveConfig(
     val context = LocalContext.current
     val form by editStorageVM.form.collectAsState()
     val validated by editStorageVM.validated.collectAsState()
+    val drives by editStorageVM.onedriveDrives.collectAsState()
     val connected = form.password.isNotEmpty()
 
     FormText(
@@ -263,7 +264,7 @@ private fun OneDriveConfig(
                 type = EaseTextButtonType.PrimaryVariant,
                 size = EaseTextButtonSize.Medium,
                 onClick = {
-                    val intent = Intent(Intent.ACTION_VIEW, ctOnedriveOauthUrl().toUri())
+                    val intent = Intent(Intent.ACTION_VIEW, editStorageVM.onedriveOauthUrl().toUri())
                     intent.flags = FLAG_ACTIVITY_NEW_TASK
                     context.startActivity(intent)
                 },
@@ -288,12 +289,42 @@ private fun OneDriveConfig(
                 onClick = {
                     editStorageVM.updateForm { storage ->
                         storage.password = ""
+                        storage.addr = ""
                         storage
                     }
+                    editStorageVM.loadOnedriveDrives()
                 },
             )
         }
     }
+    if (connected && drives.isNotEmpty()) {
+        FormWidget(
+            label = stringResource(R.string.storage_edit_onedrive_drive)
+        ) {
+            for (drive in drives) {
+                val selected = drive.addr == form.addr
+                Text(
+                    modifier = Modifier
+                        .fillMaxWidth()
+                        .clip(RoundedCornerShape(4.dp))
+                        .background(if (selected) MaterialTheme.colorScheme.secondaryContainer else Color.Transparent)
+                        .clickable {
+                            editStorageVM.updateForm { storage ->
+                                storage.addr = drive.addr
+                                storage
+                            }
+                        }
+                        .padding(horizontal = 8.dp, vertical = 10.dp),
+                    text = when (drive.kind) {
+                        OneDriveDriveKind.PERSONAL -> stringResource(R.string.storage_edit_onedrive_drive_personal)
+                        OneDriveDriveKind.SHARED_WITH_ME -> stringResource(R.string.storage_edit_onedrive_drive_shared, drive.name)
+                        OneDriveDriveKind.SHARE_POINT -> stringResource(R.string.storage_edit_onedrive_drive_sharepoint, drive.name)
+                    },
+                    fontSize = 14.sp,
+                )
+            }
+        }
+    }
 }
 
 @Composable
@@ -433,6 +433,15 @@ private fun ImportMusicsError(
     val title = when (type) {
         CurrentStorageStateType.AUTHENTICATION_FAILED -> stringResource(id = R.string.import_musics_error_authentication_title)
         CurrentStorageStateType.TIMEOUT -> stringResource(id = R.string.import_musics_error_timeout_title)
+        CurrentStorageStateType.FORBIDDEN -> stringResource(id = R.string.import_musics_error_forbidden_title)
+        CurrentStorageStateType.RATE_LIMITED -> stringResource(id = R.string.import_musics_error_rate_limited_title)
+        CurrentStorageStateType.SERVER_ERROR -> stringResource(id = R.string.import_musics_error_server_error_title)
+        CurrentStorageStateType.TLS_ERROR -> stringResource(id = R.string.import_musics_error_tls_title)
+        CurrentStorageStateType.DNS_ERROR -> stringResource(id = R.string.import_musics_error_dns_title)
+        CurrentStorageStateType.CONNECTION_REFUSED -> stringResource(id = R.string.import_musics_error_connection_refused_title)
+        CurrentStorageStateType.INSUFFICIENT_STORAGE -> stringResource(id = R.string.import_musics_error_insufficient_storage_title)
+        CurrentStorageStateType.METHOD_NOT_ALLOWED -> stringResource(id = R.string.import_musics_error_method_not_allowed_title)
+        CurrentStorageStateType.HOST_KEY_MISMATCH -> stringResource(id = R.string.import_musics_error_host_key_mismatch_title)
         CurrentStorageStateType.UNKNOWN_ERROR -> stringResource(id = R.string.import_musics_error_unknown_title)
         CurrentStorageStateType.NEED_PERMISSION -> stringResource(id = R.string.import_musics_error_permission_title)
         else -> {
@@ -442,6 +451,15 @@ private fun ImportMusicsError(
     val desc = when (type) {
         CurrentStorageStateType.AUTHENTICATION_FAILED -> stringResource(id = R.string.import_musics_error_authentication_desc)
         CurrentStorageStateType.TIMEOUT -> stringResource(id = R.string.import_musics_error_timeout_desc)
+        CurrentStorageStateType.FORBIDDEN -> stringResource(id = R.string.import_musics_error_forbidden_desc)
+        CurrentStorageStateType.RATE_LIMITED -> stringResource(id = R.string.import_musics_error_rate_limited_desc)
+        CurrentStorageStateType.SERVER_ERROR -> stringResource(id = R.string.import_musics_error_server_error_desc)
+        CurrentStorageStateType.TLS_ERROR -> stringResource(id = R.string.import_musics_error_tls_desc)
+        CurrentStorageStateType.DNS_ERROR -> stringResource(id = R.string.import_musics_error_dns_desc)
+        CurrentStorageStateType.CONNECTION_REFUSED -> stringResource(id = R.string.import_musics_error_connection_refused_desc)
+        CurrentStorageStateType.INSUFFICIENT_STORAGE -> stringResource(id = R.string.import_musics_error_insufficient_storage_desc)
+        CurrentStorageStateType.METHOD_NOT_ALLOWED -> stringResource(id = R.string.import_musics_error_method_not_allowed_desc)
+        CurrentStorageStateType.HOST_KEY_MISMATCH -> stringResource(id = R.string.import_musics_error_host_key_mismatch_desc)
         CurrentStorageStateType.UNKNOWN_ERROR -> stringResource(id = R.string.import_musics_error_unknown_desc)
         CurrentStorageStateType.NEED_PERMISSION -> stringResource(id = R.string.import_musics_error_permission_desc)
         else -> {
@@ -535,6 +553,15 @@ fun ImportMusicsPage(
             CurrentStorageStateType.LOADING -> ImportEntriesSkeleton()
             CurrentStorageStateType.TIMEOUT,
             CurrentStorageStateType.AUTHENTICATION_FAILED,
+            CurrentStorageStateType.FORBIDDEN,
+            CurrentStorageStateType.RATE_LIMITED,
+            CurrentStorageStateType.SERVER_ERROR,
+            CurrentStorageStateType.TLS_ERROR,
+            CurrentStorageStateType.DNS_ERROR,
+            CurrentStorageStateType.CONNECTION_REFUSED,
+            CurrentStorageStateType.INSUFFICIENT_STORAGE,
+            CurrentStorageStateType.METHOD_NOT_ALLOWED,
+            CurrentStorageStateType.HOST_KEY_MISMATCH,
             CurrentStorageStateType.UNKNOWN_ERROR,
             CurrentStorageStateType.NEED_PERMISSION -> ImportMusicsError()
             else -> {
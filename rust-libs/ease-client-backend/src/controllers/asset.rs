@@ -4,7 +4,11 @@ use bytes::Bytes;
 use ease_client_schema::DataSourceKey;
 use ease_remote_storage::{StorageBackendError, StorageBackendResult};
 
-use crate::{error::BResult, services::get_asset_file, Backend};
+use crate::{
+    error::BResult,
+    services::{get_asset_file, get_asset_file_range},
+    Backend,
+};
 
 #[uniffi::export]
 pub async fn ct_get_asset(cx: Arc<Backend>, key: DataSourceKey) -> BResult<Option<Vec<u8>>> {
@@ -18,6 +22,24 @@ pub async fn ct_get_asset(cx: Arc<Backend>, key: DataSourceKey) -> BResult<Optio
     Ok(Some(buf.to_vec()))
 }
 
+/// Reads the bytes in `start..end`, for callers that only need the head of a file.
+#[uniffi::export]
+pub async fn ct_get_asset_range(
+    cx: Arc<Backend>,
+    key: DataSourceKey,
+    start: u64,
+    end: u64,
+) -> BResult<Option<Vec<u8>>> {
+    let cx = cx.get_context();
+    let file = get_asset_file_range(cx, key, start, end).await?;
+    let Some(file) = file else {
+        return Ok(None);
+    };
+
+    let buf = file.bytes().await?;
+    Ok(Some(buf.to_vec()))
+}
+
 #[derive(uniffi::Object)]
 pub struct AssetStream {
     stream: async_channel::Receiver<StorageBackendResult<Bytes>>,
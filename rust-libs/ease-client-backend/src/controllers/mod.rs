@@ -1,6 +1,7 @@
 mod asset;
 mod debug;
 mod music;
+mod player;
 mod playlist;
 mod preference;
 mod storage;
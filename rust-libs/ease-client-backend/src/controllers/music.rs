@@ -4,10 +4,11 @@ use ease_client_schema::MusicId;
 
 use crate::{
     error::BResult,
-    objects::Music,
+    objects::{Music, RemoteCacheEntryState},
     services::{
-        get_music, get_music_abstract, update_music_cover, update_music_duration,
-        ArgUpdateMusicCover, ArgUpdateMusicDuration, ArgUpdateMusicLyric,
+        get_music, get_music_abstract, get_music_offline_state, pin_music_offline,
+        update_music_cover, update_music_duration, ArgUpdateMusicCover, ArgUpdateMusicDuration,
+        ArgUpdateMusicLyric,
     },
     Backend, MusicAbstract,
 };
@@ -24,6 +25,22 @@ pub fn cts_get_music_abstract(cx: Arc<Backend>, id: MusicId) -> BResult<Option<M
     get_music_abstract(cx, id)
 }
 
+/// Returns once the music is downloaded.
+#[uniffi::export]
+pub async fn ct_pin_music_offline(cx: Arc<Backend>, id: MusicId, pinned: bool) -> BResult<()> {
+    let cx = cx.get_context();
+    pin_music_offline(cx, id, pinned).await
+}
+
+#[uniffi::export]
+pub fn cts_get_music_offline_state(
+    cx: Arc<Backend>,
+    id: MusicId,
+) -> BResult<RemoteCacheEntryState> {
+    let cx = cx.get_context();
+    get_music_offline_state(cx, id)
+}
+
 #[uniffi::export]
 pub async fn ct_update_music_lyric(cx: Arc<Backend>, arg: ArgUpdateMusicLyric) -> BResult<()> {
     let cx = cx.get_context();
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+
+use crate::{
+    error::BResult,
+    objects::ArgPrefetchNextMusics,
+    services::{cancel_prefetch, get_prefetch_budget, prefetch_next_musics, set_prefetch_budget},
+    Backend,
+};
+
+/// Call again whenever the queue or the play mode changes, the previous prefetch stops.
+#[uniffi::export]
+pub async fn ct_prefetch_next_musics(cx: Arc<Backend>, arg: ArgPrefetchNextMusics) -> BResult<()> {
+    let cx = cx.get_context();
+    prefetch_next_musics(cx, arg).await
+}
+
+#[uniffi::export]
+pub fn cts_cancel_prefetch(cx: Arc<Backend>) {
+    let cx = cx.get_context();
+    cancel_prefetch(cx)
+}
+
+#[uniffi::export]
+pub fn cts_get_prefetch_budget(cx: Arc<Backend>) -> u64 {
+    let cx = cx.get_context();
+    get_prefetch_budget(cx)
+}
+
+#[uniffi::export]
+pub fn cts_set_prefetch_budget(cx: Arc<Backend>, budget: u64) {
+    let cx = cx.get_context();
+    set_prefetch_budget(cx, budget)
+}
@@ -9,12 +9,32 @@ use crate::{
     objects::{Playlist, PlaylistAbstract},
     repositories::{music::ArgDBAddMusic, playlist::AddedMusic},
     services::{
-        get_all_playlist_abstracts, get_playlist, ArgAddMusicsToPlaylist, ArgCreatePlaylist,
+        get_all_playlist_abstracts, get_playlist, is_playlist_pinned_offline,
+        pin_added_playlist_musics, pin_playlist_offline, unpin_playlist_music,
+        unpin_removed_playlist, ArgAddMusicsToPlaylist, ArgCreatePlaylist,
         ArgRemoveMusicFromPlaylist, ArgUpdatePlaylist,
     },
     Backend,
 };
 
+/// Returns once the musics are downloaded. Later additions are pinned too but only
+/// downloaded on play, pinning again fetches whatever is missing.
+#[uniffi::export]
+pub async fn ct_pin_playlist_offline(
+    cx: Arc<Backend>,
+    id: PlaylistId,
+    pinned: bool,
+) -> BResult<()> {
+    let cx = cx.get_context();
+    pin_playlist_offline(cx, id, pinned).await
+}
+
+#[uniffi::export]
+pub fn cts_is_playlist_pinned_offline(cx: Arc<Backend>, id: PlaylistId) -> BResult<bool> {
+    let cx = cx.get_context();
+    is_playlist_pinned_offline(cx, id)
+}
+
 #[uniffi::export]
 pub async fn ct_get_playlist(cx: Arc<Backend>, arg: PlaylistId) -> BResult<Option<Playlist>> {
     let cx = cx.get_context();
@@ -121,6 +141,7 @@ pub async fn ct_add_musics_to_playlist(
     let ret = cx
         .database_server()
         .add_musics_to_playlist(arg.id, musics, last_order)?;
+    pin_added_playlist_musics(cx, arg.id, ret.iter().map(|m| m.id))?;
 
     Ok(ret)
 }
@@ -131,6 +152,7 @@ pub async fn ct_remove_music_from_playlist(
     arg: ArgRemoveMusicFromPlaylist,
 ) -> BResult<()> {
     let cx = cx.get_context();
+    unpin_playlist_music(cx, arg.playlist_id, arg.music_id)?;
     cx.database_server()
         .remove_music_from_playlist(arg.playlist_id, arg.music_id)?;
 
@@ -201,6 +223,7 @@ pub fn cts_reorder_playlist(cx: Arc<Backend>, arg: ArgReorderPlaylist) -> BResul
 pub async fn ct_remove_playlist(cx: Arc<Backend>, arg: PlaylistId) -> BResult<()> {
     let cx = cx.get_context();
     cx.database_server().remove_playlist(arg)?;
+    unpin_removed_playlist(cx, arg);
 
     Ok(())
 }
@@ -268,3 +291,98 @@ pub fn cts_reorder_music_in_playlist(cx: Arc<Backend>, arg: ArgReorderMusic) ->
     cx.database_server().set_music_order(from.meta.id, order)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use ease_client_schema::StorageId;
+    use ease_client_tokio::tokio_runtime;
+
+    use super::{
+        ct_add_musics_to_playlist, ct_pin_playlist_offline, ct_remove_music_from_playlist,
+        ct_remove_playlist, cts_is_playlist_pinned_offline,
+    };
+    use crate::{
+        controllers::music::ct_pin_music_offline,
+        objects::StorageEntry,
+        services::{
+            get_remote_file_cache, ArgAddMusicsToPlaylist, ArgRemoveMusicFromPlaylist,
+            ToAddMusicEntry,
+        },
+        utils::testing::{add_memory_storage, add_playlist, test_backend},
+    };
+
+    fn to_add(storage_id: StorageId, path: &str) -> ToAddMusicEntry {
+        ToAddMusicEntry {
+            entry: StorageEntry {
+                storage_id,
+                name: path.to_string(),
+                path: path.to_string(),
+                size: None,
+                is_dir: false,
+                modified_time: None,
+                etag: None,
+                content_type: None,
+            },
+            name: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn playlist_pins_follow_the_playlist() {
+        let backend = test_backend("playlist-pins");
+        let cx = backend.get_context();
+        let (storage_id, remote) = add_memory_storage(cx, "playlist-pins");
+        for path in ["/music/a.mp3", "/music/b.mp3", "/music/c.mp3"] {
+            remote.add_file(path, &b"m"[..]);
+        }
+        let (playlist_id, ids) = add_playlist(cx, storage_id, &["/music/a.mp3", "/music/b.mp3"]);
+        let cache = get_remote_file_cache(cx).unwrap();
+        let namespace = storage_id.as_ref().to_string();
+        let is_pinned = |path: &str| cache.is_pinned(&namespace, path);
+        let rt = tokio_runtime();
+
+        rt.block_on(ct_pin_music_offline(backend.clone(), ids[0], true))
+            .unwrap();
+        rt.block_on(ct_pin_playlist_offline(backend.clone(), playlist_id, true))
+            .unwrap();
+        assert!(cts_is_playlist_pinned_offline(backend.clone(), playlist_id).unwrap());
+        assert!(is_pinned("/music/a.mp3"));
+        assert!(is_pinned("/music/b.mp3"));
+
+        // Additions follow the playlist pin
+        rt.block_on(ct_add_musics_to_playlist(
+            backend.clone(),
+            ArgAddMusicsToPlaylist {
+                id: playlist_id,
+                entries: vec![to_add(storage_id, "/music/c.mp3")],
+            },
+        ))
+        .unwrap();
+        assert!(is_pinned("/music/c.mp3"));
+
+        rt.block_on(ct_remove_music_from_playlist(
+            backend.clone(),
+            ArgRemoveMusicFromPlaylist {
+                playlist_id,
+                music_id: ids[1],
+            },
+        ))
+        .unwrap();
+        assert!(!is_pinned("/music/b.mp3"));
+
+        // The music keeps its own pin when the playlist lets go
+        rt.block_on(ct_pin_playlist_offline(backend.clone(), playlist_id, false))
+            .unwrap();
+        assert!(!cts_is_playlist_pinned_offline(backend.clone(), playlist_id).unwrap());
+        assert!(is_pinned("/music/a.mp3"));
+        assert!(!is_pinned("/music/c.mp3"));
+
+        rt.block_on(ct_pin_playlist_offline(backend.clone(), playlist_id, true))
+            .unwrap();
+        assert!(is_pinned("/music/c.mp3"));
+        rt.block_on(ct_remove_playlist(backend.clone(), playlist_id))
+            .unwrap();
+        assert!(!cts_is_playlist_pinned_offline(backend.clone(), playlist_id).unwrap());
+        assert!(!is_pinned("/music/c.mp3"));
+    }
+}
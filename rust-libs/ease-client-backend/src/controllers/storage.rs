@@ -1,15 +1,19 @@
 use std::sync::Arc;
 
 use ease_client_schema::{StorageEntryLoc, StorageId};
-use ease_remote_storage::OneDriveBackend;
+use ease_remote_storage::{Entry, StorageBackendError};
 
 use crate::{
     error::BResult,
-    objects::{ListStorageEntryChildrenResp, Storage, StorageConnectionTestResult, StorageEntry},
-    onedrive_oauth_url,
+    objects::{
+        ListStorageEntryChildrenResp, OneDriveDriveEntry, RemoteCacheUsage, Storage,
+        StorageChangesResp, StorageConnectionTestResult, StorageEntry,
+    },
     services::{
-        build_storage_backend_by_arg, evict_storage_backend_cache, get_storage_backend,
-        list_storage,
+        build_storage_backend_by_arg, evict_storage_backend_cache, get_remote_cache_usage,
+        get_storage_backend, list_onedrive_drives, list_storage, onedrive_oauth_url,
+        remove_storage_cached_files, request_jellyfin_access_token, request_onedrive_refresh_token,
+        set_remote_cache_budget, sync_storage_changes,
     },
     ArgUpsertStorage, Backend,
 };
@@ -22,6 +26,71 @@ fn normalize_arg_upsert_storage(mut arg: ArgUpsertStorage) -> ArgUpsertStorage {
     arg
 }
 
+fn build_connection_test_result(e: StorageBackendError) -> StorageConnectionTestResult {
+    if e.is_unauthorized() {
+        return StorageConnectionTestResult::Unauthorized;
+    }
+    if e.is_timeout() {
+        return StorageConnectionTestResult::Timeout;
+    }
+    match e {
+        StorageBackendError::Forbidden => StorageConnectionTestResult::Forbidden,
+        StorageBackendError::RateLimited { .. } => StorageConnectionTestResult::RateLimited,
+        StorageBackendError::ServerError(_) => StorageConnectionTestResult::ServerError,
+        StorageBackendError::TlsError(_) => StorageConnectionTestResult::TlsError,
+        StorageBackendError::DnsError(_) => StorageConnectionTestResult::DnsError,
+        StorageBackendError::ConnectionRefused => StorageConnectionTestResult::ConnectionRefused,
+        StorageBackendError::InsufficientStorage => {
+            StorageConnectionTestResult::InsufficientStorage
+        }
+        StorageBackendError::MethodNotAllowed => StorageConnectionTestResult::MethodNotAllowed,
+        StorageBackendError::HostKeyMismatch { .. } => StorageConnectionTestResult::HostKeyMismatch,
+        _ => StorageConnectionTestResult::OtherError,
+    }
+}
+
+fn build_list_children_error(e: StorageBackendError) -> ListStorageEntryChildrenResp {
+    if e.is_unauthorized() {
+        return ListStorageEntryChildrenResp::AuthenticationFailed;
+    }
+    if e.is_timeout() {
+        return ListStorageEntryChildrenResp::Timeout;
+    }
+    match e {
+        StorageBackendError::Forbidden => ListStorageEntryChildrenResp::Forbidden,
+        StorageBackendError::RateLimited { retry_after } => {
+            ListStorageEntryChildrenResp::RateLimited { retry_after }
+        }
+        StorageBackendError::ServerError(status) => ListStorageEntryChildrenResp::ServerError {
+            status: status.as_u16(),
+        },
+        StorageBackendError::TlsError(_) => ListStorageEntryChildrenResp::TlsError,
+        StorageBackendError::DnsError(_) => ListStorageEntryChildrenResp::DnsError,
+        StorageBackendError::ConnectionRefused => ListStorageEntryChildrenResp::ConnectionRefused,
+        StorageBackendError::InsufficientStorage => {
+            ListStorageEntryChildrenResp::InsufficientStorage
+        }
+        StorageBackendError::MethodNotAllowed => ListStorageEntryChildrenResp::MethodNotAllowed,
+        StorageBackendError::HostKeyMismatch { .. } => {
+            ListStorageEntryChildrenResp::HostKeyMismatch
+        }
+        _ => ListStorageEntryChildrenResp::Unknown,
+    }
+}
+
+fn build_storage_entry(storage_id: StorageId, entry: Entry) -> StorageEntry {
+    StorageEntry {
+        storage_id,
+        name: entry.name,
+        path: entry.path,
+        size: entry.size.map(|s| s as u64),
+        is_dir: entry.is_dir,
+        modified_time: entry.modified_time,
+        etag: entry.etag,
+        content_type: entry.content_type,
+    }
+}
+
 #[uniffi::export]
 pub async fn ct_list_storage(cx: Arc<Backend>) -> BResult<Vec<Storage>> {
     let cx = cx.get_context();
@@ -44,15 +113,25 @@ pub async fn ct_upsert_storage(cx: Arc<Backend>, arg: ArgUpsertStorage) -> BResu
 #[uniffi::export]
 pub async fn ct_get_refresh_token(cx: Arc<Backend>, code: String) -> BResult<String> {
     let cx = cx.get_context();
-    let refresh_token = OneDriveBackend::request_refresh_token(code).await?;
+    let refresh_token = request_onedrive_refresh_token(cx, code).await?;
     Ok(refresh_token)
 }
 
+#[uniffi::export]
+pub async fn ct_get_jellyfin_access_token(
+    _cx: Arc<Backend>,
+    arg: ArgUpsertStorage,
+) -> BResult<String> {
+    let access_token = request_jellyfin_access_token(arg).await?;
+    Ok(access_token)
+}
+
 #[uniffi::export]
 pub async fn ct_remove_storage(cx: Arc<Backend>, id: StorageId) -> BResult<()> {
     let cx = cx.get_context();
     cx.database_server().remove_storage(id)?;
     evict_storage_backend_cache(cx, id);
+    remove_storage_cached_files(cx, id);
 
     Ok(())
 }
@@ -62,7 +141,9 @@ pub async fn ct_test_storage(
     cx: Arc<Backend>,
     arg: ArgUpsertStorage,
 ) -> BResult<StorageConnectionTestResult> {
-    let arg = normalize_arg_upsert_storage(arg);
+    let mut arg = normalize_arg_upsert_storage(arg);
+    // The form may be unsaved, keep rotated credentials out of the stored row
+    arg.id = None;
     let cx = cx.get_context();
     let backend = build_storage_backend_by_arg(cx, arg)?;
     let res = backend.list("/".to_string()).await;
@@ -71,17 +152,23 @@ pub async fn ct_test_storage(
         Ok(_) => Ok(StorageConnectionTestResult::Success),
         Err(e) => {
             tracing::warn!("ct_test_storage, {e:?}");
-            if e.is_unauthorized() {
-                Ok(StorageConnectionTestResult::Unauthorized)
-            } else if e.is_timeout() {
-                Ok(StorageConnectionTestResult::Timeout)
-            } else {
-                Ok(StorageConnectionTestResult::OtherError)
-            }
+            Ok(build_connection_test_result(e))
         }
     }
 }
 
+#[uniffi::export]
+pub async fn ct_list_onedrive_drives(
+    cx: Arc<Backend>,
+    arg: ArgUpsertStorage,
+) -> BResult<Vec<OneDriveDriveEntry>> {
+    let mut arg = normalize_arg_upsert_storage(arg);
+    // Same as testing, the form may be unsaved
+    arg.id = None;
+    let cx = cx.get_context();
+    list_onedrive_drives(cx, arg).await
+}
+
 #[uniffi::export]
 pub async fn ct_list_storage_entry_children(
     cx: Arc<Backend>,
@@ -101,30 +188,105 @@ pub async fn ct_list_storage_entry_children(
         Ok(entries) => {
             let entries = entries
                 .into_iter()
-                .map(|entry| StorageEntry {
-                    storage_id: arg.storage_id,
-                    name: entry.name,
-                    path: entry.path,
-                    size: entry.size.map(|s| s as u64),
-                    is_dir: entry.is_dir,
-                })
+                .map(|entry| build_storage_entry(arg.storage_id, entry))
                 .collect();
             Ok(ListStorageEntryChildrenResp::Ok(entries))
         }
         Err(e) => {
             tracing::warn!("ct_list_storage_entry_children, {e:?}");
-            if e.is_unauthorized() {
-                Ok(ListStorageEntryChildrenResp::AuthenticationFailed)
-            } else if e.is_timeout() {
-                Ok(ListStorageEntryChildrenResp::Timeout)
-            } else {
-                Ok(ListStorageEntryChildrenResp::Unknown)
-            }
+            Ok(build_list_children_error(e))
         }
     }
 }
 
 #[uniffi::export]
-pub fn ct_onedrive_oauth_url() -> String {
-    onedrive_oauth_url()
+pub async fn ct_stat_storage_entry(
+    cx: Arc<Backend>,
+    arg: StorageEntryLoc,
+) -> BResult<Option<StorageEntry>> {
+    let cx = cx.get_context();
+    let Some(backend) = get_storage_backend(cx, arg.storage_id)? else {
+        return Ok(None);
+    };
+
+    match backend.stat(arg.path).await {
+        Ok(entry) => Ok(Some(build_storage_entry(arg.storage_id, entry))),
+        Err(e) if e.is_not_found() => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// `None` if the storage doesn't exist.
+#[uniffi::export]
+pub async fn ct_sync_storage_changes(
+    cx: Arc<Backend>,
+    storage_id: StorageId,
+) -> BResult<Option<StorageChangesResp>> {
+    let cx = cx.get_context();
+    sync_storage_changes(cx, storage_id).await
+}
+
+#[uniffi::export]
+pub fn ct_onedrive_oauth_url(cx: Arc<Backend>, arg: ArgUpsertStorage) -> String {
+    let cx = cx.get_context();
+    onedrive_oauth_url(cx, &arg)
+}
+
+#[uniffi::export]
+pub fn cts_get_remote_cache_usage(cx: Arc<Backend>) -> RemoteCacheUsage {
+    let cx = cx.get_context();
+    get_remote_cache_usage(cx)
+}
+
+/// Unpinned files are evicted, least recently played first, until they fit.
+#[uniffi::export]
+pub fn cts_set_remote_cache_budget(cx: Arc<Backend>, budget: u64) {
+    let cx = cx.get_context();
+    set_remote_cache_budget(cx, budget)
+}
+
+#[cfg(test)]
+mod tests {
+    use ease_client_tokio::tokio_runtime;
+    use ease_remote_storage::{MemoryFault, StatusCode};
+
+    use super::ct_test_storage;
+    use crate::{
+        objects::StorageConnectionTestResult,
+        services::get_memory_backend,
+        utils::testing::{memory_storage_arg, test_backend},
+    };
+
+    #[test]
+    fn test_storage_maps_errors() {
+        let backend = test_backend("test-storage");
+        let remote = get_memory_backend(backend.get_context(), "remote");
+        let test = || {
+            tokio_runtime()
+                .block_on(ct_test_storage(
+                    backend.clone(),
+                    memory_storage_arg("remote"),
+                ))
+                .unwrap()
+        };
+
+        assert_eq!(test(), StorageConnectionTestResult::Success);
+        remote.inject("/", MemoryFault::Unauthorized);
+        assert_eq!(test(), StorageConnectionTestResult::Unauthorized);
+        remote.clear_faults();
+        remote.inject("/", MemoryFault::Timeout);
+        assert_eq!(test(), StorageConnectionTestResult::Timeout);
+        remote.clear_faults();
+        remote.inject("/", MemoryFault::NotFound);
+        assert_eq!(test(), StorageConnectionTestResult::OtherError);
+        remote.clear_faults();
+        remote.inject("/", MemoryFault::Forbidden);
+        assert_eq!(test(), StorageConnectionTestResult::Forbidden);
+        remote.clear_faults();
+        remote.inject("/", MemoryFault::ServerError(StatusCode::BAD_GATEWAY));
+        assert_eq!(test(), StorageConnectionTestResult::ServerError);
+        remote.clear_faults();
+        remote.inject("/", MemoryFault::ConnectionRefused);
+        assert_eq!(test(), StorageConnectionTestResult::ConnectionRefused);
+    }
 }
@@ -7,13 +7,17 @@ use std::{
     time::Duration,
 };
 
-use crate::{repositories::core::DatabaseServer, services::StorageState};
+use crate::{
+    repositories::core::DatabaseServer,
+    services::{PrefetchState, StorageState},
+};
 
 struct BackendContextInternal {
     storage_path: RwLock<String>,
     app_document_dir: RwLock<String>,
     schema_version: AtomicU32,
     storage_state: Arc<StorageState>,
+    prefetch_state: Arc<PrefetchState>,
     database_server: Arc<DatabaseServer>,
 }
 
@@ -62,6 +66,7 @@ impl BackendContext {
                 app_document_dir: RwLock::new(String::new()),
                 schema_version: AtomicU32::new(0),
                 storage_state: Default::default(),
+                prefetch_state: Default::default(),
                 database_server: DatabaseServer::new(),
             }),
         }
@@ -90,6 +95,10 @@ impl BackendContext {
         &self.internal.storage_state
     }
 
+    pub(crate) fn prefetch_state(&self) -> &Arc<PrefetchState> {
+        &self.internal.prefetch_state
+    }
+
     pub(crate) fn database_server(&self) -> &Arc<DatabaseServer> {
         &self.internal.database_server
     }
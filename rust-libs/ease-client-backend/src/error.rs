@@ -1,11 +1,32 @@
+use std::time::Duration;
+
 use ease_client_schema::{MusicId, PlaylistId};
 use ease_order_key::OrderKeyError;
+use ease_remote_storage::StorageBackendError;
 
 #[derive(Debug, thiserror::Error, uniffi::Error)]
 #[uniffi(flat_error)]
 pub enum BError {
     #[error("remote storage error: {0:?}")]
-    RemoteStorageError(#[from] ease_remote_storage::StorageBackendError),
+    RemoteStorageError(StorageBackendError),
+    #[error("remote storage forbidden")]
+    RemoteStorageForbidden,
+    #[error("remote storage rate limited, retry after {retry_after:?}")]
+    RemoteStorageRateLimited { retry_after: Option<Duration> },
+    #[error("remote storage server error: {status}")]
+    RemoteStorageServerError { status: u16 },
+    #[error("remote storage tls error: {0}")]
+    RemoteStorageTlsError(String),
+    #[error("remote storage dns error: {0}")]
+    RemoteStorageDnsError(String),
+    #[error("remote storage connection refused")]
+    RemoteStorageConnectionRefused,
+    #[error("remote storage insufficient storage")]
+    RemoteStorageInsufficientStorage,
+    #[error("remote storage method not allowed")]
+    RemoteStorageMethodNotAllowed,
+    #[error("remote storage needs a new sign-in")]
+    RemoteStorageReauthorizationRequired,
     #[error("failed to load asset: {0:?}")]
     AssetLoadFail(String),
     #[error("asset not found")]
@@ -34,4 +55,27 @@ pub enum BError {
     AnyHowError(#[from] anyhow::Error),
 }
 
+impl From<StorageBackendError> for BError {
+    fn from(e: StorageBackendError) -> Self {
+        match e {
+            StorageBackendError::Forbidden => BError::RemoteStorageForbidden,
+            StorageBackendError::RateLimited { retry_after } => {
+                BError::RemoteStorageRateLimited { retry_after }
+            }
+            StorageBackendError::ServerError(status) => BError::RemoteStorageServerError {
+                status: status.as_u16(),
+            },
+            StorageBackendError::TlsError(e) => BError::RemoteStorageTlsError(e.to_string()),
+            StorageBackendError::DnsError(e) => BError::RemoteStorageDnsError(e.to_string()),
+            StorageBackendError::ConnectionRefused => BError::RemoteStorageConnectionRefused,
+            StorageBackendError::InsufficientStorage => BError::RemoteStorageInsufficientStorage,
+            StorageBackendError::MethodNotAllowed => BError::RemoteStorageMethodNotAllowed,
+            StorageBackendError::ReauthorizationRequired => {
+                BError::RemoteStorageReauthorizationRequired
+            }
+            e => BError::RemoteStorageError(e),
+        }
+    }
+}
+
 pub type BResult<T> = Result<T, BError>;
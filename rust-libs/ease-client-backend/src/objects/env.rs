@@ -1 +0,0 @@
-pub static EASEM_ONEDRIVE_ID: &str = "5db0dade-b21c-4161-bd4f-027e0f3e4700";
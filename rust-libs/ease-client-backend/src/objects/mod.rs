@@ -4,9 +4,6 @@ mod player;
 mod playlist;
 mod storage;
 
-mod env;
-
-pub use env::*;
 pub use lyric::*;
 pub use music::*;
 pub use player::*;
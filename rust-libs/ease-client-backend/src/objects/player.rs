@@ -1 +1,9 @@
+use ease_client_schema::{MusicId, PlayMode, PlaylistId};
 
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct ArgPrefetchNextMusics {
+    pub playlist_id: PlaylistId,
+    /// The music playing now.
+    pub music_id: MusicId,
+    pub play_mode: PlayMode,
+}
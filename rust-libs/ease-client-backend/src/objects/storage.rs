@@ -1,7 +1,10 @@
-use ease_client_schema::{MusicId, PlaylistId, StorageEntryLoc, StorageId, StorageType};
-use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
-use crate::objects::EASEM_ONEDRIVE_ID;
+use ease_client_schema::{
+    MusicId, PlaylistId, StorageEntryLoc, StorageId, StorageNetworkSettings,
+    StorageOneDriveSettings, StorageSftpSettings, StorageType,
+};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, uniffi::Record)]
 pub struct StorageEntry {
@@ -10,6 +13,9 @@ pub struct StorageEntry {
     pub path: String,
     pub size: Option<u64>,
     pub is_dir: bool,
+    pub modified_time: Option<Duration>,
+    pub etag: Option<String>,
+    pub content_type: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default, uniffi::Record)]
@@ -21,6 +27,9 @@ pub struct ArgUpsertStorage {
     pub password: String,
     pub is_anonymous: bool,
     pub typ: StorageType,
+    pub network: StorageNetworkSettings,
+    pub sftp: StorageSftpSettings,
+    pub onedrive: StorageOneDriveSettings,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, uniffi::Enum)]
@@ -32,6 +41,30 @@ pub enum StorageConnectionTestResult {
     Unauthorized,
     Timeout,
     OtherError,
+    Forbidden,
+    RateLimited,
+    ServerError,
+    TlsError,
+    DnsError,
+    ConnectionRefused,
+    InsufficientStorage,
+    MethodNotAllowed,
+    HostKeyMismatch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum OneDriveDriveKind {
+    Personal,
+    SharedWithMe,
+    SharePoint,
+}
+
+/// A drive a OneDrive storage can be rooted at, `addr` goes into the storage address.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct OneDriveDriveEntry {
+    pub name: String,
+    pub kind: OneDriveDriveKind,
+    pub addr: String,
 }
 
 #[derive(Debug, Clone, Serialize, PartialEq, Eq, uniffi::Enum)]
@@ -52,6 +85,9 @@ pub struct Storage {
     pub password: String,
     pub is_anonymous: bool,
     pub typ: StorageType,
+    pub network: StorageNetworkSettings,
+    pub sftp: StorageSftpSettings,
+    pub onedrive: StorageOneDriveSettings,
     pub music_count: u64,
 }
 
@@ -78,6 +114,15 @@ pub enum CurrentStorageStateType {
     NeedPermission,
     AuthenticationFailed,
     Timeout,
+    Forbidden,
+    RateLimited,
+    ServerError,
+    TlsError,
+    DnsError,
+    ConnectionRefused,
+    InsufficientStorage,
+    MethodNotAllowed,
+    HostKeyMismatch,
     UnknownError,
 }
 
@@ -90,12 +135,45 @@ impl StorageEntry {
     }
 }
 
+#[derive(Debug, Clone, uniffi::Enum)]
+pub enum StorageEntryChange {
+    /// A file that is new or whose content changed.
+    Upsert(StorageEntry),
+    /// The path is gone, along with everything below it. Renames show up as a removal plus
+    /// an upsert.
+    Removed(String),
+}
+
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct StorageChangesResp {
+    pub changes: Vec<StorageEntryChange>,
+    /// Whatever was known about these folders is stale, `changes` lists every file in them.
+    pub reset_folders: Vec<String>,
+    /// Folders that couldn't be read this time, they are caught up on the next sync.
+    pub failed_folders: Vec<String>,
+}
+
 #[derive(Debug, Clone, uniffi::Enum)]
 pub enum ListStorageEntryChildrenResp {
     Ok(Vec<StorageEntry>),
     AuthenticationFailed,
     Timeout,
     Unknown,
+    Forbidden,
+    /// `retry_after` is how long the server asked to wait, if it said so.
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    ServerError {
+        status: u16,
+    },
+    TlsError,
+    DnsError,
+    ConnectionRefused,
+    InsufficientStorage,
+    MethodNotAllowed,
+    /// The SSH server offered another host key than the one trusted for the storage.
+    HostKeyMismatch,
 }
 
 impl ListStorageEntryChildrenResp {
@@ -105,15 +183,30 @@ impl ListStorageEntryChildrenResp {
             ListStorageEntryChildrenResp::AuthenticationFailed => false,
             ListStorageEntryChildrenResp::Timeout => false,
             ListStorageEntryChildrenResp::Unknown => false,
+            ListStorageEntryChildrenResp::Forbidden => false,
+            ListStorageEntryChildrenResp::RateLimited { .. } => false,
+            ListStorageEntryChildrenResp::ServerError { .. } => false,
+            ListStorageEntryChildrenResp::TlsError => false,
+            ListStorageEntryChildrenResp::DnsError => false,
+            ListStorageEntryChildrenResp::ConnectionRefused => false,
+            ListStorageEntryChildrenResp::InsufficientStorage => false,
+            ListStorageEntryChildrenResp::MethodNotAllowed => false,
+            ListStorageEntryChildrenResp::HostKeyMismatch => false,
         }
     }
 }
 
-pub fn onedrive_oauth_url() -> String {
-    let base_url = "https://login.microsoftonline.com/common/oauth2/v2.0/authorize";
-    let client_id: &str = EASEM_ONEDRIVE_ID;
-    let redirect_uri = "easem://oauth2redirect/";
-    let scope = urlencoding::encode("Files.Read offline_access").to_string();
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct RemoteCacheUsage {
+    pub budget: u64,
+    pub used: u64,
+    /// Part of `used` kept whatever the budget.
+    pub pinned: u64,
+}
 
-    format!("{base_url}?client_id={client_id}&response_type=code&redirect_uri={redirect_uri}&scope={scope}")
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum RemoteCacheEntryState {
+    None,
+    Partial,
+    Complete,
 }
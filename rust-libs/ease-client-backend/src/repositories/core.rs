@@ -7,8 +7,8 @@ use crate::error::BResult;
 use super::blob::BlobManager;
 use ease_client_schema::{
     DbKeyAlloc, TABLE_ID_ALLOC, TABLE_MUSIC, TABLE_MUSIC_BY_LOC, TABLE_MUSIC_PLAYLIST,
-    TABLE_PLAYLIST, TABLE_PLAYLIST_MUSIC, TABLE_PREFERENCE, TABLE_SCHEMA_VERSION, TABLE_STORAGE,
-    TABLE_STORAGE_MUSIC,
+    TABLE_PLAYLIST, TABLE_PLAYLIST_MUSIC, TABLE_PLAYLIST_OFFLINE_PIN, TABLE_PREFERENCE,
+    TABLE_SCHEMA_VERSION, TABLE_STORAGE, TABLE_STORAGE_CHANGE_CURSOR, TABLE_STORAGE_MUSIC,
 };
 
 #[derive(Default)]
@@ -62,6 +62,8 @@ impl DatabaseServer {
         db.open_table(TABLE_MUSIC_BY_LOC)?;
         db.open_table(TABLE_STORAGE)?;
         db.open_multimap_table(TABLE_STORAGE_MUSIC)?;
+        db.open_table(TABLE_STORAGE_CHANGE_CURSOR)?;
+        db.open_table(TABLE_PLAYLIST_OFFLINE_PIN)?;
         db.open_table(TABLE_PREFERENCE)?;
         db.open_table(TABLE_SCHEMA_VERSION)?;
         db.commit()?;
@@ -8,7 +8,7 @@ use crate::{error::BResult, services::get_playlist};
 use super::{core::DatabaseServer, music::ArgDBAddMusic};
 use ease_client_schema::{
     BlobId, DbKeyAlloc, MusicId, PlaylistId, PlaylistModel, StorageEntryLoc, TABLE_MUSIC_PLAYLIST,
-    TABLE_PLAYLIST, TABLE_PLAYLIST_MUSIC,
+    TABLE_PLAYLIST, TABLE_PLAYLIST_MUSIC, TABLE_PLAYLIST_OFFLINE_PIN,
 };
 
 #[derive(Debug, uniffi::Record)]
@@ -149,6 +149,30 @@ impl DatabaseServer {
         Ok(())
     }
 
+    pub fn load_playlist_offline_pinned(self: &Arc<Self>, id: PlaylistId) -> BResult<bool> {
+        let db = self.db().begin_read()?;
+        let table = db.open_table(TABLE_PLAYLIST_OFFLINE_PIN)?;
+        Ok(table.get(id)?.is_some())
+    }
+
+    pub fn set_playlist_offline_pinned(
+        self: &Arc<Self>,
+        id: PlaylistId,
+        pinned: bool,
+    ) -> BResult<()> {
+        let db = self.db().begin_write()?;
+        {
+            let mut table = db.open_table(TABLE_PLAYLIST_OFFLINE_PIN)?;
+            if pinned {
+                table.insert(id, ())?;
+            } else {
+                table.remove(id)?;
+            }
+        }
+        db.commit()?;
+        Ok(())
+    }
+
     pub fn remove_playlist(self: &Arc<Self>, playlist_id: PlaylistId) -> BResult<()> {
         let db = self.db().begin_write()?;
         let rdb = self.db().begin_read()?;
@@ -158,8 +182,10 @@ impl DatabaseServer {
             let mut table_playlist = db.open_table(TABLE_PLAYLIST)?;
             let mut table_pm = db.open_multimap_table(TABLE_PLAYLIST_MUSIC)?;
             let mut table_mp = db.open_multimap_table(TABLE_MUSIC_PLAYLIST)?;
+            let mut table_pin = db.open_table(TABLE_PLAYLIST_OFFLINE_PIN)?;
 
             table_playlist.remove(playlist_id)?;
+            table_pin.remove(playlist_id)?;
 
             let ids = table_pm.get(playlist_id)?;
             for id in ids {
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::BTreeSet, sync::Arc};
 
 use redb::{ReadTransaction, ReadableMultimapTable, ReadableTable, ReadableTableMetadata};
 
@@ -6,8 +6,9 @@ use crate::{error::BResult, objects::ArgUpsertStorage};
 
 use super::core::DatabaseServer;
 use ease_client_schema::{
-    BlobId, DbKeyAlloc, MusicId, StorageId, StorageModel, TABLE_MUSIC, TABLE_MUSIC_BY_LOC,
-    TABLE_MUSIC_PLAYLIST, TABLE_PLAYLIST_MUSIC, TABLE_STORAGE, TABLE_STORAGE_MUSIC,
+    BlobId, DbKeyAlloc, MusicId, StorageChangeCursorModel, StorageId, StorageModel,
+    StorageSftpSettings, TABLE_MUSIC, TABLE_MUSIC_BY_LOC, TABLE_MUSIC_PLAYLIST,
+    TABLE_PLAYLIST_MUSIC, TABLE_STORAGE, TABLE_STORAGE_CHANGE_CURSOR, TABLE_STORAGE_MUSIC,
 };
 
 impl DatabaseServer {
@@ -52,7 +53,7 @@ impl DatabaseServer {
     pub fn upsert_storage(self: &Arc<Self>, arg: ArgUpsertStorage) -> BResult<StorageId> {
         let db = self.db().begin_write()?;
 
-        let id = {
+        let (id, relocated) = {
             let mut table = db.open_table(TABLE_STORAGE)?;
             let mut model = if let Some(id) = arg.id {
                 let v = table.get(id)?.unwrap().value();
@@ -68,9 +69,13 @@ impl DatabaseServer {
                     password: Default::default(),
                     is_anonymous: Default::default(),
                     typ: Default::default(),
+                    network: Default::default(),
+                    sftp: Default::default(),
+                    onedrive: Default::default(),
                 }
             };
             let id = model.id;
+            let relocated = arg.id.is_some() && (model.addr != arg.addr || model.typ != arg.typ);
 
             model.addr = arg.addr;
             model.alias = arg.alias;
@@ -78,15 +83,117 @@ impl DatabaseServer {
             model.password = arg.password;
             model.is_anonymous = arg.is_anonymous;
             model.typ = arg.typ;
+            model.network = arg.network;
+            model.sftp = StorageSftpSettings {
+                host_key_fingerprint: match arg.sftp.host_key_fingerprint {
+                    Some(v) => Some(v),
+                    // A host key learned on first use stays until the address changes
+                    None if !relocated => model.sftp.host_key_fingerprint.take(),
+                    None => None,
+                },
+                key_passphrase: arg.sftp.key_passphrase,
+            };
+            model.onedrive = arg.onedrive;
             table.insert(model.id, model)?;
 
-            id
+            (id, relocated)
         };
+        if relocated {
+            // The old cursor describes another tree
+            let mut table = db.open_table(TABLE_STORAGE_CHANGE_CURSOR)?;
+            table.remove(id)?;
+        }
         db.commit()?;
 
         Ok(id)
     }
 
+    /// Only touches the password, so a rotated credential never overwrites other edits.
+    pub fn update_storage_password(
+        self: &Arc<Self>,
+        id: StorageId,
+        password: String,
+    ) -> BResult<()> {
+        let db = self.db().begin_write()?;
+        {
+            let mut table = db.open_table(TABLE_STORAGE)?;
+            let model = table.get(id)?.map(|v| v.value());
+            if let Some(mut model) = model {
+                model.password = password;
+                table.insert(model.id, model)?;
+            }
+        }
+        db.commit()?;
+        Ok(())
+    }
+
+    /// Saves the SFTP host key learned on the first connection.
+    pub fn update_storage_host_key_fingerprint(
+        self: &Arc<Self>,
+        id: StorageId,
+        fingerprint: String,
+    ) -> BResult<()> {
+        let db = self.db().begin_write()?;
+        {
+            let mut table = db.open_table(TABLE_STORAGE)?;
+            let model = table.get(id)?.map(|v| v.value());
+            if let Some(mut model) = model {
+                model.sftp.host_key_fingerprint = Some(fingerprint);
+                table.insert(model.id, model)?;
+            }
+        }
+        db.commit()?;
+        Ok(())
+    }
+
+    /// The folders holding the storage's musics.
+    pub fn load_storage_music_folders(
+        self: &Arc<Self>,
+        id: StorageId,
+    ) -> BResult<BTreeSet<String>> {
+        let db = self.db().begin_read()?;
+        let table_storage_musics = db.open_multimap_table(TABLE_STORAGE_MUSIC)?;
+        let table_musics = db.open_table(TABLE_MUSIC)?;
+
+        let mut ret: BTreeSet<String> = Default::default();
+        for v in table_storage_musics.get(id)? {
+            let music_id = v?.value();
+            if let Some(music) = table_musics.get(music_id)?.map(|v| v.value()) {
+                let p = music.loc.path;
+                let folder = match p.rfind('/') {
+                    Some(0) | None => "/",
+                    Some(pos) => &p[..pos],
+                };
+                ret.insert(folder.to_string());
+            }
+        }
+        Ok(ret)
+    }
+
+    pub fn load_storage_change_cursor(
+        self: &Arc<Self>,
+        id: StorageId,
+    ) -> BResult<Option<StorageChangeCursorModel>> {
+        let db = self.db().begin_read()?;
+        let table = db.open_table(TABLE_STORAGE_CHANGE_CURSOR)?;
+        let cursor = table.get(id)?.map(|v| v.value());
+        Ok(cursor)
+    }
+
+    pub fn save_storage_change_cursor(
+        self: &Arc<Self>,
+        id: StorageId,
+        cursor: StorageChangeCursorModel,
+    ) -> BResult<()> {
+        let db = self.db().begin_write()?;
+        {
+            let mut table = db.open_table(TABLE_STORAGE_CHANGE_CURSOR)?;
+            table.insert(id, cursor)?;
+        }
+        db.commit()?;
+        Ok(())
+    }
+
     pub fn remove_storage(self: &Arc<Self>, id: StorageId) -> BResult<()> {
         let mut to_remove_blobs: Vec<BlobId> = Default::default();
         let db = self.db().begin_write()?;
@@ -127,6 +234,7 @@ impl DatabaseServer {
             table_music_by_loc.retain(|v, _| v.storage_id != id)?;
             table_storage.remove(id)?;
             table_storage_musics.remove_all(id)?;
+            db.open_table(TABLE_STORAGE_CHANGE_CURSOR)?.remove(id)?;
         }
 
         db.commit()?;
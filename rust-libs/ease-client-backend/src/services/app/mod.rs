@@ -1,6 +1,9 @@
-use ease_client_schema::{upgrade_v1_to_v2, upgrade_v2_to_v3, StorageType};
+use ease_client_schema::{upgrade_v1_to_v2, upgrade_v2_to_v3, upgrade_v3_to_v4, StorageType};
 
-use crate::{ctx::BackendContext, error::BResult, objects::ArgUpsertStorage};
+use crate::{
+    ctx::BackendContext, error::BResult, objects::ArgUpsertStorage,
+    services::init_remote_file_cache,
+};
 
 #[derive(Debug, Clone, uniffi::Record)]
 pub struct ArgInitializeApp {
@@ -14,6 +17,7 @@ pub fn app_bootstrap(cx: &BackendContext, arg: ArgInitializeApp) -> BResult<()>
     cx.set_storage_path(&arg.storage_path);
     // Init
     init_database(cx, &arg)?;
+    init_remote_file_cache(cx, &arg.app_cache_dir);
     Ok(())
 }
 
@@ -24,7 +28,7 @@ pub fn app_destroy(cx: &BackendContext) -> BResult<()> {
 }
 
 fn init_database(cx: &BackendContext, arg: &ArgInitializeApp) -> BResult<()> {
-    static SCHEMA_VERSION: u32 = 3;
+    static SCHEMA_VERSION: u32 = 4;
 
     cx.database_server().init(arg.app_document_dir.clone());
     let old_schema_version = cx.database_server().get_schema_version()?;
@@ -40,6 +44,9 @@ fn init_database(cx: &BackendContext, arg: &ArgInitializeApp) -> BResult<()> {
             if old_schema_version < 3 {
                 upgrade_v2_to_v3(&cx.database_server().db())?;
             }
+            if old_schema_version < 4 {
+                upgrade_v3_to_v4(&cx.database_server().db())?;
+            }
         }
     }
 
@@ -62,6 +69,9 @@ fn init_local_storage(cx: &BackendContext) -> BResult<()> {
         password: Default::default(),
         is_anonymous: Default::default(),
         typ: StorageType::Local,
+        network: Default::default(),
+        sftp: Default::default(),
+        onedrive: Default::default(),
     })?;
     Ok(())
 }
@@ -3,10 +3,12 @@ mod lyrics;
 mod music;
 mod playlist;
 mod preference;
+mod prefetch;
 mod storage;
 
 pub use app::*;
 pub use music::*;
 pub use playlist::*;
 pub use preference::*;
+pub use prefetch::*;
 pub use storage::*;
@@ -9,7 +9,7 @@ use crate::{
     StorageEntry,
 };
 
-use super::{lyrics::parse_lrc, storage::load_storage_entry_data};
+use super::{lyrics::parse_lrc, prefetch::take_prefetched_lyric, storage::load_storage_entry_data};
 
 #[derive(Debug, uniffi::Record)]
 pub struct ArgUpdatePlaylist {
@@ -175,6 +175,36 @@ pub(crate) fn update_music_cover(cx: &BackendContext, arg: ArgUpdateMusicCover)
     Ok(())
 }
 
+/// Where the lyric of a music is, `true` if it is the `.lrc` next to the music.
+pub(crate) fn music_lyric_loc(model: &MusicModel) -> (Option<StorageEntryLoc>, bool) {
+    let loc = &model.loc;
+    let using_fallback = model.lyric.is_none() && model.lyric_default;
+    if !using_fallback {
+        return (model.lyric.clone(), false);
+    }
+    let lyric_loc = StorageEntryLoc {
+        path: {
+            let mut path = loc.path.clone();
+            let new_extension = ".lrc";
+            if let Some(pos) = path.rfind('.') {
+                path.truncate(pos);
+            }
+            path.push_str(new_extension);
+            path
+        },
+        storage_id: loc.storage_id,
+    };
+    (Some(lyric_loc), true)
+}
+
+pub(crate) async fn load_music_lyric(
+    cx: &BackendContext,
+    model: &MusicModel,
+) -> Option<MusicLyric> {
+    let (lyric_loc, using_fallback) = music_lyric_loc(model);
+    load_lyric(cx, lyric_loc, using_fallback).await
+}
+
 pub(crate) async fn get_music(cx: &BackendContext, id: MusicId) -> BResult<Option<Music>> {
     let model = cx.database_server().load_music(id)?;
     if model.is_none() {
@@ -183,25 +213,11 @@ pub(crate) async fn get_music(cx: &BackendContext, id: MusicId) -> BResult<Optio
 
     let model = model.unwrap();
     let meta = build_music_meta(model.clone());
+    let lyric = match take_prefetched_lyric(cx, &model) {
+        Some(lyric) => lyric,
+        None => load_music_lyric(cx, &model).await,
+    };
     let loc = model.loc;
-    let mut lyric_loc = model.lyric;
-    let using_fallback = lyric_loc.is_none() && model.lyric_default;
-    if using_fallback {
-        lyric_loc = Some(StorageEntryLoc {
-            path: {
-                let mut path = loc.path.clone();
-                let new_extension = ".lrc";
-                if let Some(pos) = path.rfind('.') {
-                    path.truncate(pos);
-                }
-                path.push_str(new_extension);
-                path
-            },
-            storage_id: loc.storage_id,
-        });
-    }
-
-    let lyric: Option<MusicLyric> = load_lyric(cx, lyric_loc, using_fallback).await;
     let cover = if model.cover.is_none() {
         Default::default()
     } else {
@@ -237,3 +253,67 @@ pub(crate) fn get_music_abstract(
     let abstract_music = MusicAbstract { cover, meta };
     Ok(Some(abstract_music))
 }
+
+#[cfg(test)]
+mod tests {
+    use ease_client_schema::StorageEntryLoc;
+    use ease_client_tokio::tokio_runtime;
+    use ease_remote_storage::MemoryFault;
+
+    use super::get_music;
+    use crate::{
+        objects::LyricLoadState,
+        utils::testing::{add_memory_storage, add_musics, test_backend},
+    };
+
+    #[test]
+    fn lyric_load_states() {
+        let backend = test_backend("lyric");
+        let cx = backend.get_context();
+        let (storage_id, remote) = add_memory_storage(cx, "lyric");
+        remote.add_file("/music/a.mp3", &b"a"[..]);
+        remote.add_file("/music/a.lrc", &b"[00:01.00]a"[..]);
+        remote.add_file("/music/b.mp3", &b"b"[..]);
+        remote.add_file("/music/c.mp3", &b"c"[..]);
+        remote.add_file("/music/d.mp3", &b"d"[..]);
+        remote.add_file("/lyrics/c.lrc", &b"[00:01.00]c"[..]);
+        remote.add_file("/lyrics/d.lrc", &b"[00:01.00]d"[..]);
+
+        let ids = add_musics(
+            cx,
+            storage_id,
+            &[
+                "/music/a.mp3",
+                "/music/b.mp3",
+                "/music/c.mp3",
+                "/music/d.mp3",
+            ],
+        );
+        for (id, path) in [(ids[2], "/lyrics/c.lrc"), (ids[3], "/lyrics/d.lrc")] {
+            let loc = StorageEntryLoc {
+                storage_id,
+                path: path.to_string(),
+            };
+            cx.database_server()
+                .update_music_lyric(id, Some(loc))
+                .unwrap();
+        }
+        remote.inject("/lyrics/c.lrc", MemoryFault::Timeout);
+        remote.inject("/lyrics/d.lrc", MemoryFault::Truncate(4));
+
+        let state = |i: usize| {
+            let music = tokio_runtime()
+                .block_on(get_music(cx, ids[i]))
+                .unwrap()
+                .unwrap();
+            music.lyric.map(|lyric| lyric.loaded_state)
+        };
+        // Next to the music
+        assert_eq!(state(0), Some(LyricLoadState::Loaded));
+        // No lyric next to the music is not an error
+        assert_eq!(state(1), Some(LyricLoadState::Missing));
+        // The lyric picked for the music can't be read
+        assert_eq!(state(2), Some(LyricLoadState::Failed));
+        assert_eq!(state(3), Some(LyricLoadState::Failed));
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use ease_client_schema::{MusicId, MusicModel, PlayMode, StorageEntryLoc};
+
+use crate::{
+    ctx::BackendContext,
+    error::BResult,
+    objects::{ArgPrefetchNextMusics, LyricLoadState, MusicLyric},
+};
+
+use super::{
+    music::{load_music_lyric, music_lyric_loc},
+    storage::{get_storage_backend, is_storage_cached},
+};
+
+/// Musics warmed ahead of the one playing.
+const PREFETCH_MUSIC_COUNT: usize = 2;
+/// Audio warmed per music, enough for the player to start without waiting on the remote.
+const PREFETCH_HEAD_BYTES: u64 = 4 << 20;
+const DEFAULT_PREFETCH_BUDGET: u64 = PREFETCH_MUSIC_COUNT as u64 * PREFETCH_HEAD_BYTES;
+
+struct PrefetchedLyric {
+    loc: Option<StorageEntryLoc>,
+    lyric: Option<MusicLyric>,
+}
+
+pub(crate) struct PrefetchState {
+    /// Bumped whenever the queue changes, a prefetch for an older queue stops.
+    generation: AtomicU64,
+    /// Audio bytes warmed per queue change, across all the predicted musics.
+    budget: AtomicU64,
+    lyrics: Mutex<HashMap<MusicId, PrefetchedLyric>>,
+}
+
+impl Default for PrefetchState {
+    fn default() -> Self {
+        Self {
+            generation: Default::default(),
+            budget: AtomicU64::new(DEFAULT_PREFETCH_BUDGET),
+            lyrics: Default::default(),
+        }
+    }
+}
+
+impl PrefetchState {
+    fn is_cancelled(&self, generation: u64) -> bool {
+        self.generation.load(Ordering::SeqCst) != generation
+    }
+}
+
+/// The musics that play after `current`, in order.
+fn predict_next_musics(
+    ids: &[MusicId],
+    current: MusicId,
+    play_mode: PlayMode,
+    count: usize,
+) -> Vec<MusicId> {
+    let Some(pos) = ids.iter().position(|id| *id == current) else {
+        return Default::default();
+    };
+    let (before, after) = (&ids[..pos], &ids[pos + 1..]);
+    match play_mode {
+        PlayMode::Single | PlayMode::SingleLoop => Default::default(),
+        PlayMode::List => after.iter().take(count).copied().collect(),
+        PlayMode::ListLoop => after.iter().chain(before).take(count).copied().collect(),
+    }
+}
+
+/// The lyric a prefetch loaded for the music, if it still is where the music points to.
+pub(crate) fn take_prefetched_lyric(
+    cx: &BackendContext,
+    model: &MusicModel,
+) -> Option<Option<MusicLyric>> {
+    let prefetched = cx
+        .prefetch_state()
+        .lyrics
+        .lock()
+        .unwrap()
+        .remove(&model.id)?;
+    let (loc, _) = music_lyric_loc(model);
+    (prefetched.loc == loc).then_some(prefetched.lyric)
+}
+
+async fn warm_music_head(
+    cx: &BackendContext,
+    loc: &StorageEntryLoc,
+    len: u64,
+    generation: u64,
+) -> BResult<()> {
+    let Some(backend) = get_storage_backend(cx, loc.storage_id)? else {
+        return Ok(());
+    };
+    let rx = backend.get_range(loc.path.clone(), 0, len).await?.into_rx();
+    while let Ok(chunk) = rx.recv().await {
+        chunk?;
+        // Dropping the stream stops the fetches behind it
+        if cx.prefetch_state().is_cancelled(generation) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Loads the lyrics of the next musics and warms the head of their audio into the disk
+/// cache, so that the next track starts without a gap. Embedded covers come with the head
+/// of the file, the extracted ones are in the database already.
+///
+/// Returns once done, or as soon as the queue changes again.
+pub async fn prefetch_next_musics(cx: &BackendContext, arg: ArgPrefetchNextMusics) -> BResult<()> {
+    let state = cx.prefetch_state();
+    let generation = state.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+    let musics = cx
+        .database_server()
+        .load_musics_by_playlist_id(arg.playlist_id)?;
+    let ids: Vec<MusicId> = musics.iter().map(|music| music.id).collect();
+    let next = predict_next_musics(&ids, arg.music_id, arg.play_mode, PREFETCH_MUSIC_COUNT);
+    state
+        .lyrics
+        .lock()
+        .unwrap()
+        .retain(|id, _| next.contains(id));
+
+    let mut budget = state.budget.load(Ordering::SeqCst);
+    for id in next {
+        let Some(model) = musics.iter().find(|music| music.id == id) else {
+            continue;
+        };
+        if state.is_cancelled(generation) {
+            return Ok(());
+        }
+
+        if !state.lyrics.lock().unwrap().contains_key(&id) {
+            let (loc, _) = music_lyric_loc(model);
+            let lyric = load_music_lyric(cx, model).await;
+            if state.is_cancelled(generation) {
+                return Ok(());
+            }
+            // A failed load is tried again when the music starts
+            let failed = lyric
+                .as_ref()
+                .is_some_and(|lyric| lyric.loaded_state == LyricLoadState::Failed);
+            if !failed {
+                let lyric = PrefetchedLyric { loc, lyric };
+                state.lyrics.lock().unwrap().insert(id, lyric);
+            }
+        }
+
+        let len = budget.min(PREFETCH_HEAD_BYTES);
+        if len == 0 || !is_storage_cached(cx, model.loc.storage_id)? {
+            continue;
+        }
+        budget -= len;
+        if let Err(e) = warm_music_head(cx, &model.loc, len, generation).await {
+            tracing::warn!("prefetch {:?} fail: {e:?}", model.loc);
+        }
+    }
+    Ok(())
+}
+
+/// Stops a running prefetch and forgets what it loaded.
+pub fn cancel_prefetch(cx: &BackendContext) {
+    let state = cx.prefetch_state();
+    state.generation.fetch_add(1, Ordering::SeqCst);
+    state.lyrics.lock().unwrap().clear();
+}
+
+pub fn get_prefetch_budget(cx: &BackendContext) -> u64 {
+    cx.prefetch_state().budget.load(Ordering::SeqCst)
+}
+
+/// Zero only prefetches lyrics.
+pub fn set_prefetch_budget(cx: &BackendContext, budget: u64) {
+    cx.prefetch_state().budget.store(budget, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use ease_client_schema::{MusicId, PlayMode, PlaylistId};
+    use ease_client_tokio::tokio_runtime;
+    use ease_remote_storage::MemoryFault;
+    use futures_util::future::join;
+
+    use super::{predict_next_musics, prefetch_next_musics};
+    use crate::{
+        objects::{ArgPrefetchNextMusics, LyricLoadState},
+        services::{music::get_music, storage::remove_storage_cached_files},
+        utils::testing::{add_memory_storage, add_playlist, test_backend},
+    };
+
+    fn ids(v: &[i64]) -> Vec<MusicId> {
+        v.iter().map(|v| MusicId::wrap(*v)).collect()
+    }
+
+    #[test]
+    fn next_musics_follow_play_mode() {
+        let queue = ids(&[1, 2, 3, 4]);
+        let next = |current: i64, play_mode| {
+            predict_next_musics(&queue, MusicId::wrap(current), play_mode, 2)
+        };
+        assert_eq!(next(2, PlayMode::List), ids(&[3, 4]));
+        assert_eq!(next(4, PlayMode::List), ids(&[]));
+        assert_eq!(next(4, PlayMode::ListLoop), ids(&[1, 2]));
+        assert_eq!(next(3, PlayMode::ListLoop), ids(&[4, 1]));
+        assert_eq!(next(2, PlayMode::Single), ids(&[]));
+        assert_eq!(next(2, PlayMode::SingleLoop), ids(&[]));
+        assert_eq!(next(5, PlayMode::List), ids(&[]));
+        assert_eq!(
+            predict_next_musics(&ids(&[1]), MusicId::wrap(1), PlayMode::ListLoop, 2),
+            ids(&[])
+        );
+    }
+
+    fn list_arg(playlist_id: PlaylistId, music_id: MusicId) -> ArgPrefetchNextMusics {
+        ArgPrefetchNextMusics {
+            playlist_id,
+            music_id,
+            play_mode: PlayMode::List,
+        }
+    }
+
+    #[test]
+    fn queue_change_cancels_running_prefetch() {
+        let backend = test_backend("prefetch-cancel");
+        let cx = backend.get_context();
+        let (storage_id, remote) = add_memory_storage(cx, "prefetch-cancel");
+        for name in ["a", "b", "c", "d"] {
+            remote.add_file(&format!("/music/{name}.mp3"), name.as_bytes().to_vec());
+            remote.add_file(&format!("/music/{name}.lrc"), format!("[00:01.00]{name}"));
+        }
+        let (playlist_id, ids) = add_playlist(
+            cx,
+            storage_id,
+            &[
+                "/music/a.mp3",
+                "/music/b.mp3",
+                "/music/c.mp3",
+                "/music/d.mp3",
+            ],
+        );
+        remote.set_latency(Duration::from_millis(100));
+
+        // The first prefetch waits on the lyric of b when the queue moves on to c
+        let (first, second) = tokio_runtime().block_on(join(
+            prefetch_next_musics(cx, list_arg(playlist_id, ids[0])),
+            prefetch_next_musics(cx, list_arg(playlist_id, ids[2])),
+        ));
+        first.unwrap();
+        second.unwrap();
+
+        let prefetched: HashSet<MusicId> = cx
+            .prefetch_state()
+            .lyrics
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect();
+        assert_eq!(prefetched, HashSet::from([ids[3]]));
+    }
+
+    #[test]
+    fn get_music_uses_prefetched_lyric() {
+        let backend = test_backend("prefetch-lyric");
+        let cx = backend.get_context();
+        let (storage_id, remote) = add_memory_storage(cx, "prefetch-lyric");
+        remote.add_file("/music/a.mp3", &b"a"[..]);
+        remote.add_file("/music/b.mp3", &b"b"[..]);
+        remote.add_file("/music/b.lrc", &b"[00:01.00]b"[..]);
+        let (playlist_id, ids) = add_playlist(cx, storage_id, &["/music/a.mp3", "/music/b.mp3"]);
+        remote.set_latency(Duration::from_millis(20));
+
+        tokio_runtime()
+            .block_on(prefetch_next_musics(cx, list_arg(playlist_id, ids[0])))
+            .unwrap();
+        // The prefetch read went through the disk cache, drop it so only the prefetched
+        // lyric stands between `get_music` and the failing remote
+        remove_storage_cached_files(cx, storage_id);
+        remote.inject("/music/b.lrc", MemoryFault::Timeout);
+
+        let state = || {
+            let music = tokio_runtime()
+                .block_on(get_music(cx, ids[1]))
+                .unwrap()
+                .unwrap();
+            music.lyric.map(|lyric| lyric.loaded_state)
+        };
+        // Served from the prefetch, the remote isn't asked again
+        assert_eq!(state(), Some(LyricLoadState::Loaded));
+        // Only once, a later open reads the remote again. An unreadable `.lrc` next to the
+        // music counts as no lyric
+        assert_eq!(state(), Some(LyricLoadState::Missing));
+    }
+}
@@ -1 +1,3 @@
 pub mod common;
+#[cfg(test)]
+pub(crate) mod testing;
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use ease_client_schema::{MusicId, PlaylistId, StorageEntryLoc, StorageId, StorageType};
+use ease_order_key::OrderKey;
+use ease_remote_storage::MemoryBackend;
+
+use crate::{
+    ctx::BackendContext,
+    objects::ArgUpsertStorage,
+    repositories::music::ArgDBAddMusic,
+    services::{get_memory_backend, ArgInitializeApp},
+    Backend,
+};
+
+/// A backend bootstrapped in a directory of its own, `name` keeps tests running in
+/// parallel apart.
+pub(crate) fn test_backend(name: &str) -> Arc<Backend> {
+    let dir = std::env::temp_dir().join(format!("ease-backend-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_string_lossy().to_string() + "/";
+
+    let backend = Arc::new(Backend {
+        arg: ArgInitializeApp {
+            app_document_dir: dir.clone(),
+            app_cache_dir: dir.clone(),
+            storage_path: dir,
+        },
+        cx: Arc::new(BackendContext::new()),
+    });
+    backend.init().unwrap();
+    backend
+}
+
+pub(crate) fn memory_storage_arg(addr: &str) -> ArgUpsertStorage {
+    ArgUpsertStorage {
+        id: None,
+        addr: addr.to_string(),
+        alias: addr.to_string(),
+        username: Default::default(),
+        password: Default::default(),
+        is_anonymous: true,
+        typ: StorageType::Memory,
+        network: Default::default(),
+        sftp: Default::default(),
+        onedrive: Default::default(),
+    }
+}
+
+/// Saves a memory storage, the returned tree is the one it reads.
+pub(crate) fn add_memory_storage(
+    cx: &BackendContext,
+    addr: &str,
+) -> (StorageId, Arc<MemoryBackend>) {
+    let id = cx
+        .database_server()
+        .upsert_storage(memory_storage_arg(addr))
+        .unwrap();
+    (id, get_memory_backend(cx, addr))
+}
+
+/// Adds the files at `paths` as musics of a new playlist.
+pub(crate) fn add_musics(
+    cx: &BackendContext,
+    storage_id: StorageId,
+    paths: &[&str],
+) -> Vec<MusicId> {
+    add_playlist(cx, storage_id, paths).1
+}
+
+/// Like [`add_musics`], for tests that need the playlist too.
+pub(crate) fn add_playlist(
+    cx: &BackendContext,
+    storage_id: StorageId,
+    paths: &[&str],
+) -> (PlaylistId, Vec<MusicId>) {
+    let musics = paths
+        .iter()
+        .map(|path| ArgDBAddMusic {
+            loc: StorageEntryLoc {
+                storage_id,
+                path: path.to_string(),
+            },
+            title: path.to_string(),
+        })
+        .collect();
+    let (id, added) = cx
+        .database_server()
+        .create_playlist(
+            "test".to_string(),
+            None,
+            musics,
+            cx.current_time().as_millis() as i64,
+            OrderKey::default(),
+        )
+        .unwrap();
+    (id, added.into_iter().map(|music| music.id).collect())
+}
@@ -1,7 +1,15 @@
 mod v2;
 mod v3;
+mod v4;
 
 uniffi::setup_scaffolding!();
 
 pub use v2::upgrade_v1_to_v2;
 pub use v3::*;
+// v4 replaces the storage table and adds the change cursors and the playlist pins,
+// everything else is still v3
+pub use v4::{
+    StorageChangeCursorModel, StorageHeader, StorageModel, StorageNetworkSettings,
+    StorageOneDriveSettings, StorageSftpSettings, TABLE_PLAYLIST_OFFLINE_PIN, TABLE_STORAGE,
+    TABLE_STORAGE_CHANGE_CURSOR, upgrade_v3_to_v4,
+};
@@ -95,6 +95,13 @@ pub enum StorageType {
     #[default]
     Webdav,
     OneDrive,
+    S3,
+    Sftp,
+    HttpIndex,
+    Subsonic,
+    Jellyfin,
+    #[cfg(feature = "test-storage")]
+    Memory,
 }
 
 #[derive(
@@ -0,0 +1,9 @@
+mod models;
+mod objects;
+mod repositories;
+mod upgrader;
+
+pub use models::*;
+pub use objects::*;
+pub use repositories::*;
+pub use upgrader::*;
@@ -0,0 +1,26 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::objects::{StorageNetworkSettings, StorageOneDriveSettings, StorageSftpSettings};
+use crate::v3::{StorageId, StorageType};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageModel {
+    pub id: StorageId,
+    pub addr: String,
+    pub alias: String,
+    pub username: String,
+    pub password: String,
+    pub is_anonymous: bool,
+    pub typ: StorageType,
+    pub network: StorageNetworkSettings,
+    pub sftp: StorageSftpSettings,
+    pub onedrive: StorageOneDriveSettings,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageChangeCursorModel {
+    /// Folder to the cursor that follows it. Opaque, handed back to the storage backend.
+    pub cursors: BTreeMap<String, String>,
+}
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct StorageHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// Optional network settings of a storage. Only the http based storages use all of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct StorageNetworkSettings {
+    /// Extra trusted root certificates, PEM encoded.
+    pub root_ca_pem: Option<String>,
+    /// Hex SHA-256 of the server certificate. Trusts that certificate alone, even self-signed.
+    pub pinned_cert_sha256: Option<String>,
+    pub accept_invalid_certs: bool,
+    /// `http://`, `https://`, `socks5://` or `socks5h://` url.
+    pub proxy: Option<String>,
+    pub headers: Vec<StorageHeader>,
+    pub user_agent: Option<String>,
+    pub connect_timeout_ms: Option<u64>,
+    pub read_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct StorageSftpSettings {
+    /// SHA256 fingerprint of the server host key, as printed by `ssh-keygen -lf`. Learned on
+    /// the first connection when empty.
+    pub host_key_fingerprint: Option<String>,
+    /// Passphrase of the private key held in the password.
+    pub key_passphrase: Option<String>,
+}
+
+/// The Azure app a OneDrive storage signs in with. Unset fields fall back to the app built
+/// into the player on the global cloud.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, uniffi::Record)]
+pub struct StorageOneDriveSettings {
+    /// Graph API root, e.g. `https://graph.microsoft.us/v1.0` for a national cloud.
+    pub graph_api: Option<String>,
+    /// OAuth 2.0 root of the tenant, e.g. `https://login.microsoftonline.com/common/oauth2/v2.0`.
+    pub login_api: Option<String>,
+    pub client_id: Option<String>,
+    pub redirect_uri: Option<String>,
+}
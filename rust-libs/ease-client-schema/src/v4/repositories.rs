@@ -0,0 +1,22 @@
+use redb::TableDefinition;
+
+use crate::v3::{BinSerde, BinSerdeTN, PlaylistId, StorageId};
+
+use super::models::{StorageChangeCursorModel, StorageModel};
+
+impl BinSerdeTN for StorageModel {
+    const NAME: &'static str = "StorageModel";
+}
+
+impl BinSerdeTN for StorageChangeCursorModel {
+    const NAME: &'static str = "StorageChangeCursorModel";
+}
+
+pub const TABLE_STORAGE: TableDefinition<BinSerde<StorageId>, BinSerde<StorageModel>> =
+    TableDefinition::new("v4_storage");
+pub const TABLE_STORAGE_CHANGE_CURSOR: TableDefinition<
+    BinSerde<StorageId>,
+    BinSerde<StorageChangeCursorModel>,
+> = TableDefinition::new("v4_storage_change_cursor");
+pub const TABLE_PLAYLIST_OFFLINE_PIN: TableDefinition<BinSerde<PlaylistId>, ()> =
+    TableDefinition::new("v4_playlist_offline_pin");
@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use redb::ReadableTable;
+
+use crate::{v3, v4};
+
+impl From<v3::StorageModel> for v4::StorageModel {
+    fn from(value: v3::StorageModel) -> Self {
+        Self {
+            id: value.id,
+            addr: value.addr,
+            alias: value.alias,
+            username: value.username,
+            password: value.password,
+            is_anonymous: value.is_anonymous,
+            typ: value.typ,
+            network: Default::default(),
+            sftp: Default::default(),
+            onedrive: Default::default(),
+        }
+    }
+}
+
+pub fn upgrade_v3_to_v4(database: &Arc<redb::Database>) -> anyhow::Result<()> {
+    let db = database.begin_write()?;
+    {
+        let ot = db.open_table(v3::TABLE_STORAGE)?;
+        let mut nt = db.open_table(v4::TABLE_STORAGE)?;
+        for v in ot.iter()? {
+            let model: v4::StorageModel = v?.1.value().into();
+            nt.insert(model.id, model)?;
+        }
+        tracing::info!("v3 -> v4: finish to add storage network settings");
+    }
+    {
+        db.delete_table(v3::TABLE_STORAGE)?;
+        tracing::info!("v3 -> v4: finish to delete old tables");
+    }
+    {
+        let mut t = db.open_table(v3::TABLE_SCHEMA_VERSION)?;
+        t.insert((), 4)?;
+    }
+    db.commit()?;
+    tracing::info!("v3 -> v4: finish all");
+
+    Ok(())
+}
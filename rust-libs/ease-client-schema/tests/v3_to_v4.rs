@@ -0,0 +1,100 @@
+use std::{path::PathBuf, sync::Arc};
+
+use ease_client_schema::{
+    BinSerde, BinSerdeTN, StorageId, StorageType, TABLE_SCHEMA_VERSION, TABLE_STORAGE,
+    upgrade_v2_to_v3, upgrade_v3_to_v4,
+};
+use redb::{ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LegacyStorageModel {
+    id: StorageId,
+    addr: String,
+    alias: String,
+    username: String,
+    password: String,
+    is_anonymous: bool,
+    typ: StorageType,
+}
+
+impl BinSerdeTN for LegacyStorageModel {
+    const NAME: &'static str = "StorageModel";
+}
+
+const TABLE_LEGACY_STORAGE: TableDefinition<BinSerde<StorageId>, BinSerde<LegacyStorageModel>> =
+    TableDefinition::new("v3_storage");
+
+fn temp_db_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ease-schema-{name}-{}.redb", std::process::id()))
+}
+
+fn schema_version(db: &redb::Database) -> u32 {
+    let r = db.begin_read().unwrap();
+    let t = r.open_table(TABLE_SCHEMA_VERSION).unwrap();
+    t.get(()).unwrap().unwrap().value()
+}
+
+#[test]
+fn test_v3_to_v4() {
+    let p = temp_db_path("v3-to-v4");
+    let _ = std::fs::remove_file(&p);
+    let db = Arc::new(redb::Database::create(&p).unwrap());
+    {
+        let w = db.begin_write().unwrap();
+        {
+            let mut t = w.open_table(TABLE_LEGACY_STORAGE).unwrap();
+            let model = LegacyStorageModel {
+                id: StorageId::wrap(7),
+                addr: "https://dav.example.com".to_string(),
+                alias: "Home".to_string(),
+                username: "ease".to_string(),
+                password: "secret".to_string(),
+                is_anonymous: false,
+                typ: StorageType::Webdav,
+            };
+            t.insert(model.id, model).unwrap();
+        }
+        w.commit().unwrap();
+    }
+
+    upgrade_v3_to_v4(&db).unwrap();
+    assert_eq!(schema_version(&db), 4);
+
+    let r = db.begin_read().unwrap();
+    let t = r.open_table(TABLE_STORAGE).unwrap();
+    let model = t.get(StorageId::wrap(7)).unwrap().unwrap().value();
+    assert_eq!(model.addr, "https://dav.example.com");
+    assert_eq!(model.password, "secret");
+    assert_eq!(model.typ, StorageType::Webdav);
+    assert_eq!(model.network, Default::default());
+    assert_eq!(model.onedrive, Default::default());
+
+    drop(t);
+    drop(r);
+    drop(db);
+    let _ = std::fs::remove_file(&p);
+}
+
+#[test]
+fn test_v2_to_v4() {
+    let src = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data.redb");
+    let p = temp_db_path("v2-to-v4");
+    std::fs::copy(src, &p).unwrap();
+    let db = Arc::new(redb::Database::open(&p).unwrap());
+
+    upgrade_v2_to_v3(&db).unwrap();
+    upgrade_v3_to_v4(&db).unwrap();
+    assert_eq!(schema_version(&db), 4);
+
+    let r = db.begin_read().unwrap();
+    let t = r.open_table(TABLE_STORAGE).unwrap();
+    let models: Vec<_> = t.iter().unwrap().map(|v| v.unwrap().1.value()).collect();
+    assert!(!models.is_empty());
+    assert!(models.iter().all(|v| v.network == Default::default()));
+
+    drop(t);
+    drop(r);
+    drop(db);
+    let _ = std::fs::remove_file(&p);
+}
@@ -0,0 +1,97 @@
+//! Latency of repeated `list`/`get` calls on one backend, which keeps its connections, against
+//! a new backend per call, which has to connect every time.
+//!
+//! Run with `cargo bench -p ease-remote-storage --bench client_reuse`. The server is plain
+//! http on loopback, so the gap here is only the TCP connect; over TLS it grows by a full
+//! handshake per call.
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use dav_server::{fakels::FakeLs, memfs::MemFs, DavHandler};
+use ease_remote_storage::{bytes::Bytes, BuildWebdavArg, StorageBackend, UploadFile, Webdav};
+
+const ROUNDS: u32 = 200;
+
+async fn setup_server() -> String {
+    let dav_server = DavHandler::builder()
+        .filesystem(MemFs::new())
+        .locksystem(FakeLs::new())
+        .build_handler();
+
+    let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+    let make_service = hyper::service::make_service_fn(move |_| {
+        let dav_server = dav_server.clone();
+        async move {
+            let func = move |req| {
+                let dav_server = dav_server.clone();
+                async move { Ok::<_, Infallible>(dav_server.handle(req).await) }
+            };
+            Ok::<_, Infallible>(hyper::service::service_fn(func))
+        }
+    });
+    let server = hyper::Server::bind(&addr).serve(make_service);
+    let port = server.local_addr().port();
+    tokio::spawn(async move {
+        server.await.unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    format!("http://127.0.0.1:{port}")
+}
+
+fn build_backend(addr: &str) -> Webdav {
+    Webdav::new(BuildWebdavArg {
+        addr: addr.to_string(),
+        username: Default::default(),
+        password: Default::default(),
+        is_anonymous: true,
+        preemptive_basic_auth: false,
+        connect_timeout: Duration::from_secs(10),
+        pool: Default::default(),
+        network: Default::default(),
+        retry_policy: Default::default(),
+    })
+}
+
+async fn list_and_get(backend: &Webdav) {
+    backend.list("/".to_string()).await.unwrap();
+    let file = backend.get("/a.bin".to_string(), 0).await.unwrap();
+    file.bytes().await.unwrap();
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let per_round = elapsed / ROUNDS;
+    println!("{name:<24} {:>10.1?} per list+get", per_round);
+}
+
+#[tokio::main]
+async fn main() {
+    let addr = setup_server().await;
+    let backend = build_backend(&addr);
+    backend
+        .put(
+            "/a.bin".to_string(),
+            UploadFile::new_from_bytes(Bytes::from(vec![7u8; 64 << 10])),
+        )
+        .await
+        .unwrap();
+
+    // Warm up both paths once before measuring.
+    list_and_get(&backend).await;
+    list_and_get(&build_backend(&addr)).await;
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        list_and_get(&backend).await;
+    }
+    report("shared client", start.elapsed());
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        list_and_get(&build_backend(&addr)).await;
+    }
+    report("new backend per call", start.elapsed());
+}
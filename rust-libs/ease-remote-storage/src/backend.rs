@@ -1,40 +1,65 @@
-use std::{io::ErrorKind, process::Output};
+use std::{
+    io::{ErrorKind, SeekFrom},
+    process::Output,
+    time::Duration,
+};
 
 use bytes::Bytes;
 use ease_client_tokio::tokio_runtime;
 use futures_util::future::BoxFuture;
 use reqwest::StatusCode;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::oneshot::error;
 
-#[derive(Debug, Clone)]
+use crate::changes::StorageChanges;
+use crate::http_client::with_read_timeout;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Entry {
     pub name: String,
     pub path: String,
     pub size: Option<usize>,
     pub is_dir: bool,
+    /// Since the unix epoch.
+    pub modified_time: Option<Duration>,
+    /// ETag, or whatever the backend offers to tell file versions apart.
+    pub etag: Option<String>,
+    pub content_type: Option<String>,
 }
 
 enum StreamFileInner {
     Response(reqwest::Response),
     Total(bytes::Bytes),
+    Channel(async_channel::Receiver<StorageBackendResult<Bytes>>),
+    File(tokio::fs::File),
 }
 
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
 pub struct StreamFile {
     inner: StreamFileInner,
     total: Option<usize>,
     content_type: Option<String>,
     name: String,
     byte_offset: u64,
+    limit: Option<u64>,
+    full_size: Option<u64>,
+    read_timeout: Option<Duration>,
+}
+
+pub struct UploadFile {
+    rx: async_channel::Receiver<StorageBackendResult<Bytes>>,
+    size: u64,
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum StorageBackendError {
     #[error(transparent)]
-    RequestFail(#[from] reqwest::Error),
+    RequestFail(reqwest::Error),
     #[error("Parse XML Fail")]
     ParseXMLFail,
     #[error(transparent)]
-    TokioIO(#[from] tokio::io::Error),
+    TokioIO(tokio::io::Error),
     #[error(transparent)]
     TokioJoinError(#[from] tokio::task::JoinError),
     #[error("Url Parse Error")]
@@ -43,37 +68,189 @@ pub enum StorageBackendError {
     SerdeJsonError(#[from] serde_json::Error),
     #[error("QuickXML De Error: {0}")]
     QuickXMLDeError(#[from] quick_xml::DeError),
+    #[error("QuickXML Error: {0}")]
+    QuickXMLError(#[from] quick_xml::Error),
+    #[error(transparent)]
+    SshError(russh::Error),
+    #[error(transparent)]
+    SftpError(#[from] russh_sftp::client::error::Error),
+    #[error("Authentication Failed")]
+    AuthenticationFailed,
+    #[error("Unsupported Authentication: {0}")]
+    UnsupportedAuth(String),
+    #[error("Invalid Network Config: {0}")]
+    InvalidNetworkConfig(String),
+    #[error("Operation Not Supported")]
+    Unsupported,
+    #[error("Subsonic Error {code}: {message}")]
+    SubsonicError { code: u32, message: String },
+    #[error("Forbidden")]
+    Forbidden,
+    /// `retry_after` is how long the server asked to wait, if it said so.
+    #[error("Rate Limited")]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("Server Error: {0}")]
+    ServerError(StatusCode),
+    #[error("Method Not Allowed")]
+    MethodNotAllowed,
+    #[error("Insufficient Storage")]
+    InsufficientStorage,
+    /// The TLS handshake failed, most often on a certificate that isn't trusted.
+    #[error("TLS Error: {0}")]
+    TlsError(reqwest::Error),
+    #[error("DNS Error: {0}")]
+    DnsError(reqwest::Error),
+    #[error("Connection Refused")]
+    ConnectionRefused,
+    /// The SSH server offered another host key than the one trusted for the storage.
+    #[error("Host Key Mismatch: expected {expected}, got {actual}")]
+    HostKeyMismatch { expected: String, actual: String },
+    /// The sign-in didn't grant what the operation needs, e.g. writes on a token from
+    /// before they were asked for. Signing in again fixes it.
+    #[error("Reauthorization Required")]
+    ReauthorizationRequired,
+    /// Well formed, but not what the protocol allows at this point.
+    #[error("Unexpected Response: {0}")]
+    UnexpectedResponse(String),
 }
 
 #[derive(thiserror::Error, Debug)]
 enum SendChunkError {
     #[error(transparent)]
     RequestFail(#[from] reqwest::Error),
+    #[error(transparent)]
+    TokioIO(#[from] tokio::io::Error),
     #[error("mpsc send error: {0}")]
     MpscSendError(#[from] async_channel::SendError<StorageBackendResult<Bytes>>),
 }
 
 pub type StorageBackendResult<T> = std::result::Result<T, StorageBackendError>;
 
+/// The errors behind `e`. `std::io::Error` hides the one it wraps from `source`.
+fn error_chain<'a>(
+    e: &'a (dyn std::error::Error + 'static),
+) -> impl Iterator<Item = &'a (dyn std::error::Error + 'static)> {
+    std::iter::successors(Some(e), |e| {
+        match e.downcast_ref::<std::io::Error>().and_then(|e| e.get_ref()) {
+            Some(inner) => Some(inner as &(dyn std::error::Error + 'static)),
+            None => e.source(),
+        }
+    })
+}
+
+enum ConnectFailure {
+    Tls,
+    Dns,
+    Refused,
+}
+
+fn connect_failure(e: &reqwest::Error) -> Option<ConnectFailure> {
+    if !e.is_connect() {
+        return None;
+    }
+    error_chain(e).find_map(|source| {
+        if source.is::<rustls::Error>() {
+            return Some(ConnectFailure::Tls);
+        }
+        if let Some(io) = source.downcast_ref::<std::io::Error>() {
+            if io.kind() == ErrorKind::ConnectionRefused {
+                return Some(ConnectFailure::Refused);
+            }
+        }
+        // hyper tells resolver failures apart by message only
+        if source.to_string().starts_with("dns error") {
+            return Some(ConnectFailure::Dns);
+        }
+        None
+    })
+}
+
+impl From<reqwest::Error> for StorageBackendError {
+    fn from(e: reqwest::Error) -> Self {
+        if let Some(status) = e.status() {
+            return match status {
+                StatusCode::FORBIDDEN => StorageBackendError::Forbidden,
+                StatusCode::METHOD_NOT_ALLOWED => StorageBackendError::MethodNotAllowed,
+                StatusCode::TOO_MANY_REQUESTS => {
+                    StorageBackendError::RateLimited { retry_after: None }
+                }
+                StatusCode::INSUFFICIENT_STORAGE => StorageBackendError::InsufficientStorage,
+                status if status.is_server_error() => StorageBackendError::ServerError(status),
+                _ => StorageBackendError::RequestFail(e),
+            };
+        }
+        match connect_failure(&e) {
+            Some(ConnectFailure::Tls) => StorageBackendError::TlsError(e),
+            Some(ConnectFailure::Dns) => StorageBackendError::DnsError(e),
+            Some(ConnectFailure::Refused) => StorageBackendError::ConnectionRefused,
+            None => StorageBackendError::RequestFail(e),
+        }
+    }
+}
+
+impl From<tokio::io::Error> for StorageBackendError {
+    fn from(e: tokio::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::ConnectionRefused => StorageBackendError::ConnectionRefused,
+            _ => StorageBackendError::TokioIO(e),
+        }
+    }
+}
+
+impl From<russh::Error> for StorageBackendError {
+    fn from(e: russh::Error) -> Self {
+        match e {
+            russh::Error::IO(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                StorageBackendError::ConnectionRefused
+            }
+            e => StorageBackendError::SshError(e),
+        }
+    }
+}
+
 impl StorageBackendError {
+    /// The HTTP status the server failed the request with.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            StorageBackendError::RequestFail(e) => e.status(),
+            StorageBackendError::Forbidden => Some(StatusCode::FORBIDDEN),
+            StorageBackendError::RateLimited { .. } => Some(StatusCode::TOO_MANY_REQUESTS),
+            StorageBackendError::ServerError(status) => Some(*status),
+            StorageBackendError::MethodNotAllowed => Some(StatusCode::METHOD_NOT_ALLOWED),
+            StorageBackendError::InsufficientStorage => Some(StatusCode::INSUFFICIENT_STORAGE),
+            _ => None,
+        }
+    }
+
     pub fn is_timeout(&self) -> bool {
-        if let StorageBackendError::RequestFail(e) = self {
-            return e.is_timeout();
+        match self {
+            StorageBackendError::RequestFail(e) => e.is_timeout(),
+            StorageBackendError::TokioIO(e) => e.kind() == ErrorKind::TimedOut,
+            StorageBackendError::SftpError(russh_sftp::client::error::Error::Timeout) => true,
+            _ => false,
         }
-        false
     }
 
     pub fn is_unauthorized(&self) -> bool {
-        if let StorageBackendError::RequestFail(e) = self {
-            return e.status() == Some(StatusCode::UNAUTHORIZED);
+        match self {
+            StorageBackendError::RequestFail(e) => e.status() == Some(StatusCode::UNAUTHORIZED),
+            StorageBackendError::AuthenticationFailed => true,
+            StorageBackendError::UnsupportedAuth(_) => true,
+            StorageBackendError::ReauthorizationRequired => true,
+            // 40: wrong username or password, 41: token auth not supported for the user
+            StorageBackendError::SubsonicError { code, .. } => *code == 40 || *code == 41,
+            _ => false,
         }
-        false
     }
 
     pub fn is_not_found(&self) -> bool {
         match self {
             StorageBackendError::RequestFail(e) => e.status() == Some(StatusCode::NOT_FOUND),
             StorageBackendError::TokioIO(e) => e.kind() == ErrorKind::NotFound,
+            StorageBackendError::SftpError(russh_sftp::client::error::Error::Status(status)) => {
+                status.status_code == russh_sftp::protocol::StatusCode::NoSuchFile
+            }
+            StorageBackendError::SubsonicError { code, .. } => *code == 70,
             _ => false,
         }
     }
@@ -81,7 +258,114 @@ impl StorageBackendError {
 
 pub trait StorageBackend {
     fn list(&self, dir: String) -> BoxFuture<StorageBackendResult<Vec<Entry>>>;
+    /// Looks `p` up in the listing of its parent, for backends without a cheaper way.
+    fn stat(&self, p: String) -> BoxFuture<StorageBackendResult<Entry>> {
+        let p = p.trim_end_matches('/').to_string();
+        if p.is_empty() {
+            return Box::pin(async move {
+                Ok(Entry {
+                    path: "/".to_string(),
+                    is_dir: true,
+                    ..Default::default()
+                })
+            });
+        }
+        let dir = match p.rfind('/') {
+            Some(0) | None => "/".to_string(),
+            Some(pos) => p[..pos].to_string(),
+        };
+        let entries = self.list(dir);
+        Box::pin(async move {
+            entries
+                .await?
+                .into_iter()
+                .find(|entry| entry.path.trim_end_matches('/') == p)
+                .ok_or_else(|| std::io::Error::from(ErrorKind::NotFound).into())
+        })
+    }
     fn get(&self, p: String, byte_offset: u64) -> BoxFuture<StorageBackendResult<StreamFile>>;
+    /// Reads the bytes in `start..end`. Backends that can't ask for a closed range fall back to
+    /// `get` and stop the stream once `end` is reached.
+    fn get_range(
+        &self,
+        p: String,
+        start: u64,
+        end: u64,
+    ) -> BoxFuture<StorageBackendResult<StreamFile>> {
+        let file = self.get(p, start);
+        Box::pin(async move { Ok(file.await?.take(end.saturating_sub(start))) })
+    }
+    fn put(&self, p: String, file: UploadFile) -> BoxFuture<StorageBackendResult<()>>;
+    fn mkdir(&self, dir: String) -> BoxFuture<StorageBackendResult<()>>;
+    fn delete(&self, p: String) -> BoxFuture<StorageBackendResult<()>>;
+    fn rename(&self, from: String, to: String) -> BoxFuture<StorageBackendResult<()>>;
+    /// Files added, changed or removed right in `dir` since `cursor` was handed out, the
+    /// folders below it are left out. `None` starts over and reports every file.
+    fn changes_since(
+        &self,
+        dir: String,
+        cursor: Option<String>,
+    ) -> BoxFuture<StorageBackendResult<StorageChanges>>;
+}
+
+impl UploadFile {
+    pub fn new(rx: async_channel::Receiver<StorageBackendResult<Bytes>>, size: u64) -> Self {
+        Self { rx, size }
+    }
+    pub fn new_from_bytes(buf: Bytes) -> Self {
+        let size = buf.len() as u64;
+        let (tx, rx) = async_channel::bounded::<StorageBackendResult<Bytes>>(1);
+        let _ = tx.try_send(Ok(buf));
+        tx.close();
+        Self { rx, size }
+    }
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    pub fn into_rx(self) -> async_channel::Receiver<StorageBackendResult<Bytes>> {
+        self.rx
+    }
+}
+
+pub(crate) fn parse_rfc3339_time(v: &str) -> Option<Duration> {
+    let t = chrono::DateTime::parse_from_rfc3339(v.trim()).ok()?;
+    Some(Duration::from_millis(
+        u64::try_from(t.timestamp_millis()).ok()?,
+    ))
+}
+
+/// Parses an HTTP date such as `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub(crate) fn parse_http_time(v: &str) -> Option<Duration> {
+    let t = chrono::DateTime::parse_from_rfc2822(v.trim()).ok()?;
+    Some(Duration::from_millis(
+        u64::try_from(t.timestamp_millis()).ok()?,
+    ))
+}
+
+/// Value of a `Range` header for `start..end`, or for everything from `start` when `end` is `None`.
+pub(crate) fn format_range(start: u64, end: Option<u64>) -> String {
+    match end {
+        Some(end) => format!("bytes={start}-{}", end.max(start + 1) - 1),
+        None => format!("bytes={start}-"),
+    }
+}
+
+/// Parses the complete length out of `Content-Range: bytes 0-99/1234`.
+fn parse_content_range_total(v: &str) -> Option<u64> {
+    let (_, total) = v.trim().strip_prefix("bytes")?.rsplit_once('/')?;
+    total.trim().parse::<u64>().ok()
+}
+
+/// Cuts `chunk` down to what is left of `limit`.
+fn take_chunk(chunk: Bytes, limit: &mut Option<u64>) -> Bytes {
+    match limit {
+        None => chunk,
+        Some(remaining) => {
+            let n = (*remaining).min(chunk.len() as u64);
+            *remaining -= n;
+            chunk.slice(..n as usize)
+        }
+    }
 }
 
 impl StreamFile {
@@ -96,12 +380,19 @@ impl StreamFile {
         let content_type = header_map
             .get(reqwest::header::CONTENT_TYPE)
             .map(|v| v.to_str().unwrap().to_string());
+        let full_size = match header_map.get(reqwest::header::CONTENT_RANGE) {
+            Some(v) => v.to_str().ok().and_then(parse_content_range_total),
+            None => content_length.map(|v| v as u64),
+        };
         Self {
             inner: StreamFileInner::Response(resp),
             total: content_length,
             content_type,
             name: name.to_string(),
             byte_offset,
+            limit: None,
+            read_timeout: None,
+            full_size,
         }
     }
     pub fn new_from_bytes(buf: &[u8], name: &str, byte_offset: u64) -> Self {
@@ -113,10 +404,62 @@ impl StreamFile {
             content_type: None,
             name: name.to_string(),
             byte_offset: byte_offset.min(total as u64),
+            limit: None,
+            read_timeout: None,
+            full_size: Some(total as u64),
         }
     }
+    /// The producer behind `rx` is expected to have skipped to the requested offset already,
+    /// so `total` is the number of bytes it will yield.
+    pub fn new_from_rx(
+        rx: async_channel::Receiver<StorageBackendResult<Bytes>>,
+        total: Option<usize>,
+        name: &str,
+    ) -> Self {
+        Self {
+            inner: StreamFileInner::Channel(rx),
+            total,
+            content_type: None,
+            name: name.to_string(),
+            byte_offset: 0,
+            limit: None,
+            read_timeout: None,
+            full_size: None,
+        }
+    }
+    pub fn new_from_file(file: tokio::fs::File, total: u64, name: &str, byte_offset: u64) -> Self {
+        Self {
+            inner: StreamFileInner::File(file),
+            total: Some(total as usize),
+            content_type: None,
+            name: name.to_string(),
+            byte_offset: byte_offset.min(total),
+            limit: None,
+            read_timeout: None,
+            full_size: Some(total),
+        }
+    }
+    /// Fails the stream when the server sends nothing for this long.
+    pub(crate) fn with_read_timeout(mut self, read_timeout: Option<Duration>) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+    /// Stops the file after `len` bytes.
+    pub fn take(mut self, len: u64) -> Self {
+        self.limit = Some(self.limit.map_or(len, |limit| limit.min(len)));
+        self
+    }
     pub fn size(&self) -> Option<usize> {
-        self.total.map(|total| total - self.byte_offset as usize)
+        let size = self.total.map(|total| total - self.byte_offset as usize);
+        match (size, self.limit) {
+            (Some(size), Some(limit)) => Some(size.min(limit as usize)),
+            (None, Some(limit)) => Some(limit as usize),
+            (size, None) => size,
+        }
+    }
+    /// Size of the whole resource, which for a partial response comes from `Content-Range`.
+    pub fn total_size(&self) -> Option<u64> {
+        self.full_size
     }
     pub fn content_type(&self) -> Option<&str> {
         self.content_type.as_deref()
@@ -130,31 +473,64 @@ impl StreamFile {
 
         let _ = tokio_runtime().spawn(async move {
             let f = || async {
+                let mut limit = self.limit;
+                if limit == Some(0) {
+                    return Ok(());
+                }
                 match self.inner {
                     StreamFileInner::Response(mut response) => {
                         let mut remaining = self.byte_offset as usize;
 
-                        while let Some(chunk) = response.chunk().await? {
-                            if chunk.len() <= remaining {
+                        while let Some(chunk) =
+                            with_read_timeout(self.read_timeout, response.chunk()).await??
+                        {
+                            let chunk = if chunk.len() <= remaining {
                                 remaining -= chunk.len();
+                                continue;
                             } else if remaining > 0 {
                                 let chunk = Bytes::copy_from_slice(&chunk[remaining..]);
                                 remaining = 0;
-                                tx.send(Ok(chunk)).await?;
+                                chunk
                             } else {
-                                tx.send(Ok(chunk)).await?;
+                                chunk
+                            };
+                            tx.send(Ok(take_chunk(chunk, &mut limit))).await?;
+                            if limit == Some(0) {
+                                break;
                             }
                         }
                     }
                     StreamFileInner::Total(buf) => {
                         let offset = self.byte_offset as usize;
-                        if offset == 0 {
+                        if offset == 0 && limit.is_none() {
                             tx.send(Ok(buf)).await?;
                         } else {
-                            let buf = Bytes::copy_from_slice(&buf[offset..]);
+                            let buf =
+                                take_chunk(Bytes::copy_from_slice(&buf[offset..]), &mut limit);
                             tx.send(Ok(buf)).await?;
                         }
                     }
+                    StreamFileInner::Channel(rx) => {
+                        while let Ok(chunk) = rx.recv().await {
+                            tx.send(chunk.map(|chunk| take_chunk(chunk, &mut limit)))
+                                .await?;
+                            if limit == Some(0) {
+                                break;
+                            }
+                        }
+                    }
+                    StreamFileInner::File(mut file) => {
+                        file.seek(SeekFrom::Start(self.byte_offset)).await?;
+                        let mut file = file.take(limit.unwrap_or(u64::MAX));
+                        let mut buf = vec![0u8; FILE_CHUNK_SIZE];
+                        loop {
+                            let n = file.read(&mut buf).await?;
+                            if n == 0 {
+                                break;
+                            }
+                            tx.send(Ok(Bytes::copy_from_slice(&buf[..n]))).await?;
+                        }
+                    }
                 }
 
                 Ok(())
@@ -164,6 +540,7 @@ impl StreamFile {
             if let Err(e) = res {
                 let e: Option<StorageBackendError> = match e {
                     SendChunkError::RequestFail(e) => Some(e.into()),
+                    SendChunkError::TokioIO(e) => Some(e.into()),
                     _ => None,
                 };
                 if let Some(e) = e {
@@ -177,9 +554,36 @@ impl StreamFile {
     }
 
     pub async fn bytes(self) -> StorageBackendResult<Bytes> {
+        if self.limit.is_some() || self.read_timeout.is_some() {
+            let mut buf = bytes::BytesMut::with_capacity(self.size().unwrap_or_default());
+            let rx = self.into_rx();
+            while let Ok(chunk) = rx.recv().await {
+                buf.extend_from_slice(&chunk?);
+            }
+            return Ok(buf.freeze());
+        }
         let buf = match self.inner {
             StreamFileInner::Response(response) => response.bytes().await?,
             StreamFileInner::Total(buf) => buf,
+            StreamFileInner::Channel(rx) => {
+                let mut buf = bytes::BytesMut::with_capacity(self.total.unwrap_or_default());
+                while let Ok(chunk) = rx.recv().await {
+                    buf.extend_from_slice(&chunk?);
+                }
+                buf.freeze()
+            }
+            StreamFileInner::File(mut file) => {
+                let byte_offset = self.byte_offset;
+                let buf = tokio_runtime()
+                    .spawn(async move {
+                        let mut buf: Vec<u8> = Default::default();
+                        file.seek(SeekFrom::Start(byte_offset)).await?;
+                        file.read_to_end(&mut buf).await?;
+                        Ok::<_, StorageBackendError>(buf)
+                    })
+                    .await??;
+                return Ok(Bytes::from(buf));
+            }
         };
 
         let offset = (self.byte_offset as usize).min(buf.len());
@@ -0,0 +1,1217 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::SeekFrom,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use ease_client_tokio::tokio_runtime;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::watch,
+    task::JoinSet,
+};
+
+use crate::changes::{signature, StorageChanges};
+use crate::{
+    Entry, StorageBackend, StorageBackendError, StorageBackendResult, StreamFile, UploadFile,
+};
+
+const INDEX_FILE_NAME: &str = "index.json";
+const DEFAULT_CACHE_BUDGET: u64 = 1 << 30;
+/// Files are cached in blocks of this size, only the last one of a file may be shorter.
+const BLOCK_SIZE: u64 = 256 * 1024;
+/// Blocks asked for in one request to the remote.
+const FETCH_BLOCKS: u64 = 8;
+/// Requests a single read keeps in flight, each for a different part of the file.
+const FETCH_CONCURRENCY: usize = 3;
+/// How far past the read position missing blocks are fetched.
+const READ_AHEAD_BLOCKS: u64 = FETCH_BLOCKS * FETCH_CONCURRENCY as u64;
+/// How long a version check against the remote is trusted, so that seeking doesn't stat
+/// the file every time.
+const VERIFY_TTL: Duration = Duration::from_secs(5 * 60);
+/// Fetches end often, the blocks they add are written to the index at most this often.
+const INDEX_SAVE_DELAY: Duration = Duration::from_secs(1);
+
+/// Presence bitmap of the blocks of a file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct BlockSet(Vec<u64>);
+
+impl BlockSet {
+    fn contains(&self, block: u64) -> bool {
+        let (word, bit) = ((block / 64) as usize, block % 64);
+        self.0.get(word).is_some_and(|v| v & (1 << bit) != 0)
+    }
+
+    fn insert(&mut self, block: u64) {
+        let (word, bit) = ((block / 64) as usize, block % 64);
+        if self.0.len() <= word {
+            self.0.resize(word + 1, 0);
+        }
+        self.0[word] |= 1 << bit;
+    }
+
+    fn retain(&mut self, mut f: impl FnMut(u64) -> bool) {
+        for (word, v) in self.0.iter_mut().enumerate() {
+            for bit in 0..64 {
+                if *v & (1 << bit) != 0 && !f(word as u64 * 64 + bit) {
+                    *v &= !(1 << bit);
+                }
+            }
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.0.iter().enumerate().flat_map(|(word, v)| {
+            (0..64)
+                .filter(move |bit| v & (1 << bit) != 0)
+                .map(move |bit| word as u64 * 64 + bit)
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(|v| *v == 0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheItem {
+    namespace: String,
+    path: String,
+    name: String,
+    /// Signature of the remote version the bytes belong to, empty until first fetched.
+    version: String,
+    total: Option<u64>,
+    /// Who pinned the file, e.g. a music and the playlists it is in. Kept while any is left.
+    #[serde(default)]
+    pins: BTreeSet<String>,
+    /// Milliseconds since the unix epoch.
+    last_access: u64,
+    #[serde(default)]
+    blocks: BlockSet,
+}
+
+impl CacheItem {
+    fn is_pinned(&self) -> bool {
+        !self.pins.is_empty()
+    }
+
+    /// Length of a present block.
+    fn block_len(&self, block: u64) -> Option<u64> {
+        if !self.blocks.contains(block) {
+            return None;
+        }
+        let start = block * BLOCK_SIZE;
+        Some(match self.total {
+            Some(total) => total.saturating_sub(start).min(BLOCK_SIZE),
+            None => BLOCK_SIZE,
+        })
+    }
+
+    fn cached(&self) -> u64 {
+        self.blocks
+            .iter()
+            .map(|block| self.block_len(block).unwrap_or_default())
+            .sum()
+    }
+
+    fn is_complete(&self) -> bool {
+        self.total.is_some_and(|total| {
+            (0..total.div_ceil(BLOCK_SIZE)).all(|block| self.blocks.contains(block))
+        })
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    budget: Option<u64>,
+    #[serde(default)]
+    block_size: u64,
+    /// By file name.
+    items: HashMap<String, CacheItem>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    index: CacheIndex,
+    /// Blocks a request to the remote is filling, by file name.
+    fetching: HashMap<String, HashSet<u64>>,
+    verified: HashMap<String, Instant>,
+    /// A save of the index is scheduled.
+    save_pending: bool,
+}
+
+impl CacheState {
+    fn is_fetching(&self, key: &str) -> bool {
+        self.fetching.get(key).is_some_and(|v| !v.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DiskCacheUsage {
+    pub budget: u64,
+    pub used: u64,
+    pub pinned: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskCacheEntryState {
+    None,
+    Partial,
+    Complete,
+}
+
+/// Remote files kept on disk for [`CachedBackend`], shared by every storage. Files are
+/// sparse, made of fixed size blocks fetched as they are read. The least recently used
+/// files go first once the budget is exceeded, pinned ones never do.
+pub struct DiskCache {
+    dir: PathBuf,
+    state: Mutex<CacheState>,
+    /// Bumped whenever blocks are added or a fetch ends, readers waiting on a block
+    /// check again.
+    changed: watch::Sender<u64>,
+}
+
+fn now_ms() -> u64 {
+    UNIX_EPOCH.elapsed().unwrap_or_default().as_millis() as u64
+}
+
+fn file_key(namespace: &str, path: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(namespace.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(path.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+fn entry_name(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or_default().to_string()
+}
+
+impl DiskCache {
+    pub fn open(dir: impl Into<PathBuf>) -> StorageBackendResult<Arc<Self>> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut index: CacheIndex = std::fs::read(dir.join(INDEX_FILE_NAME))
+            .ok()
+            .and_then(|v| serde_json::from_slice(&v).ok())
+            .unwrap_or_default();
+        if index.block_size != BLOCK_SIZE {
+            index.items.retain(|_, item| item.is_pinned());
+            for item in index.items.values_mut() {
+                item.blocks = Default::default();
+            }
+            index.block_size = BLOCK_SIZE;
+        }
+        for (key, item) in index.items.iter_mut() {
+            // Blocks recorded before a crash may not have made it to disk
+            let len = std::fs::metadata(dir.join(key)).map_or(0, |v| v.len());
+            let total = item.total;
+            item.blocks.retain(|block| {
+                let end = (block + 1) * BLOCK_SIZE;
+                total.map_or(end, |total| end.min(total)) <= len
+            });
+        }
+        // A pin outlives its bytes, they come back on the next download
+        index
+            .items
+            .retain(|_, item| item.is_pinned() || !item.blocks.is_empty());
+        for entry in std::fs::read_dir(&dir)?.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name != INDEX_FILE_NAME && !index.items.contains_key(&name) {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+
+        Ok(Arc::new(Self {
+            dir,
+            state: Mutex::new(CacheState {
+                index,
+                ..Default::default()
+            }),
+            changed: watch::channel(0).0,
+        }))
+    }
+
+    pub fn budget(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.index.budget.unwrap_or(DEFAULT_CACHE_BUDGET)
+    }
+
+    pub fn set_budget(&self, budget: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.index.budget = Some(budget);
+        self.evict(&mut state);
+        self.save_index(&state);
+    }
+
+    pub fn usage(&self) -> DiskCacheUsage {
+        let state = self.state.lock().unwrap();
+        let items = state.index.items.values();
+        DiskCacheUsage {
+            budget: state.index.budget.unwrap_or(DEFAULT_CACHE_BUDGET),
+            used: items.clone().map(|item| item.cached()).sum(),
+            pinned: items
+                .filter(|item| item.is_pinned())
+                .map(|item| item.cached())
+                .sum(),
+        }
+    }
+
+    /// Pinned files are kept whatever the budget, until every `source` that pinned them
+    /// unpins. Pinning doesn't download anything, read the file through a [`CachedBackend`]
+    /// for that.
+    pub fn set_pinned(&self, namespace: &str, path: &str, source: &str, pinned: bool) {
+        let key = file_key(namespace, path);
+        let mut state = self.state.lock().unwrap();
+        match state.index.items.get_mut(&key) {
+            Some(item) if pinned => {
+                item.pins.insert(source.to_string());
+            }
+            Some(item) => {
+                item.pins.remove(source);
+            }
+            None if pinned => {
+                state.index.items.insert(
+                    key,
+                    CacheItem {
+                        namespace: namespace.to_string(),
+                        path: path.to_string(),
+                        name: entry_name(path),
+                        version: Default::default(),
+                        total: None,
+                        pins: BTreeSet::from([source.to_string()]),
+                        last_access: now_ms(),
+                        blocks: Default::default(),
+                    },
+                );
+            }
+            None => {}
+        }
+        self.evict(&mut state);
+        self.save_index(&state);
+    }
+
+    /// Drops the pins of `source` from every file, e.g. of a removed playlist.
+    pub fn unpin_source(&self, source: &str) {
+        let mut state = self.state.lock().unwrap();
+        for item in state.index.items.values_mut() {
+            item.pins.remove(source);
+        }
+        self.evict(&mut state);
+        self.save_index(&state);
+    }
+
+    pub fn is_pinned(&self, namespace: &str, path: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        let item = state.index.items.get(&file_key(namespace, path));
+        item.is_some_and(|item| item.is_pinned())
+    }
+
+    pub fn entry_state(&self, namespace: &str, path: &str) -> DiskCacheEntryState {
+        let state = self.state.lock().unwrap();
+        match state.index.items.get(&file_key(namespace, path)) {
+            Some(item) if item.is_complete() => DiskCacheEntryState::Complete,
+            Some(item) if !item.blocks.is_empty() => DiskCacheEntryState::Partial,
+            _ => DiskCacheEntryState::None,
+        }
+    }
+
+    pub fn remove(&self, namespace: &str, path: &str) {
+        let key = file_key(namespace, path);
+        let mut state = self.state.lock().unwrap();
+        self.remove_item(&mut state, &key);
+        self.save_index(&state);
+    }
+
+    /// Drops everything of a storage, pins included.
+    pub fn remove_namespace(&self, namespace: &str) {
+        let mut state = self.state.lock().unwrap();
+        let keys: Vec<String> = state
+            .index
+            .items
+            .iter()
+            .filter(|(_, item)| item.namespace == namespace)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            self.remove_item(&mut state, &key);
+        }
+        self.save_index(&state);
+    }
+
+    fn remove_item(&self, state: &mut CacheState, key: &str) {
+        state.index.items.remove(key);
+        state.verified.remove(key);
+        let _ = std::fs::remove_file(self.dir.join(key));
+    }
+
+    fn save_index(&self, state: &CacheState) {
+        let tmp = self.dir.join(format!("{INDEX_FILE_NAME}.tmp"));
+        let res = serde_json::to_vec(&state.index)
+            .map_err(std::io::Error::from)
+            .and_then(|buf| std::fs::write(&tmp, buf))
+            .and_then(|_| std::fs::rename(&tmp, self.dir.join(INDEX_FILE_NAME)));
+        if let Err(e) = res {
+            tracing::warn!("save disk cache index fail: {e:?}");
+        }
+    }
+
+    /// Saves the index a little later off the runtime threads, so that a burst of fetches
+    /// writes it once. Blocks not saved before a crash are fetched again.
+    fn schedule_save_index(self: &Arc<Self>, state: &mut CacheState) {
+        if state.save_pending {
+            return;
+        }
+        state.save_pending = true;
+        let cache = self.clone();
+        tokio_runtime().spawn(async move {
+            tokio::time::sleep(INDEX_SAVE_DELAY).await;
+            let res = tokio::task::spawn_blocking(move || {
+                let mut state = cache.state.lock().unwrap();
+                state.save_pending = false;
+                cache.save_index(&state);
+            })
+            .await;
+            if let Err(e) = res {
+                tracing::warn!("save disk cache index fail: {e:?}");
+            }
+        });
+    }
+
+    fn evict(&self, state: &mut CacheState) {
+        let budget = state.index.budget.unwrap_or(DEFAULT_CACHE_BUDGET);
+        let mut used: u64 = state.index.items.values().map(|item| item.cached()).sum();
+        let mut candidates: Vec<(u64, String, u64)> = state
+            .index
+            .items
+            .iter()
+            .filter(|(key, item)| !item.is_pinned() && !state.is_fetching(key))
+            .map(|(key, item)| (item.last_access, key.clone(), item.cached()))
+            .collect();
+        candidates.sort();
+        for (_, key, cached) in candidates {
+            if used <= budget {
+                break;
+            }
+            used -= cached;
+            self.remove_item(state, &key);
+        }
+    }
+
+    fn item(&self, key: &str) -> Option<CacheItem> {
+        let state = self.state.lock().unwrap();
+        state.index.items.get(key).cloned()
+    }
+
+    /// The item if its version was checked against the remote recently.
+    fn verified_item(&self, key: &str) -> Option<CacheItem> {
+        let state = self.state.lock().unwrap();
+        let verified_at = state.verified.get(key)?;
+        if verified_at.elapsed() > VERIFY_TTL {
+            return None;
+        }
+        state.index.items.get(key).cloned()
+    }
+
+    /// `None` if the remote changed while blocks of the old version are still being
+    /// fetched, the cache is bypassed until they are done.
+    fn update_version(&self, key: &str, namespace: &str, entry: &Entry) -> Option<CacheItem> {
+        let version = signature(entry);
+        let total = entry.size.map(|v| v as u64);
+        let mut state = self.state.lock().unwrap();
+        state.verified.insert(key.to_string(), Instant::now());
+
+        let stale = state
+            .index
+            .items
+            .get(key)
+            .is_some_and(|item| !item.version.is_empty() && item.version != version);
+        if stale && state.is_fetching(key) {
+            state.verified.remove(key);
+            return None;
+        }
+        if stale {
+            let pins = state.index.items[key].pins.clone();
+            self.remove_item(&mut state, key);
+            state.verified.insert(key.to_string(), Instant::now());
+            state.index.items.insert(
+                key.to_string(),
+                CacheItem {
+                    namespace: namespace.to_string(),
+                    path: entry.path.clone(),
+                    name: entry.name.clone(),
+                    version,
+                    total,
+                    pins,
+                    last_access: now_ms(),
+                    blocks: Default::default(),
+                },
+            );
+            self.save_index(&state);
+        } else if let Some(item) = state.index.items.get_mut(key) {
+            // A pin made before the first download
+            if item.version.is_empty() {
+                item.version = version;
+                item.total = total;
+                item.name = entry.name.clone();
+            }
+        } else {
+            state.index.items.insert(
+                key.to_string(),
+                CacheItem {
+                    namespace: namespace.to_string(),
+                    path: entry.path.clone(),
+                    name: entry.name.clone(),
+                    version,
+                    total,
+                    pins: Default::default(),
+                    last_access: now_ms(),
+                    blocks: Default::default(),
+                },
+            );
+        }
+        state.index.items.get(key).cloned()
+    }
+
+    fn touch(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(item) = state.index.items.get_mut(key) {
+            item.last_access = now_ms();
+        }
+    }
+
+    fn total(&self, key: &str) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        state.index.items.get(key).and_then(|item| item.total)
+    }
+
+    /// The length of the block if it is on disk, and whether it is being fetched.
+    fn block_state(&self, key: &str, block: u64) -> (Option<u64>, bool) {
+        let state = self.state.lock().unwrap();
+        let len = state
+            .index
+            .items
+            .get(key)
+            .and_then(|item| item.block_len(block));
+        let fetching = state
+            .fetching
+            .get(key)
+            .is_some_and(|blocks| blocks.contains(&block));
+        (len, fetching)
+    }
+
+    /// Claims the first run of blocks in `from..to` that is neither on disk nor being
+    /// fetched, at most [`FETCH_BLOCKS`] long.
+    fn claim(self: &Arc<Self>, key: &str, from: u64, to: u64) -> Option<BlockClaim> {
+        let mut state = self.state.lock().unwrap();
+        let item = state.index.items.get(key)?;
+        let version = item.version.clone();
+        let fetching = state.fetching.get(key);
+        let is_free = |block: u64| {
+            !item.blocks.contains(block) && !fetching.is_some_and(|v| v.contains(&block))
+        };
+        let first = (from..to).find(|block| is_free(*block))?;
+        let last = (first..to.min(first + FETCH_BLOCKS))
+            .take_while(|block| is_free(*block))
+            .last()
+            .unwrap_or(first);
+
+        state
+            .fetching
+            .entry(key.to_string())
+            .or_default()
+            .extend(first..=last);
+        self.evict(&mut state);
+        Some(BlockClaim {
+            cache: self.clone(),
+            key: key.to_string(),
+            version,
+            blocks: first..last + 1,
+        })
+    }
+
+    fn notify(&self) {
+        self.changed.send_modify(|v| *v = v.wrapping_add(1));
+    }
+}
+
+/// Blocks a fetch is responsible for. Whatever it didn't fill is released on drop, so
+/// another read can claim it again.
+struct BlockClaim {
+    cache: Arc<DiskCache>,
+    key: String,
+    version: String,
+    blocks: std::ops::Range<u64>,
+}
+
+impl BlockClaim {
+    /// Records a block as on disk, `total` once the end of the file was reached.
+    fn fill(&self, block: Option<u64>, total: Option<u64>) {
+        let mut state = self.cache.state.lock().unwrap();
+        if let Some(item) = state.index.items.get_mut(&self.key) {
+            // The entry was reset for a newer version meanwhile
+            if item.version == self.version {
+                if let Some(block) = block {
+                    item.blocks.insert(block);
+                }
+                item.last_access = now_ms();
+                if total.is_some() {
+                    item.total = total;
+                }
+            }
+        }
+        if let (Some(blocks), Some(block)) = (state.fetching.get_mut(&self.key), block) {
+            blocks.remove(&block);
+        }
+        drop(state);
+        self.cache.notify();
+    }
+}
+
+impl Drop for BlockClaim {
+    fn drop(&mut self) {
+        let mut state = self.cache.state.lock().unwrap();
+        if let Some(blocks) = state.fetching.get_mut(&self.key) {
+            for block in self.blocks.clone() {
+                blocks.remove(&block);
+            }
+            if blocks.is_empty() {
+                state.fetching.remove(&self.key);
+            }
+        }
+        self.cache.evict(&mut state);
+        self.cache.schedule_save_index(&mut state);
+        drop(state);
+        self.cache.notify();
+    }
+}
+
+/// Reads through a [`DiskCache`]. Blocks already on disk are served from there, the
+/// missing ones are fetched a few requests at a time just ahead of the reader, so seeking
+/// back into a file doesn't go to the remote again. Without a connection, whatever is on
+/// disk is served as is.
+pub struct CachedBackend {
+    inner: Arc<dyn StorageBackend + Send + Sync>,
+    cache: Arc<DiskCache>,
+    namespace: String,
+}
+
+impl CachedBackend {
+    /// `namespace` tells the storages sharing `cache` apart.
+    pub fn new(
+        inner: Arc<dyn StorageBackend + Send + Sync>,
+        cache: Arc<DiskCache>,
+        namespace: String,
+    ) -> Self {
+        Self {
+            inner,
+            cache,
+            namespace,
+        }
+    }
+
+    async fn verify(&self, key: &str, p: &str) -> StorageBackendResult<Option<CacheItem>> {
+        if let Some(item) = self.cache.verified_item(key) {
+            return Ok(Some(item));
+        }
+        let entry = self.inner.stat(p.to_string()).await?;
+        Ok(self.cache.update_version(key, &self.namespace, &entry))
+    }
+
+    async fn get_remote(
+        &self,
+        p: String,
+        start: u64,
+        end: Option<u64>,
+    ) -> StorageBackendResult<StreamFile> {
+        match end {
+            Some(end) => self.inner.get_range(p, start, end).await,
+            None => self.inner.get(p, start).await,
+        }
+    }
+
+    async fn get_impl(
+        &self,
+        p: String,
+        start: u64,
+        end: Option<u64>,
+    ) -> StorageBackendResult<StreamFile> {
+        let key = file_key(&self.namespace, &p);
+        let (item, offline) = match self.verify(&key, &p).await {
+            Ok(Some(item)) => (item, None),
+            Ok(None) => return self.get_remote(p, start, end).await,
+            Err(e) => match self.cache.item(&key) {
+                Some(item) if !e.is_not_found() && item.block_len(start / BLOCK_SIZE).is_some() => {
+                    tracing::warn!("serve {p} from disk cache, remote fails: {e:?}");
+                    (item, Some(e))
+                }
+                _ => return Err(e),
+            },
+        };
+        // Blocks can't be laid out without knowing where the file ends
+        let Some(end) = end.or(item.total) else {
+            return self.get_remote(p, start, None).await;
+        };
+        let end = item.total.map_or(end, |total| end.min(total));
+        self.cache.touch(&key);
+
+        let (tx, rx) = async_channel::bounded(10);
+        let read = BlockRead {
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+            key,
+            p,
+            end,
+            tx,
+        };
+        tokio_runtime().spawn(read.run(start, offline));
+
+        Ok(StreamFile::new_from_rx(
+            rx,
+            Some(end.saturating_sub(start) as usize),
+            &item.name,
+        ))
+    }
+}
+
+/// Streams `..end` of a file out of the cache, fetching the blocks it misses.
+struct BlockRead {
+    inner: Arc<dyn StorageBackend + Send + Sync>,
+    cache: Arc<DiskCache>,
+    key: String,
+    p: String,
+    end: u64,
+    tx: async_channel::Sender<StorageBackendResult<Bytes>>,
+}
+
+impl BlockRead {
+    async fn run(self, start: u64, offline: Option<StorageBackendError>) {
+        if let Err(e) = self.run_impl(start, offline).await {
+            let _ = self.tx.send(Err(e)).await;
+        }
+    }
+
+    async fn run_impl(
+        &self,
+        mut pos: u64,
+        mut failed: Option<StorageBackendError>,
+    ) -> StorageBackendResult<()> {
+        let mut changed = self.cache.changed.subscribe();
+        // Dropped along with the read, which aborts the fetches and releases their blocks
+        let mut fetches: JoinSet<StorageBackendResult<()>> = JoinSet::new();
+        let mut end = self.end;
+
+        while pos < end {
+            changed.borrow_and_update();
+            let block = pos / BLOCK_SIZE;
+            let last_block = end.div_ceil(BLOCK_SIZE);
+            while failed.is_none() && fetches.len() < FETCH_CONCURRENCY {
+                let to = last_block.min(block + READ_AHEAD_BLOCKS);
+                let Some(claim) = self.cache.claim(&self.key, block, to) else {
+                    break;
+                };
+                fetches.spawn(fetch_blocks(self.inner.clone(), self.p.clone(), claim));
+            }
+
+            match self.cache.block_state(&self.key, block) {
+                (Some(len), _) => {
+                    let block_end = (block * BLOCK_SIZE + len).min(end);
+                    if block_end <= pos {
+                        // The file turned out to be shorter
+                        break;
+                    }
+                    let buf = self.read_block(pos, block_end).await?;
+                    pos = block_end;
+                    if self.tx.send(Ok(buf)).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                (None, fetching) => {
+                    if !fetching && fetches.is_empty() {
+                        // Nothing left that could fill the block
+                        return Err(failed.unwrap_or_else(|| {
+                            std::io::Error::other("disk cache entry removed").into()
+                        }));
+                    }
+                    tokio::select! {
+                        _ = changed.changed() => {}
+                        Some(res) = fetches.join_next() => {
+                            match res {
+                                Ok(Ok(())) => {}
+                                Ok(Err(e)) => {
+                                    tracing::warn!("fetch {} fail: {e:?}", self.p);
+                                    failed = Some(e);
+                                }
+                                Err(e) => failed = Some(std::io::Error::other(e).into()),
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(total) = self.cache.total(&self.key) {
+                end = end.min(total);
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_block(&self, start: u64, end: u64) -> StorageBackendResult<Bytes> {
+        // Opened for every block, a file evicted meanwhile must not be read from
+        let mut file = tokio::fs::File::open(self.cache.dir.join(&self.key)).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        let mut buf = vec![0u8; (end - start) as usize];
+        file.read_exact(&mut buf).await?;
+        Ok(buf.into())
+    }
+}
+
+/// Fetches the claimed blocks and writes them to the file, a block at a time.
+async fn fetch_blocks(
+    inner: Arc<dyn StorageBackend + Send + Sync>,
+    p: String,
+    claim: BlockClaim,
+) -> StorageBackendResult<()> {
+    let start = claim.blocks.start * BLOCK_SIZE;
+    let total = claim.cache.total(&claim.key);
+    let fetch_end = claim.blocks.end * BLOCK_SIZE;
+    let fetch_end = total.map_or(fetch_end, |total| fetch_end.min(total));
+    let remote = inner.get_range(p, start, fetch_end).await?;
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(claim.cache.dir.join(&claim.key))
+        .await?;
+    file.seek(SeekFrom::Start(start)).await?;
+
+    let rx = remote.into_rx();
+    let mut block = claim.blocks.start;
+    let mut filled = 0;
+    while let Ok(chunk) = rx.recv().await {
+        let mut chunk = chunk?;
+        while !chunk.is_empty() {
+            let n = chunk.len().min((BLOCK_SIZE - filled) as usize);
+            file.write_all(&chunk.split_to(n)).await?;
+            filled += n as u64;
+            if filled == BLOCK_SIZE {
+                file.flush().await?;
+                claim.fill(Some(block), None);
+                block += 1;
+                filled = 0;
+            }
+        }
+    }
+
+    let pos = block * BLOCK_SIZE + filled;
+    if pos < fetch_end {
+        if total.is_some() {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        // Asked past the end of a file whose size wasn't known
+        file.flush().await?;
+        claim.fill((filled > 0).then_some(block), Some(pos));
+    } else if filled > 0 {
+        file.flush().await?;
+        claim.fill(Some(block), None);
+    }
+    Ok(())
+}
+
+impl StorageBackend for CachedBackend {
+    fn list(&self, dir: String) -> BoxFuture<StorageBackendResult<Vec<Entry>>> {
+        self.inner.list(dir)
+    }
+
+    fn stat(&self, p: String) -> BoxFuture<StorageBackendResult<Entry>> {
+        self.inner.stat(p)
+    }
+
+    fn get(&self, p: String, byte_offset: u64) -> BoxFuture<StorageBackendResult<StreamFile>> {
+        Box::pin(self.get_impl(p, byte_offset, None))
+    }
+
+    fn get_range(
+        &self,
+        p: String,
+        start: u64,
+        end: u64,
+    ) -> BoxFuture<StorageBackendResult<StreamFile>> {
+        Box::pin(self.get_impl(p, start, Some(end)))
+    }
+
+    fn put(&self, p: String, file: UploadFile) -> BoxFuture<StorageBackendResult<()>> {
+        self.cache.remove(&self.namespace, &p);
+        self.inner.put(p, file)
+    }
+
+    fn mkdir(&self, dir: String) -> BoxFuture<StorageBackendResult<()>> {
+        self.inner.mkdir(dir)
+    }
+
+    fn delete(&self, p: String) -> BoxFuture<StorageBackendResult<()>> {
+        self.cache.remove(&self.namespace, &p);
+        self.inner.delete(p)
+    }
+
+    fn rename(&self, from: String, to: String) -> BoxFuture<StorageBackendResult<()>> {
+        self.cache.remove(&self.namespace, &from);
+        self.inner.rename(from, to)
+    }
+
+    fn changes_since(
+        &self,
+        dir: String,
+        cursor: Option<String>,
+    ) -> BoxFuture<StorageBackendResult<StorageChanges>> {
+        self.inner.changes_since(dir, cursor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use futures_util::future::BoxFuture;
+
+    use crate::{
+        Entry, LocalBackend, StorageBackend, StorageBackendResult, StorageChanges, StreamFile,
+        UploadFile,
+    };
+
+    use super::{
+        CachedBackend, DiskCache, DiskCacheEntryState, BLOCK_SIZE, FETCH_BLOCKS, INDEX_SAVE_DELAY,
+    };
+
+    /// Counts the reads that reach the remote, and can pretend to be offline.
+    #[derive(Default)]
+    struct CountingBackend {
+        inner: LocalBackend,
+        gets: AtomicUsize,
+        /// Bytes asked for by ranged reads.
+        ranged_bytes: AtomicU64,
+        offline: AtomicBool,
+    }
+
+    impl CountingBackend {
+        fn check_online(&self) -> StorageBackendResult<()> {
+            if self.offline.load(Ordering::SeqCst) {
+                return Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into());
+            }
+            Ok(())
+        }
+    }
+
+    impl StorageBackend for CountingBackend {
+        fn list(&self, dir: String) -> BoxFuture<StorageBackendResult<Vec<Entry>>> {
+            self.inner.list(dir)
+        }
+        fn stat(&self, p: String) -> BoxFuture<StorageBackendResult<Entry>> {
+            if let Err(e) = self.check_online() {
+                return Box::pin(async { Err(e) });
+            }
+            self.inner.stat(p)
+        }
+        fn get(&self, p: String, byte_offset: u64) -> BoxFuture<StorageBackendResult<StreamFile>> {
+            if let Err(e) = self.check_online() {
+                return Box::pin(async { Err(e) });
+            }
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            self.inner.get(p, byte_offset)
+        }
+        fn get_range(
+            &self,
+            p: String,
+            start: u64,
+            end: u64,
+        ) -> BoxFuture<StorageBackendResult<StreamFile>> {
+            self.ranged_bytes.fetch_add(end - start, Ordering::SeqCst);
+            Box::pin(async move { Ok(self.get(p, start).await?.take(end - start)) })
+        }
+        fn put(&self, p: String, file: UploadFile) -> BoxFuture<StorageBackendResult<()>> {
+            self.inner.put(p, file)
+        }
+        fn mkdir(&self, dir: String) -> BoxFuture<StorageBackendResult<()>> {
+            self.inner.mkdir(dir)
+        }
+        fn delete(&self, p: String) -> BoxFuture<StorageBackendResult<()>> {
+            self.inner.delete(p)
+        }
+        fn rename(&self, from: String, to: String) -> BoxFuture<StorageBackendResult<()>> {
+            self.inner.rename(from, to)
+        }
+        fn changes_since(
+            &self,
+            dir: String,
+            cursor: Option<String>,
+        ) -> BoxFuture<StorageBackendResult<StorageChanges>> {
+            self.inner.changes_since(dir, cursor)
+        }
+    }
+
+    struct Fixture {
+        root: PathBuf,
+        remote: Arc<CountingBackend>,
+        cache: Arc<DiskCache>,
+        backend: CachedBackend,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let root =
+                std::env::temp_dir().join(format!("ease-disk-cache-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(root.join("remote")).unwrap();
+            let remote: Arc<CountingBackend> = Default::default();
+            let cache = DiskCache::open(root.join("cache")).unwrap();
+            let backend = CachedBackend::new(remote.clone(), cache.clone(), "1".to_string());
+            Self {
+                root,
+                remote,
+                cache,
+                backend,
+            }
+        }
+
+        /// Writes a remote file and returns its path.
+        fn write(&self, name: &str, buf: &[u8]) -> String {
+            let p = self.root.join("remote").join(name);
+            std::fs::write(&p, buf).unwrap();
+            p.to_string_lossy().replace('\\', "/")
+        }
+
+        fn gets(&self) -> usize {
+            self.remote.gets.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn song(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn test_read_through() {
+        let fixture = Fixture::new("read-through");
+        let buf = song(200 * 1024);
+        let p = fixture.write("a.mp3", &buf);
+
+        let file = fixture.backend.get(p.clone(), 0).await.unwrap();
+        assert_eq!(file.size(), Some(buf.len()));
+        assert_eq!(file.bytes().await.unwrap().as_ref(), buf.as_slice());
+        assert_eq!(fixture.gets(), 1);
+        assert_eq!(
+            fixture.cache.entry_state("1", &p),
+            DiskCacheEntryState::Complete
+        );
+
+        let file = fixture.backend.get(p.clone(), 0).await.unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), buf.as_slice());
+        let file = fixture
+            .backend
+            .get_range(p.clone(), 1000, 5000)
+            .await
+            .unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), &buf[1000..5000]);
+        assert_eq!(fixture.gets(), 1);
+        assert_eq!(fixture.cache.usage().used, buf.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_fetched_blocks_saved_later() {
+        let fixture = Fixture::new("save-later");
+        let buf = song(200 * 1024);
+        let p = fixture.write("a.mp3", &buf);
+
+        for _ in 0..3 {
+            let file = fixture.backend.get(p.clone(), 0).await.unwrap();
+            file.bytes().await.unwrap();
+        }
+        tokio::time::sleep(INDEX_SAVE_DELAY + Duration::from_millis(500)).await;
+
+        let cache = DiskCache::open(fixture.root.join("cache")).unwrap();
+        assert_eq!(cache.entry_state("1", &p), DiskCacheEntryState::Complete);
+    }
+
+    #[tokio::test]
+    async fn test_seek_into_sparse_file() {
+        let fixture = Fixture::new("sparse");
+        let buf = song((10 * BLOCK_SIZE + 1000) as usize);
+        let p = fixture.write("a.mp3", &buf);
+        let b = BLOCK_SIZE as usize;
+
+        let file = fixture.backend.get_range(p.clone(), 0, 100).await.unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), &buf[..100]);
+        assert_eq!(
+            fixture.cache.entry_state("1", &p),
+            DiskCacheEntryState::Partial
+        );
+        assert_eq!(fixture.cache.usage().used, BLOCK_SIZE);
+
+        // Inside a block on disk
+        let file = fixture.backend.get_range(p.clone(), 50, 200).await.unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), &buf[50..200]);
+        assert_eq!(fixture.gets(), 1);
+
+        // A seek only fetches the blocks around it
+        let file = fixture
+            .backend
+            .get_range(p.clone(), 5 * BLOCK_SIZE + 10, 5 * BLOCK_SIZE + 20)
+            .await
+            .unwrap();
+        assert_eq!(file.size(), Some(10));
+        assert_eq!(
+            file.bytes().await.unwrap().as_ref(),
+            &buf[5 * b + 10..5 * b + 20]
+        );
+        assert_eq!(fixture.gets(), 2);
+        assert_eq!(fixture.cache.usage().used, 2 * BLOCK_SIZE);
+
+        // The holes on both sides of block 5 are fetched apart
+        let file = fixture
+            .backend
+            .get(p.clone(), 3 * BLOCK_SIZE)
+            .await
+            .unwrap();
+        assert_eq!(file.size(), Some(buf.len() - 3 * b));
+        assert_eq!(file.bytes().await.unwrap().as_ref(), &buf[3 * b..]);
+        assert_eq!(fixture.gets(), 4);
+        assert_eq!(fixture.cache.usage().used, 8 * BLOCK_SIZE + 1000);
+
+        let file = fixture.backend.get(p.clone(), 0).await.unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), buf.as_slice());
+        assert_eq!(fixture.gets(), 5);
+        assert_eq!(
+            fixture.cache.entry_state("1", &p),
+            DiskCacheEntryState::Complete
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_fetches() {
+        let fixture = Fixture::new("concurrent");
+        let buf = song((4 * FETCH_BLOCKS * BLOCK_SIZE) as usize);
+        let p = fixture.write("a.mp3", &buf);
+
+        // Two readers of the same file share the blocks instead of both fetching them
+        let (a, b) = tokio::join!(
+            fixture.backend.get(p.clone(), 0),
+            fixture.backend.get(p.clone(), BLOCK_SIZE)
+        );
+        let (a, b) = tokio::join!(a.unwrap().bytes(), b.unwrap().bytes());
+        assert_eq!(a.unwrap().as_ref(), buf.as_slice());
+        assert_eq!(b.unwrap().as_ref(), &buf[BLOCK_SIZE as usize..]);
+        assert_eq!(fixture.cache.usage().used, buf.len() as u64);
+        assert_eq!(
+            fixture.remote.ranged_bytes.load(Ordering::SeqCst),
+            buf.len() as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn test_offline_and_changed_remote() {
+        let fixture = Fixture::new("offline");
+        let buf = song(4096);
+        let p = fixture.write("a.mp3", &buf);
+        fixture
+            .backend
+            .get(p.clone(), 0)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+
+        fixture.remote.offline.store(true, Ordering::SeqCst);
+        // Forget the last version check, as after a restart
+        fixture.cache.state.lock().unwrap().verified.clear();
+        let file = fixture.backend.get(p.clone(), 100).await.unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), &buf[100..]);
+        let missing = fixture.write("b.mp3", b"b");
+        assert!(fixture.backend.get(missing, 0).await.is_err());
+
+        fixture.remote.offline.store(false, Ordering::SeqCst);
+        let changed = song(5000);
+        fixture.write("a.mp3", &changed);
+        fixture.cache.state.lock().unwrap().verified.clear();
+        let file = fixture.backend.get(p.clone(), 0).await.unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), changed.as_slice());
+        assert_eq!(fixture.cache.usage().used, changed.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_budget_and_pins() {
+        let fixture = Fixture::new("budget");
+        let paths: Vec<String> = ["a.mp3", "b.mp3", "c.mp3"]
+            .iter()
+            .map(|name| fixture.write(name, &song(1000)))
+            .collect();
+        fixture.cache.set_pinned("1", &paths[0], "music:1", true);
+        fixture.cache.set_pinned("1", &paths[0], "playlist:1", true);
+        fixture.cache.set_budget(3000);
+
+        for p in paths.iter() {
+            let file = fixture.backend.get(p.clone(), 0).await.unwrap();
+            file.bytes().await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        let state = |p: &String| fixture.cache.entry_state("1", p);
+        assert_eq!(state(&paths[0]), DiskCacheEntryState::Complete);
+        assert_eq!(state(&paths[1]), DiskCacheEntryState::Complete);
+        assert_eq!(state(&paths[2]), DiskCacheEntryState::Complete);
+
+        // The oldest unpinned file goes first
+        fixture.cache.set_budget(2000);
+        assert_eq!(state(&paths[0]), DiskCacheEntryState::Complete);
+        assert_eq!(state(&paths[1]), DiskCacheEntryState::None);
+        assert_eq!(state(&paths[2]), DiskCacheEntryState::Complete);
+
+        fixture.cache.set_budget(0);
+        assert_eq!(state(&paths[0]), DiskCacheEntryState::Complete);
+        assert_eq!(state(&paths[2]), DiskCacheEntryState::None);
+        assert_eq!(
+            fixture.cache.usage(),
+            super::DiskCacheUsage {
+                budget: 0,
+                used: 1000,
+                pinned: 1000,
+            }
+        );
+
+        // Pins and bytes survive a restart
+        let cache = DiskCache::open(fixture.root.join("cache")).unwrap();
+        assert!(cache.is_pinned("1", &paths[0]));
+        assert_eq!(
+            cache.entry_state("1", &paths[0]),
+            DiskCacheEntryState::Complete
+        );
+        assert_eq!(cache.budget(), 0);
+
+        // Kept until every source unpins it
+        fixture.cache.set_pinned("1", &paths[0], "music:1", false);
+        assert!(fixture.cache.is_pinned("1", &paths[0]));
+        assert_eq!(fixture.cache.usage().used, 1000);
+        fixture.cache.unpin_source("playlist:1");
+        assert!(!fixture.cache.is_pinned("1", &paths[0]));
+        assert_eq!(fixture.cache.usage().used, 0);
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Entry, StorageBackend, StorageBackendResult};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageChange {
+    /// A file that is new or whose content changed.
+    Upsert(Entry),
+    /// `path` is gone, and everything below it if it was a directory. A rename shows up
+    /// as a removal of the old path plus an upsert of the new one.
+    Removed(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StorageChanges {
+    pub changes: Vec<StorageChange>,
+    /// Opaque, hand it to the next `changes_since` call.
+    pub cursor: String,
+    /// The old cursor was missing, foreign or expired. `changes` then holds every file as
+    /// an upsert and whatever the caller knew about the tree should be dropped.
+    pub reset: bool,
+}
+
+const SNAPSHOT_CURSOR_PREFIX: &str = "snapshot:";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SnapshotCursor {
+    /// Path to the version signature of every file seen last time.
+    files: BTreeMap<String, String>,
+}
+
+/// Tells file versions apart without downloading them.
+pub(crate) fn signature(entry: &Entry) -> String {
+    match &entry.etag {
+        Some(etag) => etag.clone(),
+        None => format!(
+            "{}:{}",
+            entry.size.unwrap_or_default(),
+            entry.modified_time.unwrap_or_default().as_millis()
+        ),
+    }
+}
+
+pub(crate) fn is_under(dir: &str, p: &str) -> bool {
+    let dir = dir.trim_end_matches('/');
+    dir.is_empty() || p == dir || p.starts_with(&format!("{dir}/"))
+}
+
+/// Whether `p` sits right in `dir`, not further down.
+pub(crate) fn is_child(dir: &str, p: &str) -> bool {
+    let dir = dir.trim_end_matches('/');
+    p.strip_prefix(dir)
+        .and_then(|v| v.strip_prefix('/'))
+        .is_some_and(|name| !name.is_empty() && !name.contains('/'))
+}
+
+/// Whether a removal of `p` takes files of `dir` with it: it is one of them, or `dir` or
+/// a folder above it is gone.
+pub(crate) fn is_removal_in(dir: &str, p: &str) -> bool {
+    is_child(dir, p) || is_under(p, dir)
+}
+
+/// Lists `dir` and diffs its files against the ones kept in the cursor. The fallback for
+/// backends without a change feed, and the mtime scan of local storage. A missing `dir`
+/// lists as empty, so its files show up as removed.
+pub(crate) async fn snapshot_changes<B>(
+    backend: &B,
+    dir: &str,
+    cursor: Option<&str>,
+) -> StorageBackendResult<StorageChanges>
+where
+    B: StorageBackend + Sync + ?Sized,
+{
+    let old = cursor
+        .and_then(|v| v.strip_prefix(SNAPSHOT_CURSOR_PREFIX))
+        .and_then(|v| serde_json::from_str::<SnapshotCursor>(v).ok());
+    let reset = old.is_none();
+    let mut old = old.unwrap_or_default().files;
+
+    let mut changes: Vec<StorageChange> = Default::default();
+    let mut files: BTreeMap<String, String> = Default::default();
+    let entries = match backend.list(dir.to_string()).await {
+        Err(e) if e.is_not_found() => Default::default(),
+        r => r?,
+    };
+    for entry in entries.into_iter().filter(|v| !v.is_dir) {
+        let sig = signature(&entry);
+        let unchanged = old.remove(&entry.path).as_ref() == Some(&sig);
+        files.insert(entry.path.clone(), sig);
+        if !unchanged {
+            changes.push(StorageChange::Upsert(entry));
+        }
+    }
+    changes.extend(old.into_keys().map(StorageChange::Removed));
+
+    let cursor =
+        SNAPSHOT_CURSOR_PREFIX.to_string() + &serde_json::to_string(&SnapshotCursor { files })?;
+    Ok(StorageChanges {
+        changes,
+        cursor,
+        reset,
+    })
+}
+
+pub(crate) fn is_snapshot_cursor(cursor: &str) -> bool {
+    cursor.starts_with(SNAPSHOT_CURSOR_PREFIX)
+}
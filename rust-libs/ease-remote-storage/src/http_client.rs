@@ -0,0 +1,638 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use once_cell::sync::OnceCell;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    StatusCode,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{retry::parse_retry_after, StorageBackendError, StorageBackendResult};
+
+/// Connection pool settings of the client a backend shares between all its requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpPoolConfig {
+    /// Idle connections kept open per host.
+    pub max_idle_per_host: usize,
+    /// Idle connections are closed after this long. `None` keeps them until the server
+    /// closes them.
+    pub idle_timeout: Option<Duration>,
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl Default for HttpPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_per_host: 8,
+            idle_timeout: Some(Duration::from_secs(90)),
+            tcp_keepalive: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Per-storage TLS, proxy and header settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpNetworkConfig {
+    /// Extra trusted root certificates, PEM encoded. May hold several.
+    pub root_ca_pem: Option<String>,
+    /// Hex SHA-256 of the server certificate in DER, colons allowed. When set, exactly that
+    /// certificate is trusted whoever signed it, and the other TLS settings are ignored.
+    pub pinned_cert_sha256: Option<String>,
+    pub accept_invalid_certs: bool,
+    /// `http://`, `https://`, `socks5://` or `socks5h://` url. Without it the system proxy
+    /// settings are ignored too.
+    pub proxy: Option<String>,
+    /// Sent with every request, before the backend's own headers.
+    pub headers: Vec<(String, String)>,
+    pub user_agent: Option<String>,
+    /// Longest wait for the response headers, and then for each chunk of the body.
+    pub read_timeout: Option<Duration>,
+}
+
+fn invalid_config(e: impl ToString) -> StorageBackendError {
+    StorageBackendError::InvalidNetworkConfig(e.to_string())
+}
+
+fn parse_fingerprint(v: &str) -> StorageBackendResult<Vec<u8>> {
+    let v: String = v
+        .chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .collect();
+    let fingerprint = hex::decode(v).map_err(invalid_config)?;
+    if fingerprint.len() != 32 {
+        return Err(invalid_config("SHA-256 fingerprint must be 32 bytes"));
+    }
+    Ok(fingerprint)
+}
+
+/// Trusts one certificate by its fingerprint, for self-signed home servers. The handshake
+/// signature is still checked against that certificate.
+struct PinnedCertVerifier {
+    fingerprint: Vec<u8>,
+}
+
+impl rustls::client::ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        if Sha256::digest(&end_entity.0).as_slice() == self.fingerprint.as_slice() {
+            Ok(rustls::client::ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "certificate fingerprint mismatch".to_string(),
+            ))
+        }
+    }
+}
+
+fn build_header_map(headers: &[(String, String)]) -> StorageBackendResult<HeaderMap> {
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(invalid_config)?;
+        let value = HeaderValue::from_str(value.trim()).map_err(invalid_config)?;
+        header_map.append(name, value);
+    }
+    Ok(header_map)
+}
+
+pub(crate) trait ResponseExt: Sized {
+    /// `error_for_status`, keeping how long a rate limited client is asked to wait.
+    fn check_status(self) -> StorageBackendResult<Self>;
+}
+
+impl ResponseExt for reqwest::Response {
+    fn check_status(self) -> StorageBackendResult<Self> {
+        if self.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(StorageBackendError::RateLimited {
+                retry_after: parse_retry_after(self.headers()),
+            });
+        }
+        Ok(self.error_for_status()?)
+    }
+}
+
+/// Maps an elapsed `read_timeout` to `ErrorKind::TimedOut`.
+pub(crate) async fn with_read_timeout<F: Future>(
+    read_timeout: Option<Duration>,
+    f: F,
+) -> std::io::Result<F::Output> {
+    match read_timeout {
+        None => Ok(f.await),
+        Some(read_timeout) => tokio::time::timeout(read_timeout, f)
+            .await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "read timeout")),
+    }
+}
+
+/// One `reqwest::Client` per backend, so that keep-alive connections, HTTP/2 and TLS
+/// sessions are reused across requests. Cloning the client only clones a handle to the
+/// same pool.
+pub(crate) struct SharedHttpClient {
+    connect_timeout: Duration,
+    pool: HttpPoolConfig,
+    network: HttpNetworkConfig,
+    client: OnceCell<reqwest::Client>,
+}
+
+impl SharedHttpClient {
+    pub fn new(
+        connect_timeout: Duration,
+        pool: HttpPoolConfig,
+        network: HttpNetworkConfig,
+    ) -> Self {
+        Self {
+            connect_timeout,
+            pool,
+            network,
+            client: Default::default(),
+        }
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.network.read_timeout
+    }
+
+    /// Built on first use, so that constructing a backend never fails. A bad setting is
+    /// reported by every request instead.
+    pub fn get(&self) -> StorageBackendResult<reqwest::Client> {
+        let client = self.client.get_or_try_init(|| self.build())?;
+        Ok(client.clone())
+    }
+
+    fn build(&self) -> StorageBackendResult<reqwest::Client> {
+        let network = &self.network;
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .pool_max_idle_per_host(self.pool.max_idle_per_host)
+            .pool_idle_timeout(self.pool.idle_timeout)
+            .tcp_keepalive(self.pool.tcp_keepalive)
+            .default_headers(build_header_map(&network.headers)?);
+
+        if let Some(user_agent) = &network.user_agent {
+            builder = builder.user_agent(user_agent.as_str());
+        }
+        builder = match &network.proxy {
+            Some(proxy) => builder.proxy(reqwest::Proxy::all(proxy).map_err(invalid_config)?),
+            None => builder.no_proxy(),
+        };
+
+        if let Some(fingerprint) = &network.pinned_cert_sha256 {
+            let verifier = PinnedCertVerifier {
+                fingerprint: parse_fingerprint(fingerprint)?,
+            };
+            let tls = rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+                .with_no_client_auth();
+            builder = builder.use_preconfigured_tls(tls);
+        } else {
+            if let Some(pem) = &network.root_ca_pem {
+                let certs = reqwest::Certificate::from_pem_bundle(pem.as_bytes())
+                    .map_err(invalid_config)?;
+                if certs.is_empty() {
+                    return Err(invalid_config("no certificate in the root CA PEM"));
+                }
+                for cert in certs {
+                    builder = builder.add_root_certificate(cert);
+                }
+            }
+            builder = builder.danger_accept_invalid_certs(network.accept_invalid_certs);
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use hyper::{Body, Request, Response};
+    use sha2::Digest;
+
+    use crate::{retry::send_with_retry, RetryPolicy, StorageBackendError, StreamFile};
+
+    use super::{HttpNetworkConfig, ResponseExt, SharedHttpClient};
+
+    fn build_client(network: HttpNetworkConfig) -> reqwest::Client {
+        SharedHttpClient::new(Duration::from_secs(10), Default::default(), network)
+            .get()
+            .unwrap()
+    }
+
+    async fn setup_server_with<F, Fut>(f: F) -> (u16, tokio::task::JoinHandle<()>)
+    where
+        F: Fn(Request<Body>) -> Fut + Clone + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Response<Body>> + Send + 'static,
+    {
+        let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let make_service = hyper::service::make_service_fn(move |_| {
+            let f = f.clone();
+            async move {
+                Ok::<_, Infallible>(hyper::service::service_fn(move |req| {
+                    let f = f.clone();
+                    async move { Ok::<_, Infallible>(f(req).await) }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&addr).serve(make_service);
+        let port = server.local_addr().port();
+        let handle = tokio::spawn(async move {
+            server.await.unwrap();
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        (port, handle)
+    }
+
+    struct TestCerts {
+        ca_pem: String,
+        leaf_der: Vec<u8>,
+        leaf_key_der: Vec<u8>,
+    }
+
+    fn generate_certs() -> TestCerts {
+        let mut ca_params = rcgen::CertificateParams::new(vec![]);
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Ease Test CA");
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+
+        let leaf = rcgen::Certificate::from_params(rcgen::CertificateParams::new(vec![
+            "localhost".to_string(),
+        ]))
+        .unwrap();
+        TestCerts {
+            ca_pem: ca.serialize_pem().unwrap(),
+            leaf_der: leaf.serialize_der_with_signer(&ca).unwrap(),
+            leaf_key_der: leaf.serialize_private_key_der(),
+        }
+    }
+
+    async fn setup_tls_server(certs: &TestCerts) -> (u16, tokio::task::JoinHandle<()>) {
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(certs.leaf_der.clone())],
+                rustls::PrivateKey(certs.leaf_key_der.clone()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let service = hyper::service::service_fn(|_req| async move {
+                        Ok::<_, Infallible>(Response::new(Body::from("ok")))
+                    });
+                    let _ = hyper::server::conn::Http::new()
+                        .serve_connection(stream, service)
+                        .await;
+                });
+            }
+        });
+        (port, handle)
+    }
+
+    async fn setup_counting_server() -> (String, Arc<AtomicUsize>, tokio::task::JoinHandle<()>) {
+        let connections: Arc<AtomicUsize> = Default::default();
+        let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let make_service = hyper::service::make_service_fn({
+            let connections = connections.clone();
+            move |_| {
+                connections.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    Ok::<_, Infallible>(hyper::service::service_fn(|_req| async move {
+                        Ok::<_, Infallible>(hyper::Response::new(hyper::Body::from("ok")))
+                    }))
+                }
+            }
+        });
+        let server = hyper::Server::bind(&addr).serve(make_service);
+        let port = server.local_addr().port();
+        let handle = tokio::spawn(async move {
+            server.await.unwrap();
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        (format!("http://127.0.0.1:{port}/"), connections, handle)
+    }
+
+    #[tokio::test]
+    async fn test_shared_client_reuses_connection() {
+        let (addr, connections, handle) = setup_counting_server().await;
+
+        let shared = SharedHttpClient::new(
+            Duration::from_secs(10),
+            Default::default(),
+            Default::default(),
+        );
+        for _ in 0..5 {
+            let resp = shared.get().unwrap().get(&addr).send().await.unwrap();
+            assert_eq!(resp.text().await.unwrap(), "ok");
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+        for _ in 0..3 {
+            let shared = SharedHttpClient::new(
+                Duration::from_secs(10),
+                Default::default(),
+                Default::default(),
+            );
+            shared.get().unwrap().get(&addr).send().await.unwrap();
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 4);
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_no_idle_connections_kept() {
+        let (addr, connections, handle) = setup_counting_server().await;
+
+        let pool = super::HttpPoolConfig {
+            max_idle_per_host: 0,
+            ..Default::default()
+        };
+        let shared = SharedHttpClient::new(Duration::from_secs(10), pool, Default::default());
+        for _ in 0..3 {
+            let resp = shared.get().unwrap().get(&addr).send().await.unwrap();
+            resp.text().await.unwrap();
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_tls_settings() {
+        let certs = generate_certs();
+        let (port, handle) = setup_tls_server(&certs).await;
+        let url = format!("https://localhost:{port}/");
+
+        let fetch = |network: HttpNetworkConfig| {
+            let url = url.clone();
+            async move {
+                let resp = build_client(network).get(url).send().await?;
+                resp.text().await
+            }
+        };
+
+        assert!(fetch(Default::default()).await.is_err());
+
+        let network = HttpNetworkConfig {
+            root_ca_pem: Some(certs.ca_pem.clone()),
+            ..Default::default()
+        };
+        assert_eq!(fetch(network).await.unwrap(), "ok");
+
+        let network = HttpNetworkConfig {
+            accept_invalid_certs: true,
+            ..Default::default()
+        };
+        assert_eq!(fetch(network).await.unwrap(), "ok");
+
+        let fingerprint = hex::encode_upper(sha2::Sha256::digest(&certs.leaf_der));
+        let fingerprint = fingerprint
+            .as_bytes()
+            .chunks(2)
+            .map(|v| std::str::from_utf8(v).unwrap())
+            .collect::<Vec<_>>()
+            .join(":");
+        let network = HttpNetworkConfig {
+            pinned_cert_sha256: Some(fingerprint),
+            ..Default::default()
+        };
+        assert_eq!(fetch(network).await.unwrap(), "ok");
+
+        let network = HttpNetworkConfig {
+            pinned_cert_sha256: Some(hex::encode([7u8; 32])),
+            accept_invalid_certs: true,
+            ..Default::default()
+        };
+        assert!(fetch(network).await.is_err());
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_error_taxonomy() {
+        let (port, handle) = setup_server_with(|req: Request<Body>| async move {
+            let status: u16 = req.uri().path()[1..].parse().unwrap();
+            Response::builder()
+                .status(status)
+                .header("Retry-After", "30")
+                .body(Body::empty())
+                .unwrap()
+        })
+        .await;
+        let client = build_client(Default::default());
+        let fetch = |status: u16| {
+            let req = client.get(format!("http://127.0.0.1:{port}/{status}"));
+            async move { req.send().await.unwrap().check_status().map(|_| ()) }
+        };
+
+        assert!(fetch(200).await.is_ok());
+        assert!(matches!(
+            fetch(403).await,
+            Err(StorageBackendError::Forbidden)
+        ));
+        assert!(matches!(
+            fetch(405).await,
+            Err(StorageBackendError::MethodNotAllowed)
+        ));
+        assert!(matches!(
+            fetch(429).await,
+            Err(StorageBackendError::RateLimited { retry_after: Some(v) })
+                if v == Duration::from_secs(30)
+        ));
+        assert!(matches!(
+            fetch(503).await,
+            Err(StorageBackendError::ServerError(status)) if status.as_u16() == 503
+        ));
+        assert!(matches!(
+            fetch(507).await,
+            Err(StorageBackendError::InsufficientStorage)
+        ));
+        assert!(fetch(401).await.unwrap_err().is_unauthorized());
+        assert!(fetch(404).await.unwrap_err().is_not_found());
+        handle.abort();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let e: StorageBackendError = client
+            .get(format!("http://127.0.0.1:{port}/"))
+            .send()
+            .await
+            .unwrap_err()
+            .into();
+        assert!(matches!(e, StorageBackendError::ConnectionRefused));
+
+        let e: StorageBackendError = client
+            .get("http://music.invalid/")
+            .send()
+            .await
+            .unwrap_err()
+            .into();
+        assert!(matches!(e, StorageBackendError::DnsError(_)));
+
+        let certs = generate_certs();
+        let (port, handle) = setup_tls_server(&certs).await;
+        let e: StorageBackendError = client
+            .get(format!("https://localhost:{port}/"))
+            .send()
+            .await
+            .unwrap_err()
+            .into();
+        assert!(matches!(e, StorageBackendError::TlsError(_)));
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_headers_user_agent_and_proxy() {
+        let (port, handle) = setup_server_with(|req: Request<Body>| async move {
+            let header = |name: &str| {
+                req.headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            let text = format!(
+                "{} {} {}",
+                req.uri(),
+                header("user-agent"),
+                header("x-ease-token")
+            );
+            Response::new(Body::from(text))
+        })
+        .await;
+
+        let network = HttpNetworkConfig {
+            proxy: Some(format!("http://127.0.0.1:{port}")),
+            headers: vec![("X-Ease-Token".to_string(), "abc".to_string())],
+            user_agent: Some("EaseMusic/1.0".to_string()),
+            ..Default::default()
+        };
+        let text = build_client(network)
+            .get("http://music.invalid/a.mp3")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(text, "http://music.invalid/a.mp3 EaseMusic/1.0 abc");
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        let (port, handle) = setup_server_with(|req: Request<Body>| async move {
+            if req.uri().path() == "/slow-headers" {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                return Response::new(Body::from("late"));
+            }
+            let (mut tx, body) = Body::channel();
+            tokio::spawn(async move {
+                tx.send_data("first".into()).await.unwrap();
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                drop(tx);
+            });
+            Response::new(body)
+        })
+        .await;
+        let shared = SharedHttpClient::new(
+            Duration::from_secs(10),
+            Default::default(),
+            HttpNetworkConfig {
+                read_timeout: Some(Duration::from_millis(300)),
+                ..Default::default()
+            },
+        );
+
+        let res = send_with_retry(
+            &RetryPolicy::none(),
+            &shared,
+            reqwest::Method::GET,
+            format!("http://127.0.0.1:{port}/slow-headers")
+                .parse()
+                .unwrap(),
+            Default::default(),
+            None,
+        )
+        .await;
+        assert!(matches!(
+            res,
+            Err(StorageBackendError::TokioIO(e)) if e.kind() == std::io::ErrorKind::TimedOut
+        ));
+
+        let resp = send_with_retry(
+            &RetryPolicy::none(),
+            &shared,
+            reqwest::Method::GET,
+            format!("http://127.0.0.1:{port}/slow-body")
+                .parse()
+                .unwrap(),
+            Default::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        let file = StreamFile::new(resp, 0).with_read_timeout(shared.read_timeout());
+        assert!(file.bytes().await.is_err());
+        handle.abort();
+    }
+
+    #[test]
+    fn test_invalid_network_config() {
+        let build = |network: HttpNetworkConfig| {
+            SharedHttpClient::new(Duration::from_secs(10), Default::default(), network).get()
+        };
+        let invalid = [
+            HttpNetworkConfig {
+                headers: vec![("Bad Header".to_string(), "v".to_string())],
+                ..Default::default()
+            },
+            HttpNetworkConfig {
+                pinned_cert_sha256: Some("abcd".to_string()),
+                ..Default::default()
+            },
+            HttpNetworkConfig {
+                root_ca_pem: Some("not a pem".to_string()),
+                ..Default::default()
+            },
+            HttpNetworkConfig {
+                proxy: Some("::not a url".to_string()),
+                ..Default::default()
+            },
+        ];
+        for network in invalid {
+            assert!(matches!(
+                build(network),
+                Err(StorageBackendError::InvalidNetworkConfig(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,518 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use ease_client_tokio::tokio_runtime;
+use futures_util::future::BoxFuture;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+
+use crate::changes::{snapshot_changes, StorageChanges};
+use crate::http_client::{
+    with_read_timeout, HttpNetworkConfig, HttpPoolConfig, ResponseExt, SharedHttpClient,
+};
+use crate::{
+    Entry, StorageBackend, StorageBackendError, StorageBackendResult, StreamFile, UploadFile,
+};
+
+pub struct BuildHttpIndexArg {
+    pub addr: String,
+    pub username: String,
+    pub password: String,
+    pub is_anonymous: bool,
+    pub connect_timeout: Duration,
+    pub pool: HttpPoolConfig,
+    pub network: HttpNetworkConfig,
+}
+
+/// Read-only backend over the HTML directory listings of a plain static web server.
+pub struct HttpIndexBackend {
+    addr: String,
+    username: String,
+    password: String,
+    is_anonymous: bool,
+    client: SharedHttpClient,
+}
+
+fn find_from(haystack: &str, needle: &str, from: usize) -> Option<usize> {
+    haystack.get(from..)?.find(needle).map(|i| i + from)
+}
+
+fn decode_html_entities(s: &str) -> String {
+    s.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+fn strip_tags(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut in_tag = false;
+    for c in s.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                ret.push(' ');
+            }
+            _ if !in_tag => ret.push(c),
+            _ => {}
+        }
+    }
+    ret
+}
+
+fn parse_attr(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    loop {
+        let i = find_from(&lower, name, from)?;
+        from = i + name.len();
+        let preceded_by_space = lower[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_whitespace());
+        let rest = lower[from..].trim_start();
+        if !preceded_by_space || !rest.starts_with('=') {
+            continue;
+        }
+
+        let value_start = tag.len() - rest.len() + 1;
+        let value = tag[value_start..].trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &value[1..];
+                &value[..value.find(quote).unwrap_or(value.len())]
+            }
+            _ => {
+                let end = value
+                    .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                    .unwrap_or(value.len());
+                &value[..end]
+            }
+        };
+        return Some(decode_html_entities(value));
+    }
+}
+
+/// Sizes are shown as exact bytes (nginx, Caddy's `data-size`) or rounded with a
+/// binary unit suffix (Apache, nginx with `autoindex_exact_size off`).
+fn parse_size_token(token: &str) -> Option<usize> {
+    if token.chars().all(|c| c.is_ascii_digit()) {
+        return token.parse().ok();
+    }
+    let unit = token.chars().next_back()?.to_ascii_uppercase();
+    let exp = match unit {
+        'K' => 1,
+        'M' => 2,
+        'G' => 3,
+        'T' => 4,
+        _ => return None,
+    };
+    let num = &token[..token.len() - 1];
+    if num.is_empty() || !num.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    let num: f64 = num.parse().ok()?;
+    Some((num * 1024f64.powi(exp)) as usize)
+}
+
+fn parse_size(segment: &str) -> Option<usize> {
+    if let Some(value) = parse_attr(segment, "data-size") {
+        return value.parse().ok();
+    }
+    let text = decode_html_entities(&strip_tags(segment));
+    text.split_whitespace().rev().find_map(parse_size_token)
+}
+
+/// Extracts the direct children of `dir_url` from an autoindex page. Links that
+/// resolve elsewhere (parent, sort options, breadcrumbs, external sites) are dropped.
+fn parse_index_html(html: &str, dir_url: &Url, dir: &str) -> Vec<Entry> {
+    let lower = html.to_ascii_lowercase();
+    let dir = dir.trim_end_matches('/');
+    let mut names: HashSet<String> = Default::default();
+    let mut ret: Vec<Entry> = Default::default();
+
+    let mut from = 0;
+    while let Some(start) = find_from(&lower, "<a", from) {
+        from = start + 2;
+        if !lower[from..].starts_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let Some(tag_end) = find_from(&lower, ">", from) else {
+            break;
+        };
+        let tag = &html[start..tag_end];
+        from = tag_end + 1;
+
+        let Some(href) = parse_attr(tag, "href") else {
+            continue;
+        };
+        let Ok(url) = dir_url.join(&href) else {
+            continue;
+        };
+        if url.origin() != dir_url.origin() || url.query().is_some() {
+            continue;
+        }
+        let Some(rest) = url.path().strip_prefix(dir_url.path()) else {
+            continue;
+        };
+        let is_dir = rest.ends_with('/');
+        let rest = rest.trim_end_matches('/');
+        if rest.is_empty() || rest.contains('/') {
+            continue;
+        }
+        let name = match urlencoding::decode(rest) {
+            Ok(name) => name.to_string(),
+            Err(_) => continue,
+        };
+        if !names.insert(name.clone()) {
+            continue;
+        }
+
+        let size = if is_dir {
+            None
+        } else {
+            let segment_start = find_from(&lower, "</a>", from).unwrap_or(from);
+            let segment_end = ["<a ", "</tr>", "</li>"]
+                .iter()
+                .filter_map(|v| find_from(&lower, v, segment_start + 1))
+                .min()
+                .unwrap_or(html.len());
+            parse_size(&html[segment_start..segment_end])
+        };
+
+        ret.push(Entry {
+            path: format!("{}/{}", dir, name),
+            name,
+            size,
+            is_dir,
+            ..Default::default()
+        });
+    }
+    ret
+}
+
+impl HttpIndexBackend {
+    pub fn new(arg: BuildHttpIndexArg) -> Self {
+        Self {
+            addr: arg.addr,
+            username: arg.username,
+            password: arg.password,
+            is_anonymous: arg.is_anonymous,
+            client: SharedHttpClient::new(arg.connect_timeout, arg.pool, arg.network),
+        }
+    }
+
+    fn get_url<const IS_DIR: bool>(&self, p: &str) -> StorageBackendResult<Url> {
+        let mut url = reqwest::Url::parse(&self.addr)
+            .map_err(|e| StorageBackendError::UrlParseError(e.to_string()))?;
+        let base = url.path();
+        let mut p = base.trim_end_matches('/').to_string() + "/" + p.trim_start_matches('/');
+        if IS_DIR && !p.ends_with('/') {
+            p += "/";
+        }
+        url.set_path(&p);
+        Ok(url)
+    }
+
+    fn build_client(&self) -> StorageBackendResult<reqwest::Client> {
+        self.client.get()
+    }
+
+    fn build_request(&self, url: Url) -> StorageBackendResult<reqwest::RequestBuilder> {
+        let client = self.build_client()?;
+        let mut req = client.get(url);
+        if !self.is_anonymous {
+            req = req.basic_auth(&self.username, Some(&self.password));
+        }
+        Ok(req)
+    }
+
+    async fn list_impl(&self, dir: String) -> StorageBackendResult<Vec<Entry>> {
+        let url = self.get_url::<true>(&dir)?;
+        let req = self.build_request(url)?;
+        let read_timeout = self.client.read_timeout();
+
+        let (dir_url, html) = tokio_runtime()
+            .spawn(async move {
+                let resp = with_read_timeout(read_timeout, req.send())
+                    .await??
+                    .check_status()?;
+                // Redirects such as `/music` -> `/music/` change the base for relative links.
+                let dir_url = resp.url().clone();
+                let html = with_read_timeout(read_timeout, resp.text()).await??;
+                Ok::<_, StorageBackendError>((dir_url, html))
+            })
+            .await??;
+
+        let mut ret = parse_index_html(&html, &dir_url, &dir);
+        ret.sort_by(|lhs, rhs| {
+            if lhs.is_dir ^ rhs.is_dir {
+                if lhs.is_dir {
+                    return Ordering::Less;
+                } else {
+                    return Ordering::Greater;
+                }
+            }
+            if lhs.path < rhs.path {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        });
+        Ok(ret)
+    }
+
+    async fn get_impl(&self, p: String, byte_offset: u64) -> StorageBackendResult<StreamFile> {
+        let url = self.get_url::<false>(&p)?;
+        let req = self.build_request(url)?.header(
+            reqwest::header::RANGE,
+            HeaderValue::from_str(format!("bytes={byte_offset}-").as_str()).unwrap(),
+        );
+
+        let read_timeout = self.client.read_timeout();
+        let resp = tokio_runtime()
+            .spawn(with_read_timeout(read_timeout, req.send()))
+            .await???;
+        let byte_offset = if resp.headers().get(reqwest::header::CONTENT_RANGE).is_some() {
+            0
+        } else {
+            byte_offset
+        };
+
+        let res = resp
+            .check_status()
+            .map(|resp| StreamFile::new(resp, byte_offset).with_read_timeout(read_timeout))?;
+        Ok(res)
+    }
+}
+
+impl StorageBackend for HttpIndexBackend {
+    fn list(&self, dir: String) -> BoxFuture<StorageBackendResult<Vec<Entry>>> {
+        Box::pin(self.list_impl(dir))
+    }
+
+    fn get(&self, p: String, byte_offset: u64) -> BoxFuture<StorageBackendResult<StreamFile>> {
+        Box::pin(self.get_impl(p, byte_offset))
+    }
+
+    fn put(&self, _p: String, _file: UploadFile) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn mkdir(&self, _dir: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn delete(&self, _p: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn rename(&self, _from: String, _to: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn changes_since(
+        &self,
+        dir: String,
+        cursor: Option<String>,
+    ) -> BoxFuture<StorageBackendResult<StorageChanges>> {
+        Box::pin(async move { snapshot_changes(self, &dir, cursor.as_deref()).await })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+    use hyper::{Body, Request, Response, StatusCode};
+    use reqwest::Url;
+    use tokio::task::JoinHandle;
+
+    use crate::{StorageBackend, StorageBackendError, UploadFile};
+
+    use super::{parse_index_html, BuildHttpIndexArg, HttpIndexBackend};
+
+    const SONG: &[u8] = b"0123456789";
+
+    struct SetupServerRes {
+        addr: String,
+        handle: JoinHandle<()>,
+    }
+    impl Drop for SetupServerRes {
+        fn drop(&mut self) {
+            self.handle.abort();
+        }
+    }
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(format!("test/assets/http_index/{name}.html")).unwrap()
+    }
+
+    fn assert_music_listing(html: &str, with_size: bool) {
+        let dir_url = Url::parse("http://localhost/music/").unwrap();
+        let list = parse_index_html(html, &dir_url, "/music");
+        let album = list.iter().find(|v| v.name == "Album A").unwrap();
+        assert_eq!(album.path, "/music/Album A");
+        assert!(album.is_dir);
+        assert_eq!(album.size, None);
+
+        let song = list.iter().find(|v| v.name == "song #1.mp3").unwrap();
+        assert_eq!(song.path, "/music/song #1.mp3");
+        assert!(!song.is_dir);
+
+        let cover = list.iter().find(|v| v.name == "cover.jpg").unwrap();
+        if with_size {
+            assert!(song.size.unwrap().abs_diff(3145728) < 1024);
+            assert!(cover.size.unwrap().abs_diff(51234) < 1024);
+        } else {
+            assert_eq!(song.size, None);
+            assert_eq!(cover.size, None);
+        }
+    }
+
+    #[test]
+    fn test_parse_nginx() {
+        let html = fixture("nginx");
+        assert_music_listing(&html, true);
+
+        let dir_url = Url::parse("http://localhost/music/").unwrap();
+        let list = parse_index_html(&html, &dir_url, "/music");
+        assert_eq!(list.len(), 4);
+        let long = list.iter().find(|v| v.name.ends_with(".flac")).unwrap();
+        assert_eq!(
+            long.name,
+            "a very long file name that nginx will truncate.flac"
+        );
+        assert_eq!(long.size, Some(1024));
+    }
+
+    #[test]
+    fn test_parse_apache() {
+        let html = fixture("apache");
+        assert_music_listing(&html, true);
+
+        let dir_url = Url::parse("http://localhost/music/").unwrap();
+        assert_eq!(parse_index_html(&html, &dir_url, "/music").len(), 3);
+    }
+
+    #[test]
+    fn test_parse_caddy() {
+        let html = fixture("caddy");
+        assert_music_listing(&html, true);
+
+        let dir_url = Url::parse("http://localhost/music/").unwrap();
+        let list = parse_index_html(&html, &dir_url, "/music");
+        assert_eq!(list.len(), 3);
+        let song = list.iter().find(|v| v.name == "song #1.mp3").unwrap();
+        assert_eq!(song.size, Some(3145728));
+    }
+
+    #[test]
+    fn test_parse_python() {
+        let html = fixture("python");
+        assert_music_listing(&html, false);
+
+        let dir_url = Url::parse("http://localhost/music/").unwrap();
+        assert_eq!(parse_index_html(&html, &dir_url, "/music").len(), 3);
+    }
+
+    async fn setup_server() -> SetupServerRes {
+        let make_svc = hyper::service::make_service_fn(|_conn| async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(
+                move |req: Request<Body>| async move {
+                    let res = match req.uri().path() {
+                        "/music" => Response::builder()
+                            .status(StatusCode::MOVED_PERMANENTLY)
+                            .header(hyper::header::LOCATION, "/music/")
+                            .body(Body::empty()),
+                        "/music/" => Response::builder()
+                            .header(hyper::header::CONTENT_TYPE, "text/html")
+                            .body(Body::from(fixture("nginx"))),
+                        "/music/song%20%231.mp3" => {
+                            let start: usize = req
+                                .headers()
+                                .get(hyper::header::RANGE)
+                                .and_then(|v| v.to_str().ok())
+                                .and_then(|v| v.strip_prefix("bytes="))
+                                .and_then(|v| v.trim_end_matches('-').parse().ok())
+                                .unwrap_or_default();
+                            Response::builder()
+                                .status(StatusCode::PARTIAL_CONTENT)
+                                .header(
+                                    hyper::header::CONTENT_RANGE,
+                                    format!("bytes {}-{}/{}", start, SONG.len() - 1, SONG.len()),
+                                )
+                                .header(hyper::header::CONTENT_LENGTH, SONG.len() - start)
+                                .body(Body::from(&SONG[start..]))
+                        }
+                        _ => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty()),
+                    };
+                    Ok::<_, Infallible>(res.unwrap())
+                },
+            ))
+        });
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let server = hyper::Server::bind(&addr).serve(make_svc);
+        let addr = format!("http://{}", server.local_addr());
+        let handle = tokio::spawn(async move {
+            let _ = server.await;
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        SetupServerRes { addr, handle }
+    }
+
+    fn build_backend(server: &SetupServerRes) -> HttpIndexBackend {
+        HttpIndexBackend::new(BuildHttpIndexArg {
+            addr: server.addr.clone(),
+            username: Default::default(),
+            password: Default::default(),
+            is_anonymous: true,
+            connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_list_and_get() {
+        let server = setup_server().await;
+        let backend = build_backend(&server);
+
+        let list = backend.list("/music".to_string()).await.unwrap();
+        assert_eq!(list.len(), 4);
+        assert_eq!(list[0].path, "/music/Album A");
+        assert!(list[0].is_dir);
+
+        let file = backend
+            .get("/music/song #1.mp3".to_string(), 4)
+            .await
+            .unwrap();
+        assert_eq!(file.size(), Some(6));
+        let bytes = file.bytes().await.unwrap();
+        assert_eq!(bytes.as_ref(), b"456789");
+
+        let res = backend.get("/music/missing.mp3".to_string(), 0).await;
+        assert!(res.is_err_and(|e| e.is_not_found()));
+    }
+
+    #[tokio::test]
+    async fn test_read_only() {
+        let server = setup_server().await;
+        let backend = build_backend(&server);
+
+        let file = UploadFile::new_from_bytes(bytes::Bytes::from_static(b"ease"));
+        let res = backend.put("/music/a.lrc".to_string(), file).await;
+        assert!(matches!(res, Err(StorageBackendError::Unsupported)));
+        let res = backend.mkdir("/music/folder".to_string()).await;
+        assert!(matches!(res, Err(StorageBackendError::Unsupported)));
+    }
+}
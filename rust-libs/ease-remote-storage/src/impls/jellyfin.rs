@@ -0,0 +1,546 @@
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use ease_client_tokio::tokio_runtime;
+use futures_util::future::BoxFuture;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+
+use crate::changes::{snapshot_changes, StorageChanges};
+use crate::http_client::{
+    with_read_timeout, HttpNetworkConfig, HttpPoolConfig, ResponseExt, SharedHttpClient,
+};
+use crate::{
+    Entry, StorageBackend, StorageBackendError, StorageBackendResult, StreamFile, UploadFile,
+};
+
+const JELLYFIN_CLIENT_NAME: &str = "Ease Music Player";
+const JELLYFIN_DEVICE_NAME: &str = "Ease";
+const JELLYFIN_DEVICE_ID: &str = "ease-music-player";
+const JELLYFIN_CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub struct BuildJellyfinArg {
+    pub addr: String,
+    /// Obtained once from [`JellyfinBackend::request_access_token`].
+    pub access_token: String,
+    pub connect_timeout: Duration,
+    pub pool: HttpPoolConfig,
+    pub network: HttpNetworkConfig,
+}
+
+/// Browses a Jellyfin music library as `/{artist id}/{album id}/{audio id}.{container}`.
+pub struct JellyfinBackend {
+    addr: String,
+    access_token: String,
+    client: SharedHttpClient,
+    user_id: tokio::sync::RwLock<Option<String>>,
+}
+
+mod jellyfin_types {
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "PascalCase")]
+    pub struct AuthenticateResp {
+        pub access_token: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "PascalCase")]
+    pub struct User {
+        pub id: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "PascalCase")]
+    pub struct ItemsResp {
+        #[serde(default)]
+        pub items: Vec<Item>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "PascalCase")]
+    pub struct Item {
+        pub id: String,
+        pub name: String,
+        #[serde(rename = "Type")]
+        pub typ: String,
+        pub container: Option<String>,
+        #[serde(default)]
+        pub media_sources: Vec<MediaSource>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "PascalCase")]
+    pub struct MediaSource {
+        pub size: Option<u64>,
+    }
+}
+
+fn build_authorization(access_token: Option<&str>) -> HeaderValue {
+    let mut value = format!(
+        r#"MediaBrowser Client="{JELLYFIN_CLIENT_NAME}", Device="{JELLYFIN_DEVICE_NAME}", DeviceId="{JELLYFIN_DEVICE_ID}", Version="{JELLYFIN_CLIENT_VERSION}""#
+    );
+    if let Some(access_token) = access_token {
+        value += format!(r#", Token="{access_token}""#).as_str();
+    }
+    HeaderValue::from_str(&value).unwrap()
+}
+
+fn build_url(addr: &str, p: &str) -> StorageBackendResult<Url> {
+    let url = addr.trim_end_matches('/').to_string() + p;
+    Url::parse(&url).map_err(|e| StorageBackendError::UrlParseError(e.to_string()))
+}
+
+fn build_entry(dir: &str, item: jellyfin_types::Item) -> Option<Entry> {
+    match item.typ.as_str() {
+        "MusicArtist" | "MusicAlbum" | "Folder" | "CollectionFolder" => Some(Entry {
+            name: item.name,
+            path: format!("{}/{}", dir, item.id),
+            is_dir: true,
+            ..Default::default()
+        }),
+        "Audio" => {
+            let container = item.container.unwrap_or_default();
+            // Multi-version items report the container as a comma separated list.
+            let ext = container.split(',').next().unwrap_or_default();
+            Some(Entry {
+                name: format!("{}.{}", item.name, ext),
+                path: format!("{}/{}.{}", dir, item.id, ext),
+                size: item
+                    .media_sources
+                    .first()
+                    .and_then(|v| v.size)
+                    .map(|v| v as usize),
+                is_dir: false,
+                ..Default::default()
+            })
+        }
+        _ => None,
+    }
+}
+
+impl JellyfinBackend {
+    pub fn new(arg: BuildJellyfinArg) -> Self {
+        Self {
+            addr: arg.addr,
+            access_token: arg.access_token,
+            client: SharedHttpClient::new(arg.connect_timeout, arg.pool, arg.network),
+            user_id: Default::default(),
+        }
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: Url) -> StorageBackendResult<T> {
+        let client = self.client.get()?;
+        let authorization = build_authorization(Some(&self.access_token));
+        let read_timeout = self.client.read_timeout();
+
+        let text = tokio_runtime()
+            .spawn(async move {
+                let req = client
+                    .get(url)
+                    .header(reqwest::header::AUTHORIZATION, authorization);
+                let resp = with_read_timeout(read_timeout, req.send())
+                    .await??
+                    .check_status()?;
+                Ok::<_, StorageBackendError>(with_read_timeout(read_timeout, resp.text()).await??)
+            })
+            .await??;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    async fn ensure_user_id(&self) -> StorageBackendResult<String> {
+        {
+            let r = self.user_id.read().await;
+            if let Some(user_id) = r.as_ref() {
+                return Ok(user_id.clone());
+            }
+        }
+
+        let mut w = self.user_id.write().await;
+        if let Some(user_id) = w.as_ref() {
+            return Ok(user_id.clone());
+        }
+        let url = build_url(&self.addr, "/Users/Me")?;
+        let user: jellyfin_types::User = self.get_json(url).await?;
+        *w = Some(user.id.clone());
+        Ok(user.id)
+    }
+
+    async fn list_impl(&self, dir: String) -> StorageBackendResult<Vec<Entry>> {
+        let user_id = self.ensure_user_id().await?;
+        let dir = dir.trim_end_matches('/').to_string();
+        let segments: Vec<&str> = dir.split('/').filter(|v| !v.is_empty()).collect();
+
+        let mut url = match segments.as_slice() {
+            [] => build_url(&self.addr, "/Artists/AlbumArtists")?,
+            [artist_id] => {
+                let mut url = build_url(&self.addr, "/Items")?;
+                url.query_pairs_mut()
+                    .append_pair("IncludeItemTypes", "MusicAlbum")
+                    .append_pair("Recursive", "true")
+                    .append_pair("AlbumArtistIds", artist_id);
+                url
+            }
+            [.., parent_id] => {
+                let mut url = build_url(&self.addr, "/Items")?;
+                url.query_pairs_mut()
+                    .append_pair("ParentId", parent_id)
+                    .append_pair("SortBy", "ParentIndexNumber,IndexNumber,SortName")
+                    .append_pair("Fields", "MediaSources");
+                url
+            }
+        };
+        url.query_pairs_mut().append_pair("userId", &user_id);
+        if segments.len() < 2 {
+            url.query_pairs_mut().append_pair("SortBy", "SortName");
+        }
+
+        let resp: jellyfin_types::ItemsResp = self.get_json(url).await?;
+        // Server order is kept, albums list their tracks by disc and index number.
+        Ok(resp
+            .items
+            .into_iter()
+            .filter_map(|item| build_entry(&dir, item))
+            .collect())
+    }
+
+    async fn get_impl(&self, p: String, byte_offset: u64) -> StorageBackendResult<StreamFile> {
+        let last = p.split('/').next_back().unwrap_or_default();
+        let (id, ext) = last.rsplit_once('.').unwrap_or((last, ""));
+        // Lyric sidecars are probed by swapping the extension, they never exist here.
+        if ext == "lrc" {
+            return Err(std::io::Error::from(ErrorKind::NotFound).into());
+        }
+
+        let mut url = build_url(&self.addr, format!("/Audio/{id}/stream").as_str())?;
+        url.query_pairs_mut().append_pair("static", "true");
+        let client = self.client.get()?;
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            build_authorization(Some(&self.access_token)),
+        );
+        headers.insert(
+            reqwest::header::RANGE,
+            HeaderValue::from_str(format!("bytes={byte_offset}-").as_str()).unwrap(),
+        );
+
+        let read_timeout = self.client.read_timeout();
+        let resp = tokio_runtime()
+            .spawn(with_read_timeout(
+                read_timeout,
+                client.get(url).headers(headers).send(),
+            ))
+            .await???;
+        let byte_offset = if resp.headers().get(reqwest::header::CONTENT_RANGE).is_some() {
+            0
+        } else {
+            byte_offset
+        };
+
+        let res = resp
+            .check_status()
+            .map(|resp| StreamFile::new(resp, byte_offset).with_read_timeout(read_timeout))?;
+        Ok(res)
+    }
+
+    /// Goes through the same proxy, TLS and timeout settings as the backend built from
+    /// the token afterwards.
+    pub async fn request_access_token(
+        addr: String,
+        username: String,
+        password: String,
+        connect_timeout: Duration,
+        network: HttpNetworkConfig,
+    ) -> StorageBackendResult<String> {
+        let url = build_url(&addr, "/Users/AuthenticateByName")?;
+        let body = serde_json::json!({
+            "Username": username,
+            "Pw": password,
+        })
+        .to_string();
+
+        let client = SharedHttpClient::new(connect_timeout, Default::default(), network);
+        let read_timeout = client.read_timeout();
+        let client = client.get()?;
+
+        let text = tokio_runtime()
+            .spawn(async move {
+                let req = client
+                    .post(url)
+                    .header(reqwest::header::AUTHORIZATION, build_authorization(None))
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body);
+                let resp = with_read_timeout(read_timeout, req.send())
+                    .await??
+                    .check_status()?;
+                Ok::<_, StorageBackendError>(with_read_timeout(read_timeout, resp.text()).await??)
+            })
+            .await??;
+        let value = serde_json::from_str::<jellyfin_types::AuthenticateResp>(&text)?;
+        Ok(value.access_token)
+    }
+}
+
+impl StorageBackend for JellyfinBackend {
+    fn list(&self, dir: String) -> BoxFuture<StorageBackendResult<Vec<Entry>>> {
+        Box::pin(self.list_impl(dir))
+    }
+
+    fn get(&self, p: String, byte_offset: u64) -> BoxFuture<StorageBackendResult<StreamFile>> {
+        Box::pin(self.get_impl(p, byte_offset))
+    }
+
+    fn put(&self, _p: String, _file: UploadFile) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn mkdir(&self, _dir: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn delete(&self, _p: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn rename(&self, _from: String, _to: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn changes_since(
+        &self,
+        dir: String,
+        cursor: Option<String>,
+    ) -> BoxFuture<StorageBackendResult<StorageChanges>> {
+        Box::pin(async move { snapshot_changes(self, &dir, cursor.as_deref()).await })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, convert::Infallible, net::SocketAddr, time::Duration};
+
+    use hyper::{Body, Request, Response, StatusCode};
+    use tokio::task::JoinHandle;
+
+    use crate::StorageBackend;
+
+    use super::{BuildJellyfinArg, JellyfinBackend};
+
+    const USERNAME: &str = "ease";
+    const PASSWORD: &str = "sesame";
+    const ACCESS_TOKEN: &str = "4b5e0a7d2f1c4e0b9a3d6c8e1f2a3b4c";
+    const USER_ID: &str = "d2b7c9e0a1f34b5c8d6e7f8091a2b3c4";
+    const SONG: &[u8] = b"0123456789";
+
+    struct SetupServerRes {
+        addr: String,
+        handle: JoinHandle<()>,
+    }
+    impl Drop for SetupServerRes {
+        fn drop(&mut self) {
+            self.handle.abort();
+        }
+    }
+
+    fn json(body: &str) -> Response<Body> {
+        Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn status(code: StatusCode) -> Response<Body> {
+        Response::builder()
+            .status(code)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn handle(req: Request<Body>) -> Response<Body> {
+        let authorization = req
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if !authorization.starts_with("MediaBrowser ") || !authorization.contains("DeviceId=") {
+            return status(StatusCode::BAD_REQUEST);
+        }
+
+        if req.uri().path() == "/Users/AuthenticateByName" {
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            if body["Username"] != USERNAME || body["Pw"] != PASSWORD {
+                return status(StatusCode::UNAUTHORIZED);
+            }
+            return json(&format!(
+                r#"{{"User":{{"Name":"{USERNAME}","Id":"{USER_ID}"}},"AccessToken":"{ACCESS_TOKEN}","ServerId":"s1"}}"#
+            ));
+        }
+        if !authorization.contains(&format!(r#"Token="{ACCESS_TOKEN}""#)) {
+            return status(StatusCode::UNAUTHORIZED);
+        }
+
+        let query: HashMap<String, String> = req
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|kv| kv.split_once('='))
+            .map(|(k, v)| (k.to_string(), urlencoding::decode(v).unwrap().to_string()))
+            .collect();
+        let path = req.uri().path();
+        if path == "/Items" || path == "/Artists/AlbumArtists" {
+            assert_eq!(query.get("userId").map(|v| v.as_str()), Some(USER_ID));
+        }
+
+        match path {
+            "/Users/Me" => json(&format!(r#"{{"Name":"{USERNAME}","Id":"{USER_ID}"}}"#)),
+            "/Artists/AlbumArtists" => json(
+                r#"{"Items":[{"Name":"ABBA","Id":"ar1","Type":"MusicArtist"}],"TotalRecordCount":1,"StartIndex":0}"#,
+            ),
+            "/Items" if query.get("AlbumArtistIds").map(|v| v.as_str()) == Some("ar1") => json(
+                r#"{"Items":[{"Name":"Arrival","Id":"al1","Type":"MusicAlbum"}],"TotalRecordCount":1,"StartIndex":0}"#,
+            ),
+            "/Items" if query.get("ParentId").map(|v| v.as_str()) == Some("al1") => json(
+                r#"{"Items":[
+                    {"Name":"Dancing Queen","Id":"a1","Type":"Audio","Container":"flac","IndexNumber":1,
+                     "MediaSources":[{"Id":"a1","Size":10,"Container":"flac"}]},
+                    {"Name":"Arrival Booklet","Id":"b1","Type":"Book"}
+                ],"TotalRecordCount":2,"StartIndex":0}"#,
+            ),
+            "/Audio/a1/stream" => {
+                assert_eq!(query.get("static").map(|v| v.as_str()), Some("true"));
+                let start: usize = req
+                    .headers()
+                    .get(hyper::header::RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.strip_prefix("bytes="))
+                    .and_then(|v| v.trim_end_matches('-').parse().ok())
+                    .unwrap_or_default();
+                Response::builder()
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(hyper::header::CONTENT_TYPE, "audio/flac")
+                    .header(
+                        hyper::header::CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", start, SONG.len() - 1, SONG.len()),
+                    )
+                    .header(hyper::header::CONTENT_LENGTH, SONG.len() - start)
+                    .body(Body::from(&SONG[start..]))
+                    .unwrap()
+            }
+            _ => status(StatusCode::NOT_FOUND),
+        }
+    }
+
+    async fn setup_server() -> SetupServerRes {
+        let make_svc = hyper::service::make_service_fn(|_conn| async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(
+                |req: Request<Body>| async move { Ok::<_, Infallible>(handle(req).await) },
+            ))
+        });
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let server = hyper::Server::bind(&addr).serve(make_svc);
+        let addr = format!("http://{}", server.local_addr());
+        let handle = tokio::spawn(async move {
+            let _ = server.await;
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        SetupServerRes { addr, handle }
+    }
+
+    async fn build_backend(server: &SetupServerRes) -> JellyfinBackend {
+        let access_token = JellyfinBackend::request_access_token(
+            server.addr.clone(),
+            USERNAME.to_string(),
+            PASSWORD.to_string(),
+            Duration::from_secs(10),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(access_token, ACCESS_TOKEN);
+
+        JellyfinBackend::new(BuildJellyfinArg {
+            addr: server.addr.clone(),
+            access_token,
+            connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_list() {
+        let server = setup_server().await;
+        let backend = build_backend(&server).await;
+
+        let list = backend.list("/".to_string()).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, "ABBA");
+        assert_eq!(list[0].path, "/ar1");
+        assert!(list[0].is_dir);
+
+        let list = backend.list("/ar1".to_string()).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, "Arrival");
+        assert_eq!(list[0].path, "/ar1/al1");
+        assert!(list[0].is_dir);
+
+        let list = backend.list("/ar1/al1".to_string()).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, "Dancing Queen.flac");
+        assert_eq!(list[0].path, "/ar1/al1/a1.flac");
+        assert_eq!(list[0].size, Some(10));
+        assert!(!list[0].is_dir);
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let server = setup_server().await;
+        let backend = build_backend(&server).await;
+
+        let file = backend
+            .get("/ar1/al1/a1.flac".to_string(), 3)
+            .await
+            .unwrap();
+        assert_eq!(file.size(), Some(7));
+        assert_eq!(file.content_type(), Some("audio/flac"));
+        let bytes = file.bytes().await.unwrap();
+        assert_eq!(bytes.as_ref(), b"3456789");
+
+        let res = backend.get("/ar1/al1/a1.lrc".to_string(), 0).await;
+        assert!(res.is_err_and(|e| e.is_not_found()));
+        let res = backend.get("/ar1/al1/missing.flac".to_string(), 0).await;
+        assert!(res.is_err_and(|e| e.is_not_found()));
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized() {
+        let server = setup_server().await;
+
+        let res = JellyfinBackend::request_access_token(
+            server.addr.clone(),
+            USERNAME.to_string(),
+            "wrong".to_string(),
+            Duration::from_secs(10),
+            Default::default(),
+        )
+        .await;
+        assert!(res.is_err_and(|e| e.is_unauthorized()));
+
+        let backend = JellyfinBackend::new(BuildJellyfinArg {
+            addr: server.addr.clone(),
+            access_token: "expired".to_string(),
+            connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+        });
+        let res = backend.list("/".to_string()).await;
+        assert!(res.is_err_and(|e| e.is_unauthorized()));
+    }
+}
@@ -1,15 +1,32 @@
-use std::io::SeekFrom;
+use std::time::{Duration, UNIX_EPOCH};
 
 use ease_client_tokio::tokio_runtime;
 use futures_util::future::BoxFuture;
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::io::AsyncWriteExt;
 
-use crate::{Entry, StorageBackend, StorageBackendError, StorageBackendResult, StreamFile};
+use crate::changes::{snapshot_changes, StorageChanges};
+use crate::{
+    Entry, StorageBackend, StorageBackendError, StorageBackendResult, StreamFile, UploadFile,
+};
 
 pub struct LocalBackend;
 
 static ANDROID_PREFIX_PATH: &str = "/storage/emulated/0";
 
+fn to_local_path(p: String) -> String {
+    if std::env::consts::OS == "windows" {
+        p.replace('/', "\\")
+    } else if std::env::consts::OS == "android" {
+        ANDROID_PREFIX_PATH.to_string() + p.as_str()
+    } else {
+        p
+    }
+}
+
+fn modified_time(metadata: &std::fs::Metadata) -> Option<Duration> {
+    metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()
+}
+
 impl Default for LocalBackend {
     fn default() -> Self {
         Self::new()
@@ -22,13 +39,7 @@ impl LocalBackend {
     }
 
     async fn list_impl(&self, dir: String) -> StorageBackendResult<Vec<Entry>> {
-        let dir = if std::env::consts::OS == "windows" {
-            dir.replace('/', "\\")
-        } else if std::env::consts::OS == "android" {
-            ANDROID_PREFIX_PATH.to_string() + dir.as_str()
-        } else {
-            dir.to_string()
-        };
+        let dir = to_local_path(dir);
 
         let mut ret = tokio_runtime()
             .spawn(async move {
@@ -54,6 +65,8 @@ impl LocalBackend {
                         path: path.replace('\\', "/"),
                         size: Some(metadata.len() as usize),
                         is_dir: metadata.is_dir(),
+                        modified_time: modified_time(&metadata),
+                        ..Default::default()
                     });
                 }
 
@@ -65,32 +78,108 @@ impl LocalBackend {
         Ok(ret)
     }
 
-    async fn get_impl(&self, p: String, byte_offset: u64) -> StorageBackendResult<StreamFile> {
-        let p = if std::env::consts::OS == "windows" {
-            p.replace('/', "\\")
-        } else if std::env::consts::OS == "android" {
-            ANDROID_PREFIX_PATH.to_string() + p.as_str()
-        } else {
-            p.to_string()
-        };
+    async fn get_impl(
+        &self,
+        p: String,
+        byte_offset: u64,
+        end: Option<u64>,
+    ) -> StorageBackendResult<StreamFile> {
+        let p = to_local_path(p);
 
-        let buf = {
+        let (file, total) = {
             let p = p.clone();
             tokio_runtime()
                 .spawn(async move {
-                    let mut buf: Vec<u8> = Default::default();
                     let path = tokio::fs::canonicalize(&p).await?;
-                    let mut file = tokio::fs::File::open(path).await?;
-
-                    file.seek(SeekFrom::Start(byte_offset)).await?;
-                    file.read_to_end(&mut buf).await?;
+                    let file = tokio::fs::File::open(path).await?;
+                    let total = file.metadata().await?.len();
 
-                    Ok::<_, StorageBackendError>(buf)
+                    Ok::<_, StorageBackendError>((file, total))
                 })
                 .await??
         };
 
-        Ok(StreamFile::new_from_bytes(buf.as_slice(), &p, 0))
+        let file = StreamFile::new_from_file(file, total, &p, byte_offset);
+        match end {
+            Some(end) => Ok(file.take(end.saturating_sub(byte_offset))),
+            None => Ok(file),
+        }
+    }
+
+    async fn stat_impl(&self, p: String) -> StorageBackendResult<Entry> {
+        let local_path = to_local_path(p.clone());
+
+        let metadata = tokio_runtime()
+            .spawn(async move { tokio::fs::metadata(local_path).await })
+            .await??;
+        let name = p
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+
+        Ok(Entry {
+            name,
+            path: p,
+            size: Some(metadata.len() as usize),
+            is_dir: metadata.is_dir(),
+            modified_time: modified_time(&metadata),
+            ..Default::default()
+        })
+    }
+
+    async fn put_impl(&self, p: String, file: UploadFile) -> StorageBackendResult<()> {
+        let p = to_local_path(p);
+
+        tokio_runtime()
+            .spawn(async move {
+                let rx = file.into_rx();
+                let mut file = tokio::fs::File::create(&p).await?;
+                while let Ok(chunk) = rx.recv().await {
+                    file.write_all(chunk?.as_ref()).await?;
+                }
+                file.flush().await?;
+
+                Ok::<_, StorageBackendError>(())
+            })
+            .await??;
+        Ok(())
+    }
+
+    async fn mkdir_impl(&self, dir: String) -> StorageBackendResult<()> {
+        let dir = to_local_path(dir);
+
+        tokio_runtime()
+            .spawn(async move { tokio::fs::create_dir(dir).await })
+            .await??;
+        Ok(())
+    }
+
+    async fn delete_impl(&self, p: String) -> StorageBackendResult<()> {
+        let p = to_local_path(p);
+
+        tokio_runtime()
+            .spawn(async move {
+                let metadata = tokio::fs::metadata(&p).await?;
+                if metadata.is_dir() {
+                    tokio::fs::remove_dir_all(&p).await
+                } else {
+                    tokio::fs::remove_file(&p).await
+                }
+            })
+            .await??;
+        Ok(())
+    }
+
+    async fn rename_impl(&self, from: String, to: String) -> StorageBackendResult<()> {
+        let from = to_local_path(from);
+        let to = to_local_path(to);
+
+        tokio_runtime()
+            .spawn(async move { tokio::fs::rename(from, to).await })
+            .await??;
+        Ok(())
     }
 }
 
@@ -99,7 +188,38 @@ impl StorageBackend for LocalBackend {
         Box::pin(self.list_impl(dir))
     }
     fn get(&self, p: String, byte_offset: u64) -> BoxFuture<StorageBackendResult<StreamFile>> {
-        Box::pin(self.get_impl(p, byte_offset))
+        Box::pin(self.get_impl(p, byte_offset, None))
+    }
+    fn get_range(
+        &self,
+        p: String,
+        start: u64,
+        end: u64,
+    ) -> BoxFuture<StorageBackendResult<StreamFile>> {
+        Box::pin(self.get_impl(p, start, Some(end)))
+    }
+    fn stat(&self, p: String) -> BoxFuture<StorageBackendResult<Entry>> {
+        Box::pin(self.stat_impl(p))
+    }
+    fn put(&self, p: String, file: UploadFile) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.put_impl(p, file))
+    }
+    fn mkdir(&self, dir: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.mkdir_impl(dir))
+    }
+    fn delete(&self, p: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.delete_impl(p))
+    }
+    fn rename(&self, from: String, to: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.rename_impl(from, to))
+    }
+
+    fn changes_since(
+        &self,
+        dir: String,
+        cursor: Option<String>,
+    ) -> BoxFuture<StorageBackendResult<StorageChanges>> {
+        Box::pin(async move { snapshot_changes(self, &dir, cursor.as_deref()).await })
     }
 }
 
@@ -107,7 +227,7 @@ impl StorageBackend for LocalBackend {
 mod test {
     use futures_util::{pin_mut, StreamExt};
 
-    use crate::{LocalBackend, StorageBackend};
+    use crate::{LocalBackend, StorageBackend, StorageChange, UploadFile};
 
     #[tokio::test]
     async fn test_list_dir() {
@@ -152,6 +272,22 @@ mod test {
         assert_eq!(String::from_utf8_lossy(bytes.as_ref()), "og.txt");
     }
 
+    #[tokio::test]
+    async fn test_range_bytes() {
+        let backend = LocalBackend::new();
+
+        let cwd = std::env::current_dir()
+            .unwrap()
+            .join("test/assets/case_list/b.log.txt");
+        let cwd = cwd.to_string_lossy().to_string();
+        let file = backend.get_range(cwd, 3, 5).await.unwrap();
+        assert_eq!(file.size(), Some(2));
+        assert_eq!(file.total_size(), Some(9));
+        let bytes = file.bytes().await.unwrap();
+
+        assert_eq!(String::from_utf8_lossy(bytes.as_ref()), "og");
+    }
+
     #[tokio::test]
     async fn test_partial_stream() {
         let backend = LocalBackend::new();
@@ -168,4 +304,102 @@ mod test {
         let chunk = chunk.unwrap().unwrap();
         assert_eq!(String::from_utf8_lossy(chunk.as_ref()), "og.txt");
     }
+
+    #[tokio::test]
+    async fn test_write_operations() {
+        let backend = LocalBackend::new();
+
+        let root = std::env::temp_dir().join(format!("ease-local-write-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let root = root.to_string_lossy().replace('\\', "/");
+
+        let dir = format!("{root}/folder");
+        backend.mkdir(dir.clone()).await.unwrap();
+
+        let p = format!("{dir}/a.lrc");
+        let file = UploadFile::new_from_bytes(bytes::Bytes::from_static(b"[00:00.00]ease"));
+        backend.put(p.clone(), file).await.unwrap();
+        let bytes = backend
+            .get(p.clone(), 0)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(bytes.as_ref(), b"[00:00.00]ease");
+
+        let renamed = format!("{dir}/b.lrc");
+        backend.rename(p.clone(), renamed.clone()).await.unwrap();
+        let list = backend.list(dir.clone()).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, "b.lrc");
+
+        backend.delete(dir.clone()).await.unwrap();
+        let list = backend.list(root.clone()).await.unwrap();
+        assert!(list.is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stat() {
+        let backend = LocalBackend::new();
+
+        let cwd = std::env::current_dir()
+            .unwrap()
+            .join("test/assets/case_list/b.log.txt");
+        let cwd = cwd.to_string_lossy().to_string();
+        let entry = backend.stat(cwd.clone()).await.unwrap();
+        assert_eq!(entry.name, "b.log.txt");
+        assert_eq!(entry.path, cwd);
+        assert_eq!(entry.size, Some(9));
+        assert!(!entry.is_dir);
+        assert!(entry.modified_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_changes_since() {
+        let backend = LocalBackend::new();
+
+        let root = std::env::temp_dir().join(format!("ease-local-changes-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("album")).unwrap();
+        std::fs::write(root.join("a.mp3"), b"a").unwrap();
+        std::fs::write(root.join("b.mp3"), b"b").unwrap();
+        std::fs::write(root.join("album/c.mp3"), b"c").unwrap();
+        let root = root.to_string_lossy().replace('\\', "/");
+
+        // The album below isn't part of it
+        let first = backend.changes_since(root.clone(), None).await.unwrap();
+        assert!(first.reset);
+        assert_eq!(first.changes.len(), 2);
+
+        std::fs::write(format!("{root}/a.mp3"), b"a longer").unwrap();
+        std::fs::rename(format!("{root}/b.mp3"), format!("{root}/d.mp3")).unwrap();
+        std::fs::write(format!("{root}/album/c.mp3"), b"c longer").unwrap();
+        let second = backend
+            .changes_since(root.clone(), Some(first.cursor))
+            .await
+            .unwrap();
+        assert!(!second.reset);
+        let mut changes: Vec<String> = second
+            .changes
+            .iter()
+            .map(|v| match v {
+                StorageChange::Upsert(entry) => format!("+{}", entry.name),
+                StorageChange::Removed(p) => format!("-{}", p.strip_prefix(&root).unwrap()),
+            })
+            .collect();
+        changes.sort();
+        assert_eq!(changes, vec!["+a.mp3", "+d.mp3", "-/b.mp3"]);
+
+        let third = backend
+            .changes_since(root.clone(), Some(second.cursor))
+            .await
+            .unwrap();
+        assert!(third.changes.is_empty());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
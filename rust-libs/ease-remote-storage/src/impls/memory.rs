@@ -0,0 +1,610 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use ease_client_tokio::tokio_runtime;
+use futures_util::future::BoxFuture;
+use reqwest::StatusCode;
+
+use crate::changes::{is_under, snapshot_changes, StorageChanges};
+use crate::{
+    Entry, StorageBackend, StorageBackendError, StorageBackendResult, StreamFile, UploadFile,
+};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A failure the backend plays back for every path under the prefix it was injected at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryFault {
+    /// Credentials are rejected, like a 401.
+    Unauthorized,
+    /// The request times out.
+    Timeout,
+    /// The path is missing, like a 404.
+    NotFound,
+    Forbidden,
+    RateLimited(Option<Duration>),
+    ServerError(StatusCode),
+    MethodNotAllowed,
+    InsufficientStorage,
+    ConnectionRefused,
+    /// Reads fail after this many bytes of the body.
+    Truncate(u64),
+    /// Reads start at byte zero whatever offset was asked, like a server without `Range`
+    /// support. The client skips to the offset itself.
+    IgnoreRange,
+}
+
+impl MemoryFault {
+    fn error(self) -> Option<StorageBackendError> {
+        match self {
+            MemoryFault::Unauthorized => Some(StorageBackendError::AuthenticationFailed),
+            MemoryFault::Timeout => Some(std::io::Error::from(ErrorKind::TimedOut).into()),
+            MemoryFault::NotFound => Some(std::io::Error::from(ErrorKind::NotFound).into()),
+            MemoryFault::Forbidden => Some(StorageBackendError::Forbidden),
+            MemoryFault::RateLimited(retry_after) => {
+                Some(StorageBackendError::RateLimited { retry_after })
+            }
+            MemoryFault::ServerError(status) => Some(StorageBackendError::ServerError(status)),
+            MemoryFault::MethodNotAllowed => Some(StorageBackendError::MethodNotAllowed),
+            MemoryFault::InsufficientStorage => Some(StorageBackendError::InsufficientStorage),
+            MemoryFault::ConnectionRefused => Some(StorageBackendError::ConnectionRefused),
+            MemoryFault::Truncate(_) | MemoryFault::IgnoreRange => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Node {
+    Dir,
+    File {
+        data: Bytes,
+        modified_time: Duration,
+        etag: String,
+    },
+}
+
+#[derive(Default)]
+struct MemoryState {
+    nodes: BTreeMap<String, Node>,
+    faults: Vec<(String, MemoryFault)>,
+    latency: Duration,
+    version: u64,
+}
+
+impl MemoryState {
+    fn file(&mut self, data: Bytes) -> Node {
+        self.version += 1;
+        Node::File {
+            data,
+            modified_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            etag: format!("v{}", self.version),
+        }
+    }
+
+    fn is_dir(&self, p: &str) -> bool {
+        matches!(self.nodes.get(p), Some(Node::Dir))
+    }
+
+    fn create_parents(&mut self, p: &str) {
+        let mut parent = parent_of(p);
+        while let Some(dir) = parent {
+            self.nodes.insert(dir.to_string(), Node::Dir);
+            parent = parent_of(dir);
+        }
+    }
+
+    /// The node at `p` and everything below it.
+    fn subtree(&self, p: &str) -> Vec<String> {
+        self.nodes
+            .keys()
+            .filter(|key| is_under(p, key))
+            .cloned()
+            .collect()
+    }
+}
+
+/// A storage kept in memory, for tests. Latency and faults can be set per path to play
+/// back how a remote misbehaves.
+pub struct MemoryBackend {
+    state: Mutex<MemoryState>,
+    sent_bytes: Arc<AtomicU64>,
+}
+
+fn normalize(p: &str) -> String {
+    "/".to_string() + p.trim_matches('/')
+}
+
+fn parent_of(p: &str) -> Option<&str> {
+    match p.rfind('/') {
+        Some(0) if p.len() > 1 => Some("/"),
+        Some(0) | None => None,
+        Some(i) => Some(&p[..i]),
+    }
+}
+
+fn entry(p: &str, node: &Node) -> Entry {
+    let name = p.rsplit('/').next().unwrap_or_default().to_string();
+    match node {
+        Node::Dir => Entry {
+            name,
+            path: p.to_string(),
+            is_dir: true,
+            ..Default::default()
+        },
+        Node::File {
+            data,
+            modified_time,
+            etag,
+        } => Entry {
+            name,
+            path: p.to_string(),
+            size: Some(data.len()),
+            modified_time: Some(*modified_time),
+            etag: Some(etag.clone()),
+            ..Default::default()
+        },
+    }
+}
+
+fn not_found() -> StorageBackendError {
+    std::io::Error::from(ErrorKind::NotFound).into()
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        let mut state = MemoryState::default();
+        state.nodes.insert("/".to_string(), Node::Dir);
+        Self {
+            state: Mutex::new(state),
+            sent_bytes: Default::default(),
+        }
+    }
+
+    /// Writes a file, creating the directories above it.
+    pub fn add_file(&self, p: &str, data: impl Into<Bytes>) {
+        let p = normalize(p);
+        let mut state = self.state.lock().unwrap();
+        state.create_parents(&p);
+        let node = state.file(data.into());
+        state.nodes.insert(p, node);
+    }
+
+    pub fn add_dir(&self, p: &str) {
+        let p = normalize(p);
+        let mut state = self.state.lock().unwrap();
+        state.create_parents(&p);
+        state.nodes.insert(p, Node::Dir);
+    }
+
+    pub fn read_file(&self, p: &str) -> Option<Bytes> {
+        match self.state.lock().unwrap().nodes.get(&normalize(p)) {
+            Some(Node::File { data, .. }) => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    /// Delay before every request is answered.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    pub fn inject(&self, prefix: &str, fault: MemoryFault) {
+        let prefix = normalize(prefix);
+        self.state.lock().unwrap().faults.push((prefix, fault));
+    }
+
+    pub fn clear_faults(&self) {
+        self.state.lock().unwrap().faults.clear();
+    }
+
+    /// File bytes sent to readers so far, including the ones skipped over when the range
+    /// was ignored.
+    pub fn sent_bytes(&self) -> u64 {
+        self.sent_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Waits out the latency, then fails if a fault applies to `p`. Faults that only change
+    /// how a file is read are handed back.
+    async fn request(&self, p: &str) -> StorageBackendResult<Vec<MemoryFault>> {
+        let (latency, faults) = {
+            let state = self.state.lock().unwrap();
+            let faults: Vec<MemoryFault> = state
+                .faults
+                .iter()
+                .filter(|(prefix, _)| is_under(prefix, p))
+                .map(|(_, fault)| *fault)
+                .collect();
+            (state.latency, faults)
+        };
+        if !latency.is_zero() {
+            tokio_runtime().spawn(tokio::time::sleep(latency)).await?;
+        }
+        if let Some(e) = faults.iter().find_map(|fault| fault.error()) {
+            return Err(e);
+        }
+        Ok(faults)
+    }
+
+    async fn list_impl(&self, dir: String) -> StorageBackendResult<Vec<Entry>> {
+        let dir = normalize(&dir);
+        self.request(&dir).await?;
+
+        let state = self.state.lock().unwrap();
+        if !state.is_dir(&dir) {
+            return Err(not_found());
+        }
+        let ret = state
+            .nodes
+            .iter()
+            .filter(|(p, _)| p.as_str() != dir && parent_of(p) == Some(dir.as_str()))
+            .map(|(p, node)| entry(p, node))
+            .collect();
+        Ok(ret)
+    }
+
+    async fn stat_impl(&self, p: String) -> StorageBackendResult<Entry> {
+        let p = normalize(&p);
+        self.request(&p).await?;
+
+        let state = self.state.lock().unwrap();
+        let node = state.nodes.get(&p).ok_or_else(not_found)?;
+        Ok(entry(&p, node))
+    }
+
+    async fn get_impl(
+        &self,
+        p: String,
+        start: u64,
+        end: Option<u64>,
+    ) -> StorageBackendResult<StreamFile> {
+        let p = normalize(&p);
+        let faults = self.request(&p).await?;
+
+        let data = match self.state.lock().unwrap().nodes.get(&p) {
+            Some(Node::File { data, .. }) => data.clone(),
+            _ => return Err(not_found()),
+        };
+        let len = data.len() as u64;
+        let start = start.min(len);
+        let end = end.map_or(len, |end| end.clamp(start, len));
+        let body_start = if faults.contains(&MemoryFault::IgnoreRange) {
+            0
+        } else {
+            start
+        };
+        let truncate = faults.iter().find_map(|fault| match fault {
+            MemoryFault::Truncate(n) => Some(*n),
+            _ => None,
+        });
+
+        let (tx, rx) = async_channel::bounded::<StorageBackendResult<Bytes>>(10);
+        let sent_bytes = self.sent_bytes.clone();
+        tokio_runtime().spawn(async move {
+            let mut pos = body_start;
+            while pos < end {
+                let mut chunk_end = (pos + CHUNK_SIZE as u64).min(end);
+                if let Some(n) = truncate {
+                    chunk_end = chunk_end.min(body_start + n);
+                }
+                if chunk_end <= pos {
+                    let e = std::io::Error::from(ErrorKind::UnexpectedEof);
+                    let _ = tx.send(Err(e.into())).await;
+                    break;
+                }
+                sent_bytes.fetch_add(chunk_end - pos, Ordering::SeqCst);
+                let from = pos.max(start);
+                pos = chunk_end;
+                if from >= chunk_end {
+                    continue;
+                }
+                let chunk = data.slice(from as usize..chunk_end as usize);
+                if tx.send(Ok(chunk)).await.is_err() {
+                    break;
+                }
+            }
+            tx.close();
+        });
+
+        let name = p.rsplit('/').next().unwrap_or_default();
+        Ok(StreamFile::new_from_rx(
+            rx,
+            Some((end - start) as usize),
+            name,
+        ))
+    }
+
+    async fn put_impl(&self, p: String, file: UploadFile) -> StorageBackendResult<()> {
+        let p = normalize(&p);
+        self.request(&p).await?;
+
+        let mut buf = bytes::BytesMut::with_capacity(file.size() as usize);
+        let rx = file.into_rx();
+        while let Ok(chunk) = rx.recv().await {
+            buf.extend_from_slice(&chunk?);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if !parent_of(&p).is_some_and(|parent| state.is_dir(parent)) || state.is_dir(&p) {
+            return Err(not_found());
+        }
+        let node = state.file(buf.freeze());
+        state.nodes.insert(p, node);
+        Ok(())
+    }
+
+    async fn mkdir_impl(&self, dir: String) -> StorageBackendResult<()> {
+        let dir = normalize(&dir);
+        self.request(&dir).await?;
+
+        let mut state = self.state.lock().unwrap();
+        if state.nodes.contains_key(&dir) {
+            return Err(std::io::Error::from(ErrorKind::AlreadyExists).into());
+        }
+        if !parent_of(&dir).is_some_and(|parent| state.is_dir(parent)) {
+            return Err(not_found());
+        }
+        state.nodes.insert(dir, Node::Dir);
+        Ok(())
+    }
+
+    async fn delete_impl(&self, p: String) -> StorageBackendResult<()> {
+        let p = normalize(&p);
+        self.request(&p).await?;
+
+        let mut state = self.state.lock().unwrap();
+        if p == "/" || !state.nodes.contains_key(&p) {
+            return Err(not_found());
+        }
+        for key in state.subtree(&p) {
+            state.nodes.remove(&key);
+        }
+        Ok(())
+    }
+
+    async fn rename_impl(&self, from: String, to: String) -> StorageBackendResult<()> {
+        let from = normalize(&from);
+        let to = normalize(&to);
+        self.request(&from).await?;
+        self.request(&to).await?;
+
+        let mut state = self.state.lock().unwrap();
+        if from == "/" || !state.nodes.contains_key(&from) {
+            return Err(not_found());
+        }
+        if !parent_of(&to).is_some_and(|parent| state.is_dir(parent)) {
+            return Err(not_found());
+        }
+        if is_under(&from, &to) {
+            return Err(std::io::Error::from(ErrorKind::InvalidInput).into());
+        }
+        for key in state.subtree(&to) {
+            state.nodes.remove(&key);
+        }
+        for key in state.subtree(&from) {
+            let node = state.nodes.remove(&key).unwrap();
+            state.nodes.insert(to.clone() + &key[from.len()..], node);
+        }
+        Ok(())
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn list(&self, dir: String) -> BoxFuture<StorageBackendResult<Vec<Entry>>> {
+        Box::pin(self.list_impl(dir))
+    }
+    fn stat(&self, p: String) -> BoxFuture<StorageBackendResult<Entry>> {
+        Box::pin(self.stat_impl(p))
+    }
+    fn get(&self, p: String, byte_offset: u64) -> BoxFuture<StorageBackendResult<StreamFile>> {
+        Box::pin(self.get_impl(p, byte_offset, None))
+    }
+    fn get_range(
+        &self,
+        p: String,
+        start: u64,
+        end: u64,
+    ) -> BoxFuture<StorageBackendResult<StreamFile>> {
+        Box::pin(self.get_impl(p, start, Some(end)))
+    }
+    fn put(&self, p: String, file: UploadFile) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.put_impl(p, file))
+    }
+    fn mkdir(&self, dir: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.mkdir_impl(dir))
+    }
+    fn delete(&self, p: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.delete_impl(p))
+    }
+    fn rename(&self, from: String, to: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.rename_impl(from, to))
+    }
+
+    fn changes_since(
+        &self,
+        dir: String,
+        cursor: Option<String>,
+    ) -> BoxFuture<StorageBackendResult<StorageChanges>> {
+        Box::pin(async move { snapshot_changes(self, &dir, cursor.as_deref()).await })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{MemoryBackend, MemoryFault, StorageBackend, StorageChange, UploadFile};
+
+    #[tokio::test]
+    async fn test_tree() {
+        let backend = MemoryBackend::new();
+        backend.add_file("/music/b.mp3", &b"bbb"[..]);
+        backend.add_file("/music/a.mp3", &b"aa"[..]);
+        backend.add_file("/music/album/c.mp3", &b"c"[..]);
+
+        let list = backend.list("/music".to_string()).await.unwrap();
+        let names: Vec<&str> = list.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["a.mp3", "album", "b.mp3"]);
+        assert_eq!(list[0].path, "/music/a.mp3");
+        assert_eq!(list[0].size, Some(2));
+        assert!(list[1].is_dir);
+        assert_eq!(backend.list("/".to_string()).await.unwrap().len(), 1);
+
+        let buf = UploadFile::new_from_bytes(bytes::Bytes::from_static(b"dd"));
+        backend.put("/music/d.lrc".to_string(), buf).await.unwrap();
+        let buf = UploadFile::new_from_bytes(bytes::Bytes::from_static(b"e"));
+        let err = backend.put("/other/e".to_string(), buf).await.unwrap_err();
+        assert!(err.is_not_found());
+        assert_eq!(backend.read_file("/music/d.lrc").unwrap().as_ref(), b"dd");
+
+        backend
+            .rename("/music/album".to_string(), "/album".to_string())
+            .await
+            .unwrap();
+        assert_eq!(backend.read_file("/album/c.mp3").unwrap().as_ref(), b"c");
+        assert!(backend.read_file("/music/album/c.mp3").is_none());
+
+        backend.delete("/album".to_string()).await.unwrap();
+        assert!(backend.read_file("/album/c.mp3").is_none());
+        let err = backend.stat("/album".to_string()).await.unwrap_err();
+        assert!(err.is_not_found());
+
+        backend.mkdir("/music/new".to_string()).await.unwrap();
+        assert!(backend.stat("/music/new".to_string()).await.unwrap().is_dir);
+    }
+
+    #[tokio::test]
+    async fn test_get_range() {
+        let backend = MemoryBackend::new();
+        let data: Vec<u8> = (0..200_000u32).map(|v| v as u8).collect();
+        backend.add_file("/a.bin", data.clone());
+
+        let file = backend.get("/a.bin".to_string(), 0).await.unwrap();
+        assert_eq!(file.size(), Some(data.len()));
+        assert_eq!(file.bytes().await.unwrap().as_ref(), &data[..]);
+
+        let file = backend
+            .get_range("/a.bin".to_string(), 70_000, 140_000)
+            .await
+            .unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), &data[70_000..140_000]);
+        assert_eq!(backend.sent_bytes(), 200_000 + 70_000);
+
+        let file = backend.get("/a.bin".to_string(), 300_000).await.unwrap();
+        assert!(file.bytes().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_faults() {
+        let backend = MemoryBackend::new();
+        let data: Vec<u8> = (0..200_000u32).map(|v| v as u8).collect();
+        backend.add_file("/locked/a.bin", data.clone());
+        backend.add_file("/slow/a.bin", data.clone());
+        backend.add_file("/gone/a.bin", data.clone());
+        backend.add_file("/cut/a.bin", data.clone());
+        backend.add_file("/norange/a.bin", data.clone());
+
+        backend.inject("/locked", MemoryFault::Unauthorized);
+        backend.inject("/slow", MemoryFault::Timeout);
+        backend.inject("/gone/a.bin", MemoryFault::NotFound);
+        backend.inject("/cut", MemoryFault::Truncate(100_000));
+        backend.inject("/norange", MemoryFault::IgnoreRange);
+
+        let err = backend.list("/locked".to_string()).await.unwrap_err();
+        assert!(err.is_unauthorized());
+        let err = backend
+            .get("/slow/a.bin".to_string(), 0)
+            .await
+            .err()
+            .unwrap();
+        assert!(err.is_timeout());
+        let err = backend.stat("/gone/a.bin".to_string()).await.unwrap_err();
+        assert!(err.is_not_found());
+        assert_eq!(backend.list("/gone".to_string()).await.unwrap().len(), 1);
+
+        let file = backend.get("/cut/a.bin".to_string(), 0).await.unwrap();
+        assert_eq!(file.size(), Some(data.len()));
+        let rx = file.into_rx();
+        let mut received = 0;
+        let mut failed = false;
+        while let Ok(chunk) = rx.recv().await {
+            match chunk {
+                Ok(chunk) => received += chunk.len(),
+                Err(_) => failed = true,
+            }
+        }
+        assert_eq!(received, 100_000);
+        assert!(failed);
+
+        let before = backend.sent_bytes();
+        let file = backend
+            .get_range("/norange/a.bin".to_string(), 150_000, 160_000)
+            .await
+            .unwrap();
+        assert_eq!(
+            file.bytes().await.unwrap().as_ref(),
+            &data[150_000..160_000]
+        );
+        assert_eq!(backend.sent_bytes() - before, 160_000);
+
+        backend.clear_faults();
+        assert!(backend.list("/locked".to_string()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_latency() {
+        let backend = MemoryBackend::new();
+        backend.add_file("/a.txt", &b"a"[..]);
+        backend.set_latency(Duration::from_millis(50));
+
+        let start = Instant::now();
+        backend.stat("/a.txt".to_string()).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_changes_since() {
+        let backend = MemoryBackend::new();
+        backend.add_file("/music/a.mp3", &b"a"[..]);
+        backend.add_file("/music/b.mp3", &b"b"[..]);
+
+        let changes = backend
+            .changes_since("/music".to_string(), None)
+            .await
+            .unwrap();
+        assert!(changes.reset);
+        assert_eq!(changes.changes.len(), 2);
+
+        backend.add_file("/music/a.mp3", &b"aa"[..]);
+        backend.delete("/music/b.mp3".to_string()).await.unwrap();
+        let changes = backend
+            .changes_since("/music".to_string(), Some(changes.cursor))
+            .await
+            .unwrap();
+        assert!(!changes.reset);
+        assert_eq!(changes.changes.len(), 2);
+        assert!(matches!(
+            &changes.changes[0],
+            StorageChange::Upsert(entry) if entry.path == "/music/a.mp3"
+        ));
+        assert_eq!(
+            changes.changes[1],
+            StorageChange::Removed("/music/b.mp3".to_string())
+        );
+    }
+}
@@ -1,8 +1,24 @@
+mod http_index;
+mod jellyfin;
 mod local;
+mod memory;
 mod onedrive;
+mod s3;
+mod sftp;
+mod subsonic;
 mod webdav;
+mod webdav_multistatus;
 
+pub use http_index::{BuildHttpIndexArg, HttpIndexBackend};
+pub use jellyfin::{BuildJellyfinArg, JellyfinBackend};
 pub use local::LocalBackend;
+pub use memory::{MemoryBackend, MemoryFault};
 
-pub use onedrive::{BuildOneDriveArg, OneDriveBackend};
+pub use onedrive::{
+    BuildOneDriveArg, OneDriveAppConfig, OneDriveBackend, OneDriveDrive, OneDriveDriveInfo,
+    OneDriveDriveKind, OneDrivePkce, OneDriveRefreshTokenCallback,
+};
+pub use s3::{BuildS3Arg, S3Backend};
+pub use sftp::{BuildSftpArg, SftpAuth, SftpBackend, SftpHostKeyCallback};
+pub use subsonic::{BuildSubsonicArg, SubsonicBackend};
 pub use webdav::{BuildWebdavArg, Webdav};
@@ -1,27 +1,194 @@
-use std::{cmp::Ordering, time::Duration};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
-use ease_client_tokio::tokio_runtime;
+use base64::Engine;
+use bytes::Bytes;
 use futures_util::future::BoxFuture;
+use rand::RngCore;
 use reqwest::header::HeaderValue;
 use reqwest::StatusCode;
 
+use crate::backend::{format_range, parse_rfc3339_time};
+use crate::changes::{
+    is_child, is_removal_in, is_snapshot_cursor, is_under, snapshot_changes, StorageChange,
+    StorageChanges,
+};
+use crate::http_client::{HttpNetworkConfig, HttpPoolConfig, ResponseExt, SharedHttpClient};
+use crate::retry::send_with_retry;
 use crate::{
-    env::EASEM_ONEDRIVE_ID, Entry, StorageBackend, StorageBackendError, StorageBackendResult,
-    StreamFile,
+    env::EASEM_ONEDRIVE_ID, Entry, RetryPolicy, StorageBackend, StorageBackendError,
+    StorageBackendResult, StreamFile, UploadFile,
 };
+use sha2::{Digest, Sha256};
+
+/// Receives the new refresh token whenever Microsoft rotates it.
+pub type OneDriveRefreshTokenCallback = Arc<dyn Fn(String) + Send + Sync>;
+
+/// The Azure app registration and the cloud it lives in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OneDriveAppConfig {
+    /// Graph API root, e.g. `https://graph.microsoft.us/v1.0` for a national cloud.
+    pub graph_api: String,
+    /// OAuth 2.0 root of the tenant, `/authorize` and `/token` live below it.
+    pub login_api: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: String,
+}
+
+impl Default for OneDriveAppConfig {
+    fn default() -> Self {
+        Self {
+            graph_api: ONEDRIVE_GRAPH_API.to_string(),
+            login_api: ONEDRIVE_LOGIN_API.to_string(),
+            client_id: EASEM_ONEDRIVE_ID.to_string(),
+            redirect_uri: ONEDRIVE_REDIRECT_URI.to_string(),
+            scope: ONEDRIVE_SCOPE.to_string(),
+        }
+    }
+}
+
+impl OneDriveAppConfig {
+    /// The sign-in page url, the code it redirects with must be redeemed with the same `pkce`.
+    pub fn authorize_url(&self, pkce: &OneDrivePkce) -> String {
+        let login_api = &self.login_api;
+        let query = form_urlencoded(&[
+            ("client_id", &self.client_id),
+            ("response_type", "code"),
+            ("redirect_uri", &self.redirect_uri),
+            ("scope", &self.scope),
+            ("code_challenge", &pkce.code_challenge),
+            ("code_challenge_method", "S256"),
+        ]);
+        format!("{login_api}/authorize?{query}")
+    }
+}
+
+/// A PKCE (RFC 7636) verifier and its S256 challenge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OneDrivePkce {
+    pub code_verifier: String,
+    pub code_challenge: String,
+}
+
+impl OneDrivePkce {
+    pub fn generate() -> Self {
+        let mut buf = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut buf);
+        Self::from_verifier(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(buf))
+    }
+
+    pub fn from_verifier(code_verifier: String) -> Self {
+        let code_challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(Sha256::digest(code_verifier.as_bytes()));
+        Self {
+            code_verifier,
+            code_challenge,
+        }
+    }
+}
+
+/// Which drive, or folder inside one, a OneDrive storage is rooted at.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OneDriveDrive {
+    /// The signed-in user's own OneDrive.
+    #[default]
+    Personal,
+    /// A whole drive, e.g. a SharePoint document library.
+    Drive { drive_id: String },
+    /// A folder someone else shared, living in their drive.
+    Item { drive_id: String, item_id: String },
+}
+
+impl OneDriveDrive {
+    /// The Graph resource path relative to the API root, empty for the personal drive.
+    /// Storages keep it in their address.
+    pub fn to_addr(&self) -> String {
+        match self {
+            OneDriveDrive::Personal => Default::default(),
+            OneDriveDrive::Drive { drive_id } => format!("drives/{drive_id}"),
+            OneDriveDrive::Item { drive_id, item_id } => {
+                format!("drives/{drive_id}/items/{item_id}")
+            }
+        }
+    }
+
+    /// The reverse of [`OneDriveDrive::to_addr`], anything unknown is the personal drive.
+    pub fn from_addr(addr: &str) -> Self {
+        let parts: Vec<&str> = addr.trim_matches('/').split('/').collect();
+        match parts.as_slice() {
+            ["drives", drive_id] if !drive_id.is_empty() => OneDriveDrive::Drive {
+                drive_id: drive_id.to_string(),
+            },
+            ["drives", drive_id, "items", item_id]
+                if !drive_id.is_empty() && !item_id.is_empty() =>
+            {
+                OneDriveDrive::Item {
+                    drive_id: drive_id.to_string(),
+                    item_id: item_id.to_string(),
+                }
+            }
+            _ => OneDriveDrive::Personal,
+        }
+    }
+
+    /// The item every storage path is resolved against.
+    fn root_api(&self, graph_api: &str) -> String {
+        match self {
+            OneDriveDrive::Personal => format!("{graph_api}/me/drive/root"),
+            OneDriveDrive::Drive { drive_id } => format!("{graph_api}/drives/{drive_id}/root"),
+            OneDriveDrive::Item { drive_id, item_id } => {
+                format!("{graph_api}/drives/{drive_id}/items/{item_id}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OneDriveDriveKind {
+    Personal,
+    SharedWithMe,
+    SharePoint,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OneDriveDriveInfo {
+    pub name: String,
+    pub kind: OneDriveDriveKind,
+    pub drive: OneDriveDrive,
+}
 
 pub struct BuildOneDriveArg {
     pub code: String,
+    pub app: OneDriveAppConfig,
+    pub drive: OneDriveDrive,
+    pub connect_timeout: Duration,
+    pub pool: HttpPoolConfig,
+    pub network: HttpNetworkConfig,
+    pub retry_policy: RetryPolicy,
+    /// Called with the rotated refresh token so the caller can persist it, the old one
+    /// stops working after a while.
+    pub on_refresh_token: Option<OneDriveRefreshTokenCallback>,
 }
 
 struct Auth {
     access_token: String,
     refresh_token: String,
+    can_write: bool,
 }
 
 pub struct OneDriveBackend {
     refresh_token: String,
     auth: tokio::sync::RwLock<Option<Auth>>,
+    client: SharedHttpClient,
+    retry_policy: RetryPolicy,
+    on_refresh_token: Option<OneDriveRefreshTokenCallback>,
+    app: OneDriveAppConfig,
+    drive: OneDriveDrive,
 }
 
 mod onedrive_types {
@@ -32,6 +199,8 @@ mod onedrive_types {
     pub struct RedeemCodeResp {
         pub access_token: String,
         pub refresh_token: String,
+        #[serde(default)]
+        pub scope: Option<String>,
     }
 
     #[serde_as]
@@ -43,9 +212,65 @@ mod onedrive_types {
         pub next_link: Option<String>,
     }
 
+    #[serde_as]
+    #[derive(Debug, Deserialize)]
+    #[serde(bound = "T: Deserialize<'de>")]
+    pub struct Page<T> {
+        #[serde_as(deserialize_as = "Vec<DefaultOnError>")]
+        pub value: Vec<Option<T>>,
+        #[serde(rename = "@odata.nextLink")]
+        pub next_link: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ItemId {
+        pub id: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct SharedItem {
+        pub name: String,
+        #[serde(rename = "remoteItem")]
+        pub remote_item: RemoteItem,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct RemoteItem {
+        pub id: String,
+        pub folder: Option<serde_json::Value>,
+        #[serde(rename = "parentReference")]
+        pub parent_reference: ParentReference,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ParentReference {
+        #[serde(rename = "driveId")]
+        pub drive_id: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Site {
+        pub id: String,
+        #[serde(rename = "displayName")]
+        pub display_name: Option<String>,
+        pub name: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Drive {
+        pub id: String,
+        pub name: String,
+        #[serde(rename = "driveType")]
+        pub drive_type: Option<String>,
+    }
+
     #[derive(Debug, Deserialize)]
     pub struct ListItem {
         pub name: String,
+        #[serde(rename = "lastModifiedDateTime")]
+        pub last_modified_date_time: Option<String>,
+        #[serde(rename = "eTag")]
+        pub e_tag: Option<String>,
         #[serde(flatten)]
         pub kind: ListItemKind,
     }
@@ -55,8 +280,7 @@ mod onedrive_types {
     pub enum ListItemKind {
         File {
             size: u64,
-            #[serde(rename = "file")]
-            _file: ListFileMetadata,
+            file: ListFileMetadata,
         },
         Folder {
             #[serde(rename = "folder")]
@@ -73,52 +297,242 @@ mod onedrive_types {
     #[derive(Debug, Deserialize)]
     pub struct ListFileMetadata {
         #[serde(rename = "mimeType")]
-        pub _mime_type: String,
+        pub mime_type: Option<String>,
+    }
+
+    #[serde_as]
+    #[derive(Debug, Deserialize)]
+    pub struct DeltaPage {
+        #[serde_as(deserialize_as = "Vec<DefaultOnError>")]
+        pub value: Vec<Option<DeltaItem>>,
+        #[serde(rename = "@odata.nextLink")]
+        pub next_link: Option<String>,
+        #[serde(rename = "@odata.deltaLink")]
+        pub delta_link: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct DeltaItem {
+        pub id: String,
+        pub name: Option<String>,
+        #[serde(rename = "parentReference")]
+        pub parent_reference: Option<DeltaParentReference>,
+        pub size: Option<u64>,
+        #[serde(rename = "lastModifiedDateTime")]
+        pub last_modified_date_time: Option<String>,
+        #[serde(rename = "eTag")]
+        pub e_tag: Option<String>,
+        pub file: Option<ListFileMetadata>,
+        pub folder: Option<serde_json::Value>,
+        pub root: Option<serde_json::Value>,
+        pub deleted: Option<serde_json::Value>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct DeltaParentReference {
+        pub id: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct UploadSessionResp {
+        #[serde(rename = "uploadUrl")]
+        pub upload_url: String,
     }
 }
 
-const ONEDRIVE_ROOT_API: &str = "https://graph.microsoft.com/v1.0/me/drive";
-const ONEDRIVE_API_BASE: &str = "https://login.microsoftonline.com/common/oauth2/v2.0";
+const ONEDRIVE_GRAPH_API: &str = "https://graph.microsoft.com/v1.0";
+const ONEDRIVE_LOGIN_API: &str = "https://login.microsoftonline.com/common/oauth2/v2.0";
 const ONEDRIVE_REDIRECT_URI: &str = "easem://oauth2redirect/";
+// Shared folders need `Files.ReadWrite.All`, SharePoint libraries `Sites.ReadWrite.All`.
+// Write access is needed to save lyrics and covers back. A refresh keeps the scopes of the
+// sign-in, so older read-only sign-ins get `ReauthorizationRequired` on writes
+const ONEDRIVE_SCOPE: &str = "Files.ReadWrite.All Sites.ReadWrite.All offline_access";
+// Simple uploads are limited to 4 MiB, larger files go through an upload session
+const ONEDRIVE_SIMPLE_UPLOAD_LIMIT: u64 = 4 << 20;
+// Upload session fragments must be a multiple of 320 KiB
+const ONEDRIVE_UPLOAD_FRAGMENT_SIZE: usize = 320 * 1024 * 16;
+
+fn is_unauthorized(e: &StorageBackendError) -> bool {
+    if let StorageBackendError::RequestFail(e) = e {
+        if let Some(StatusCode::UNAUTHORIZED) = e.status() {
+            return true;
+        }
+    }
+    false
+}
 
 fn is_auth_error<T>(r: &StorageBackendResult<T>) -> bool {
     if let Err(e) = r {
-        if let StorageBackendError::RequestFail(e) = e {
-            if let Some(StatusCode::UNAUTHORIZED) = e.status() {
-                return true;
-            }
-        }
+        return is_unauthorized(e);
     }
     false
 }
 
-fn build_client() -> StorageBackendResult<reqwest::Client> {
-    let client = reqwest::Client::builder()
-        .connect_timeout(Duration::from_secs(10))
-        .no_proxy()
-        .build()?;
-    Ok(client)
+/// Signing in before writes were asked for granted read-only scopes, and refreshing keeps
+/// those. Nothing is known when the token response lists no scopes.
+fn scope_grants_write(scope: Option<&str>) -> bool {
+    let Some(scope) = scope else {
+        return true;
+    };
+    scope.split_whitespace().any(|s| {
+        // Either `Files.ReadWrite.All` or `https://graph.microsoft.com/Files.ReadWrite.All`
+        let name = s.rsplit('/').next().unwrap_or(s);
+        name.eq_ignore_ascii_case("Files.ReadWrite")
+            || name.eq_ignore_ascii_case("Files.ReadWrite.All")
+    })
+}
+
+fn form_urlencoded(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(k, v)| format!("{k}={}", urlencoding::encode(v)))
+        .collect::<Vec<_>>()
+        .join("&")
 }
 
-async fn refresh_token_by_code_impl(code: String) -> StorageBackendResult<Auth> {
-    let client_id = EASEM_ONEDRIVE_ID;
-    let body =
-        format!("client_id={client_id}&redirect_uri={ONEDRIVE_REDIRECT_URI}&code={code}&grant_type=authorization_code");
-
-    let resp = tokio_runtime()
-        .spawn(async move {
-            let ret = build_client()?
-                .request(reqwest::Method::POST, format!("{ONEDRIVE_API_BASE}/token"))
-                .header("Content-Type", "application/x-www-form-urlencoded")
-                .body(body)
-                .send()
-                .await?;
-            Ok::<_, StorageBackendError>(ret)
+fn split_parent(p: &str) -> (&str, &str) {
+    let p = p.trim_end_matches('/');
+    match p.rfind('/') {
+        Some(pos) => (&p[..pos], &p[pos + 1..]),
+        None => ("", p),
+    }
+}
+
+fn build_entry(path: String, item: onedrive_types::ListItem) -> Entry {
+    let mut entry = Entry {
+        name: item.name,
+        path,
+        modified_time: item
+            .last_modified_date_time
+            .as_deref()
+            .and_then(parse_rfc3339_time),
+        etag: item.e_tag,
+        ..Default::default()
+    };
+    match item.kind {
+        onedrive_types::ListItemKind::File { size, file } => {
+            entry.size = Some(size as usize);
+            entry.content_type = file.mime_type;
+        }
+        onedrive_types::ListItemKind::Folder { .. } => {
+            entry.is_dir = true;
+        }
+    }
+    entry
+}
+
+const DELTA_CURSOR_PREFIX: &str = "onedrive-delta:";
+
+/// What the delta feed told us so far. Delta items only carry their parent id, so the
+/// tree is kept around to turn them into paths. Only the folders leading to the synced
+/// directory and what lies right in it are kept, the cursor is stored and rewritten on
+/// every sync.
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+struct DeltaCursor {
+    delta_link: String,
+    root_id: Option<String>,
+    items: HashMap<String, DeltaNode>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct DeltaNode {
+    parent: Option<String>,
+    name: String,
+    is_dir: bool,
+    size: Option<u64>,
+    etag: Option<String>,
+    modified_time: Option<u64>,
+    content_type: Option<String>,
+}
+
+impl DeltaCursor {
+    /// `None` when the item is not below the storage root, or its parents weren't seen yet.
+    fn path_of(&self, id: &str) -> Option<String> {
+        let mut names: Vec<&str> = Default::default();
+        let mut id = id;
+        while Some(id) != self.root_id.as_deref() {
+            let node = self.items.get(id)?;
+            // A cycle can only come from a broken feed
+            if names.len() > self.items.len() {
+                return None;
+            }
+            names.push(&node.name);
+            id = node.parent.as_deref()?;
+        }
+        names.reverse();
+        Some("/".to_string() + &names.join("/"))
+    }
+
+    /// Drops the nodes that are neither right in `dir`, `dir` itself nor one of its parents.
+    fn retain_for(&mut self, dir: &str) {
+        let keep: HashSet<String> = self
+            .items
+            .keys()
+            .filter(|id| {
+                self.path_of(id)
+                    .is_some_and(|p| is_child(dir, &p) || is_under(&p, dir))
+            })
+            .cloned()
+            .collect();
+        self.items.retain(|id, _| keep.contains(id));
+    }
+
+    fn child_count(&self, id: &str) -> u64 {
+        self.items
+            .values()
+            .filter(|v| v.parent.as_deref() == Some(id))
+            .count() as u64
+    }
+
+    fn entry_of(&self, id: &str, path: String) -> Option<Entry> {
+        let node = self.items.get(id)?;
+        Some(Entry {
+            name: node.name.clone(),
+            path,
+            size: node.size.map(|v| v as usize),
+            is_dir: node.is_dir,
+            modified_time: node.modified_time.map(Duration::from_millis),
+            etag: node.etag.clone(),
+            content_type: node.content_type.clone(),
         })
-        .await??;
+    }
+}
+
+async fn refresh_token_by_code_impl(
+    client: &SharedHttpClient,
+    app: &OneDriveAppConfig,
+    code: &str,
+    code_verifier: &str,
+) -> StorageBackendResult<Auth> {
+    let body = form_urlencoded(&[
+        ("client_id", &app.client_id),
+        ("redirect_uri", &app.redirect_uri),
+        ("code", code),
+        ("code_verifier", code_verifier),
+        ("grant_type", "authorization_code"),
+    ]);
+    let url = reqwest::Url::parse(&format!("{}/token", app.login_api))
+        .map_err(|e| StorageBackendError::UrlParseError(e.to_string()))?;
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-www-form-urlencoded"),
+    );
+    let resp = send_with_retry(
+        &RetryPolicy::default(),
+        client,
+        reqwest::Method::POST,
+        url,
+        headers,
+        Some(reqwest::Body::from(body)),
+    )
+    .await?
+    .check_status()?;
     let resp_text = resp.text().await?;
     let value = serde_json::from_str::<onedrive_types::RedeemCodeResp>(&resp_text)?;
     Ok(Auth {
+        can_write: scope_grants_write(value.scope.as_deref()),
         access_token: value.access_token,
         refresh_token: value.refresh_token,
     })
@@ -129,9 +543,18 @@ impl OneDriveBackend {
         Self {
             refresh_token: arg.code,
             auth: Default::default(),
+            client: SharedHttpClient::new(arg.connect_timeout, arg.pool, arg.network),
+            retry_policy: arg.retry_policy,
+            on_refresh_token: arg.on_refresh_token,
+            app: arg.app,
+            drive: arg.drive,
         }
     }
 
+    fn root_api(&self) -> String {
+        self.drive.root_api(&self.app.graph_api)
+    }
+
     async fn build_base_header_map(&self) -> reqwest::header::HeaderMap {
         let mut header_map = reqwest::header::HeaderMap::new();
         {
@@ -159,29 +582,42 @@ impl OneDriveBackend {
         &self,
         w: &mut Option<Auth>,
     ) -> StorageBackendResult<()> {
-        let client_id = EASEM_ONEDRIVE_ID;
-        let refresh_token = self.refresh_token.clone();
-        let body =
-            format!("client_id={client_id}&redirect_uri={ONEDRIVE_REDIRECT_URI}&refresh_token={refresh_token}&grant_type=refresh_token");
-
-        let resp = {
-            let client = self.build_client()?;
-
-            tokio_runtime()
-                .spawn(async move {
-                    client
-                        .request(reqwest::Method::POST, format!("{ONEDRIVE_API_BASE}/token"))
-                        .header("Content-Type", "application/x-www-form-urlencoded")
-                        .body(body)
-                        .send()
-                        .await
-                })
-                .await??
+        // Prefer the latest rotated token over the one the backend was built with
+        let refresh_token = match w.as_ref() {
+            Some(auth) => auth.refresh_token.clone(),
+            None => self.refresh_token.clone(),
         };
+        let body = form_urlencoded(&[
+            ("client_id", &self.app.client_id),
+            ("redirect_uri", &self.app.redirect_uri),
+            ("refresh_token", &refresh_token),
+            ("grant_type", "refresh_token"),
+        ]);
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        let resp = self
+            .send_core(
+                reqwest::Method::POST,
+                &format!("{}/token", self.app.login_api),
+                headers,
+                Some(reqwest::Body::from(body)),
+            )
+            .await?
+            .check_status()?;
         let resp_text = resp.text().await?;
         let value = serde_json::from_str::<onedrive_types::RedeemCodeResp>(&resp_text)?;
 
+        if value.refresh_token != refresh_token {
+            if let Some(on_refresh_token) = self.on_refresh_token.as_ref() {
+                on_refresh_token(value.refresh_token.clone());
+            }
+        }
         *w = Some(Auth {
+            can_write: scope_grants_write(value.scope.as_deref()),
             access_token: value.access_token,
             refresh_token: value.refresh_token,
         });
@@ -194,35 +630,31 @@ impl OneDriveBackend {
         Ok(())
     }
 
+    /// A 403 on a write with a token that can't write asks for a new sign-in rather than
+    /// reporting the file as locked.
+    async fn check_write_scope<T>(&self, r: StorageBackendResult<T>) -> StorageBackendResult<T> {
+        if let Err(StorageBackendError::Forbidden) = &r {
+            let auth = self.auth.read().await;
+            if auth.as_ref().is_some_and(|auth| !auth.can_write) {
+                return Err(StorageBackendError::ReauthorizationRequired);
+            }
+        }
+        r
+    }
+
     async fn list_core_by_url(&self, url: &str) -> StorageBackendResult<reqwest::Response> {
-        let url = reqwest::Url::parse(url)
-            .map_err(|e| StorageBackendError::UrlParseError(e.to_string()))?;
         let base_headers = self.build_base_header_map().await;
-
-        let resp = {
-            let client = self.build_client()?;
-
-            tokio_runtime()
-                .spawn(async move {
-                    client
-                        .request(reqwest::Method::GET, url.clone())
-                        .headers(base_headers)
-                        .send()
-                        .await
-                })
-                .await??
-        };
-
-        Ok(resp)
+        self.send_core(reqwest::Method::GET, url, base_headers, None)
+            .await
     }
 
     fn compute_list_url(&self, dir: &str) -> String {
         let subdir = if dir == "/" {
-            "/root/children".to_string()
+            "/children".to_string()
         } else {
-            ("/root:".to_string() + dir + ":/children").to_string()
+            (":".to_string() + dir + ":/children").to_string()
         };
-        let _url = ONEDRIVE_ROOT_API.to_string() + subdir.as_str();
+        let _url = self.root_api() + subdir.as_str();
         _url
     }
 
@@ -231,7 +663,7 @@ impl OneDriveBackend {
 
         let mut ret: Vec<Entry> = Default::default();
         loop {
-            let resp = self.list_core_by_url(&url).await?.error_for_status()?;
+            let resp = self.list_core_by_url(&url).await?.check_status()?;
             let text: String = resp.text().await?;
             let obj: onedrive_types::ListItemResponse =
                 serde_json::from_str(&text).map_err(|e| {
@@ -240,26 +672,8 @@ impl OneDriveBackend {
                 })?;
 
             for item in obj.value.into_iter().flatten() {
-                let name = item.name;
-                let path = dir.to_string() + "/" + name.as_str();
-                match item.kind {
-                    onedrive_types::ListItemKind::File { size, .. } => {
-                        ret.push(Entry {
-                            name,
-                            path,
-                            size: Some(size as usize),
-                            is_dir: false,
-                        });
-                    }
-                    onedrive_types::ListItemKind::Folder { .. } => {
-                        ret.push(Entry {
-                            name,
-                            path,
-                            size: None,
-                            is_dir: true,
-                        });
-                    }
-                }
+                let path = dir.to_string() + "/" + item.name.as_str();
+                ret.push(build_entry(path, item));
             }
             tracing::info!("load {} items", ret.len());
 
@@ -298,51 +712,659 @@ impl OneDriveBackend {
         return self.list_impl(dir.as_str()).await;
     }
 
-    async fn get_impl(&self, p: &str, byte_offset: u64) -> StorageBackendResult<StreamFile> {
-        let _url = ONEDRIVE_ROOT_API.to_string() + "/root:" + p + ":/content";
-        let url = reqwest::Url::parse(_url.as_str())
-            .map_err(|e| StorageBackendError::UrlParseError(e.to_string()))?;
+    async fn stat_impl(&self, p: &str) -> StorageBackendResult<Entry> {
+        let url = if p == "/" {
+            self.root_api()
+        } else {
+            self.root_api() + ":" + p
+        };
+        let resp = self.list_core_by_url(&url).await?.check_status()?;
+        let text: String = resp.text().await?;
+        let item: onedrive_types::ListItem = serde_json::from_str(&text).map_err(|e| {
+            tracing::warn!("onedrive stat resp: {text}");
+            e
+        })?;
+        Ok(build_entry(p.to_string(), item))
+    }
 
+    async fn stat_with_retry_impl(&self, p: String) -> StorageBackendResult<Entry> {
+        self.try_ensure_refresh_token_by_refresh_token().await?;
+        let r = self.stat_impl(p.as_str()).await;
+        if !is_auth_error(&r) {
+            return r;
+        }
+        self.refresh_token_by_refresh_token().await?;
+        return self.stat_impl(p.as_str()).await;
+    }
+
+    async fn get_impl(
+        &self,
+        p: &str,
+        byte_offset: u64,
+        end: Option<u64>,
+    ) -> StorageBackendResult<StreamFile> {
+        let _url = self.root_api() + ":" + p + ":/content";
         let mut headers = self.build_base_header_map().await;
         headers.insert(
             reqwest::header::RANGE,
-            HeaderValue::from_str(format!("bytes={byte_offset}-").as_str()).unwrap(),
+            HeaderValue::from_str(format_range(byte_offset, end).as_str()).unwrap(),
         );
 
-        let resp = {
-            let client = self.build_client()?;
-
-            tokio_runtime()
-                .spawn(async move { client.get(url.clone()).headers(headers).send().await })
-                .await??
-        };
+        let resp = self
+            .send_core(reqwest::Method::GET, &_url, headers, None)
+            .await?;
         let byte_offset = if resp.headers().get(reqwest::header::CONTENT_RANGE).is_some() {
             0
         } else {
             byte_offset
         };
-        let res = resp
-            .error_for_status()
-            .map(|resp| StreamFile::new(resp, byte_offset))?;
-        Ok(res)
+        let res = resp.check_status().map(|resp| {
+            StreamFile::new(resp, byte_offset).with_read_timeout(self.client.read_timeout())
+        })?;
+        match end {
+            Some(end) => Ok(res.take(end.saturating_sub(byte_offset))),
+            None => Ok(res),
+        }
     }
 
     async fn get_with_retry_impl(
         &self,
         p: String,
         byte_offset: u64,
+        end: Option<u64>,
     ) -> StorageBackendResult<StreamFile> {
         self.try_ensure_refresh_token_by_refresh_token().await?;
-        let r = self.get_impl(p.as_str(), byte_offset).await;
+        let r = self.get_impl(p.as_str(), byte_offset, end).await;
         if !is_auth_error(&r) {
             return r;
         }
         self.refresh_token_by_refresh_token().await?;
-        return self.get_impl(p.as_str(), byte_offset).await;
+        return self.get_impl(p.as_str(), byte_offset, end).await;
+    }
+
+    async fn send_core(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        headers: reqwest::header::HeaderMap,
+        body: Option<reqwest::Body>,
+    ) -> StorageBackendResult<reqwest::Response> {
+        let url = reqwest::Url::parse(url)
+            .map_err(|e| StorageBackendError::UrlParseError(e.to_string()))?;
+
+        send_with_retry(&self.retry_policy, &self.client, method, url, headers, body).await
+    }
+
+    async fn send_json_core(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        body: Option<serde_json::Value>,
+    ) -> StorageBackendResult<reqwest::Response> {
+        let mut headers = self.build_base_header_map().await;
+        let body = match body {
+            Some(body) => {
+                headers.insert(
+                    reqwest::header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/json"),
+                );
+                Some(reqwest::Body::from(serde_json::to_vec(&body)?))
+            }
+            None => None,
+        };
+        self.send_core(method, url, headers, body).await
+    }
+
+    async fn put_simple_impl(&self, p: &str, buf: Bytes) -> StorageBackendResult<()> {
+        let url = self.root_api() + ":" + p + ":/content";
+
+        let mut headers = self.build_base_header_map().await;
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/octet-stream"),
+        );
+        headers.insert(
+            reqwest::header::CONTENT_LENGTH,
+            HeaderValue::from(buf.len()),
+        );
+
+        self.send_core(
+            reqwest::Method::PUT,
+            &url,
+            headers,
+            Some(reqwest::Body::from(buf)),
+        )
+        .await?
+        .check_status()?;
+        Ok(())
+    }
+
+    async fn put_upload_fragment(
+        &self,
+        upload_url: &str,
+        fragment: Vec<u8>,
+        start: u64,
+        total: u64,
+    ) -> StorageBackendResult<()> {
+        let end = start + fragment.len() as u64;
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::CONTENT_RANGE,
+            HeaderValue::from_str(format!("bytes {start}-{}/{total}", end - 1).as_str()).unwrap(),
+        );
+        headers.insert(
+            reqwest::header::CONTENT_LENGTH,
+            HeaderValue::from(fragment.len()),
+        );
+
+        // The upload url is pre-authenticated and must not carry the bearer token
+        self.send_core(
+            reqwest::Method::PUT,
+            upload_url,
+            headers,
+            Some(reqwest::Body::from(fragment)),
+        )
+        .await?
+        .check_status()?;
+        Ok(())
+    }
+
+    /// Returns the pre-authenticated url the fragments go to.
+    async fn create_upload_session(&self, p: &str) -> StorageBackendResult<String> {
+        let url = self.root_api() + ":" + p + ":/createUploadSession";
+        let body = serde_json::json!({
+            "item": {
+                "@microsoft.graph.conflictBehavior": "replace",
+            }
+        });
+        let resp = self
+            .send_json_core(reqwest::Method::POST, &url, Some(body))
+            .await?
+            .check_status()?;
+        let text = resp.text().await?;
+        let session = serde_json::from_str::<onedrive_types::UploadSessionResp>(&text)?;
+        Ok(session.upload_url)
+    }
+
+    async fn put_session_impl(
+        &self,
+        upload_url: &str,
+        file: UploadFile,
+    ) -> StorageBackendResult<()> {
+        let total = file.size();
+        let rx = file.into_rx();
+        let mut start: u64 = 0;
+        let mut fragment: Vec<u8> = Vec::with_capacity(ONEDRIVE_UPLOAD_FRAGMENT_SIZE);
+        while let Ok(chunk) = rx.recv().await {
+            let mut chunk = chunk?;
+            while !chunk.is_empty() {
+                let n = chunk
+                    .len()
+                    .min(ONEDRIVE_UPLOAD_FRAGMENT_SIZE - fragment.len());
+                fragment.extend_from_slice(&chunk.split_to(n));
+
+                if fragment.len() == ONEDRIVE_UPLOAD_FRAGMENT_SIZE {
+                    let len = fragment.len() as u64;
+                    let buf = std::mem::replace(
+                        &mut fragment,
+                        Vec::with_capacity(ONEDRIVE_UPLOAD_FRAGMENT_SIZE),
+                    );
+                    self.put_upload_fragment(upload_url, buf, start, total)
+                        .await?;
+                    start += len;
+                }
+            }
+        }
+        if !fragment.is_empty() {
+            self.put_upload_fragment(upload_url, fragment, start, total)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn put_with_retry_impl(&self, p: String, file: UploadFile) -> StorageBackendResult<()> {
+        self.try_ensure_refresh_token_by_refresh_token().await?;
+        if file.size() <= ONEDRIVE_SIMPLE_UPLOAD_LIMIT {
+            // Small enough to keep around, so that it can be sent again after a refresh
+            let rx = file.into_rx();
+            let mut buf: Vec<u8> = Default::default();
+            while let Ok(chunk) = rx.recv().await {
+                buf.extend_from_slice(&chunk?);
+            }
+            let buf = Bytes::from(buf);
+
+            let r = self.put_simple_impl(p.as_str(), buf.clone()).await;
+            if !is_auth_error(&r) {
+                return r;
+            }
+            self.refresh_token_by_refresh_token().await?;
+            return self.put_simple_impl(p.as_str(), buf).await;
+        }
+
+        // Only creating the session needs the token, the fragments go to a signed url
+        let r = self.create_upload_session(p.as_str()).await;
+        let upload_url = if !is_auth_error(&r) {
+            r?
+        } else {
+            self.refresh_token_by_refresh_token().await?;
+            self.create_upload_session(p.as_str()).await?
+        };
+        self.put_session_impl(&upload_url, file).await
+    }
+
+    async fn mkdir_impl(&self, dir: &str) -> StorageBackendResult<()> {
+        let (parent, name) = split_parent(dir);
+        let url = if parent.is_empty() {
+            self.root_api() + "/children"
+        } else {
+            self.root_api() + ":" + parent + ":/children"
+        };
+        let body = serde_json::json!({
+            "name": name,
+            "folder": {},
+            "@microsoft.graph.conflictBehavior": "fail",
+        });
+
+        self.send_json_core(reqwest::Method::POST, &url, Some(body))
+            .await?
+            .check_status()?;
+        Ok(())
     }
 
-    fn build_client(&self) -> StorageBackendResult<reqwest::Client> {
-        build_client()
+    async fn delete_impl(&self, p: &str) -> StorageBackendResult<()> {
+        let url = self.root_api() + ":" + p;
+
+        self.send_json_core(reqwest::Method::DELETE, &url, None)
+            .await?
+            .check_status()?;
+        Ok(())
+    }
+
+    async fn parent_reference(&self, parent: &str) -> StorageBackendResult<serde_json::Value> {
+        match &self.drive {
+            OneDriveDrive::Personal => Ok(serde_json::json!({
+                "path": "/drive/root:".to_string() + parent,
+            })),
+            OneDriveDrive::Drive { drive_id } => Ok(serde_json::json!({
+                "driveId": drive_id,
+                "path": format!("/drives/{drive_id}/root:{parent}"),
+            })),
+            // Paths only work relative to a drive root, a shared folder is addressed by id
+            OneDriveDrive::Item { drive_id, .. } => {
+                let url = if parent.is_empty() {
+                    self.root_api()
+                } else {
+                    self.root_api() + ":" + parent
+                };
+                let text = self
+                    .list_core_by_url(&url)
+                    .await?
+                    .check_status()?
+                    .text()
+                    .await?;
+                let item = serde_json::from_str::<onedrive_types::ItemId>(&text)?;
+                Ok(serde_json::json!({
+                    "driveId": drive_id,
+                    "id": item.id,
+                }))
+            }
+        }
+    }
+
+    async fn rename_impl(&self, from: &str, to: &str) -> StorageBackendResult<()> {
+        let url = self.root_api() + ":" + from;
+        let (parent, name) = split_parent(to);
+        let mut body = serde_json::json!({
+            "name": name,
+        });
+        if split_parent(from).0 != parent {
+            body["parentReference"] = self.parent_reference(parent).await?;
+        }
+
+        self.send_json_core(reqwest::Method::PATCH, &url, Some(body))
+            .await?
+            .check_status()?;
+        Ok(())
+    }
+
+    async fn get_all_pages<T: serde::de::DeserializeOwned>(
+        &self,
+        url: String,
+    ) -> StorageBackendResult<Vec<T>> {
+        let mut url = url;
+        let mut ret: Vec<T> = Default::default();
+        loop {
+            let text = self
+                .list_core_by_url(&url)
+                .await?
+                .check_status()?
+                .text()
+                .await?;
+            let page = serde_json::from_str::<onedrive_types::Page<T>>(&text)?;
+            ret.extend(page.value.into_iter().flatten());
+            match page.next_link {
+                Some(next_link) => url = next_link,
+                None => break,
+            }
+        }
+        Ok(ret)
+    }
+
+    async fn list_sharepoint_drives(&self) -> StorageBackendResult<Vec<OneDriveDriveInfo>> {
+        let graph_api = &self.app.graph_api;
+        let sites = self
+            .get_all_pages::<onedrive_types::Site>(format!("{graph_api}/sites?search=*"))
+            .await?;
+
+        let mut ret: Vec<OneDriveDriveInfo> = Default::default();
+        for site in sites {
+            let drives = self
+                .get_all_pages::<onedrive_types::Drive>(format!(
+                    "{graph_api}/sites/{}/drives",
+                    site.id
+                ))
+                .await?;
+            let site_name = site.display_name.or(site.name).unwrap_or_default();
+            for drive in drives {
+                if drive.drive_type.as_deref() != Some("documentLibrary") {
+                    continue;
+                }
+                ret.push(OneDriveDriveInfo {
+                    name: format!("{site_name} / {}", drive.name),
+                    kind: OneDriveDriveKind::SharePoint,
+                    drive: OneDriveDrive::Drive { drive_id: drive.id },
+                });
+            }
+        }
+        Ok(ret)
+    }
+
+    async fn list_drives_impl(&self) -> StorageBackendResult<Vec<OneDriveDriveInfo>> {
+        let mut ret = vec![OneDriveDriveInfo {
+            name: "OneDrive".to_string(),
+            kind: OneDriveDriveKind::Personal,
+            drive: OneDriveDrive::Personal,
+        }];
+
+        let shared = self
+            .get_all_pages::<onedrive_types::SharedItem>(format!(
+                "{}/me/drive/sharedWithMe",
+                self.app.graph_api
+            ))
+            .await?;
+        for item in shared {
+            if item.remote_item.folder.is_none() {
+                continue;
+            }
+            ret.push(OneDriveDriveInfo {
+                name: item.name,
+                kind: OneDriveDriveKind::SharedWithMe,
+                drive: OneDriveDrive::Item {
+                    drive_id: item.remote_item.parent_reference.drive_id,
+                    item_id: item.remote_item.id,
+                },
+            });
+        }
+
+        // Personal accounts have no SharePoint at all, that shouldn't hide the other drives
+        let sharepoint = self.list_sharepoint_drives().await;
+        match sharepoint {
+            Ok(drives) => ret.extend(drives),
+            Err(e) if is_unauthorized(&e) => return Err(e),
+            Err(e) => tracing::warn!("list sharepoint drives fail: {e:?}"),
+        }
+        Ok(ret)
+    }
+
+    async fn list_drives_with_retry_impl(&self) -> StorageBackendResult<Vec<OneDriveDriveInfo>> {
+        self.try_ensure_refresh_token_by_refresh_token().await?;
+        let r = self.list_drives_impl().await;
+        if !is_auth_error(&r) {
+            return r;
+        }
+        self.refresh_token_by_refresh_token().await?;
+        self.list_drives_impl().await
+    }
+
+    fn is_delta_root(&self, item: &onedrive_types::DeltaItem) -> bool {
+        match &self.drive {
+            OneDriveDrive::Item { item_id, .. } => item.id == *item_id,
+            _ => item.root.is_some(),
+        }
+    }
+
+    /// Follows `nextLink` until the `deltaLink` to resume from next time. `None` if the
+    /// link expired and a full sync is needed.
+    async fn get_delta_pages(
+        &self,
+        url: String,
+    ) -> StorageBackendResult<Option<(Vec<onedrive_types::DeltaItem>, String)>> {
+        let mut url = url;
+        let mut ret: Vec<onedrive_types::DeltaItem> = Default::default();
+        loop {
+            let resp = self.list_core_by_url(&url).await?;
+            if resp.status() == StatusCode::GONE {
+                return Ok(None);
+            }
+            let text = resp.check_status()?.text().await?;
+            let page = serde_json::from_str::<onedrive_types::DeltaPage>(&text)?;
+            ret.extend(page.value.into_iter().flatten());
+            match (page.next_link, page.delta_link) {
+                (Some(next_link), _) => url = next_link,
+                (None, Some(delta_link)) => return Ok(Some((ret, delta_link))),
+                (None, None) => {
+                    return Err(StorageBackendError::UnexpectedResponse(
+                        "delta page without nextLink or deltaLink".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+
+    async fn delta_changes(
+        &self,
+        dir: &str,
+        cursor: Option<&str>,
+    ) -> StorageBackendResult<StorageChanges> {
+        let old = cursor
+            .and_then(|v| v.strip_prefix(DELTA_CURSOR_PREFIX))
+            .and_then(|v| serde_json::from_str::<DeltaCursor>(v).ok());
+        if let Some(old) = old {
+            match self.get_delta_pages(old.delta_link.clone()).await? {
+                Some((items, delta_link)) => {
+                    if let Some(changes) = self.apply_delta(dir, &old, items, delta_link)? {
+                        return Ok(changes);
+                    }
+                    tracing::info!("onedrive folder moved in from outside {dir}, start over");
+                }
+                None => tracing::info!("onedrive delta link expired, start over"),
+            }
+        }
+
+        let (items, delta_link) = self
+            .get_delta_pages(self.root_api() + "/delta")
+            .await?
+            .ok_or_else(|| {
+                StorageBackendError::UnexpectedResponse("fresh delta link expired".to_string())
+            })?;
+        let changes = self.apply_delta(dir, &Default::default(), items, delta_link)?;
+        // Nothing was trimmed before a full sync, so no folder can miss its children
+        changes.ok_or_else(|| {
+            StorageBackendError::UnexpectedResponse("delta folder without children".to_string())
+        })
+    }
+
+    /// `None` if `dir` or a folder above it showed up without the children it reports. It
+    /// was moved in from outside the kept tree, and only a full sync lists them.
+    fn apply_delta(
+        &self,
+        dir: &str,
+        old: &DeltaCursor,
+        items: Vec<onedrive_types::DeltaItem>,
+        delta_link: String,
+    ) -> StorageBackendResult<Option<StorageChanges>> {
+        let reset = old.delta_link.is_empty();
+        let mut new = old.clone();
+        new.delta_link = delta_link;
+        let mut touched: Vec<(String, bool)> = Default::default();
+        let mut child_counts: Vec<(String, u64)> = Default::default();
+        for item in items {
+            if self.is_delta_root(&item) {
+                new.root_id = Some(item.id);
+                continue;
+            }
+            if item.deleted.is_some() {
+                new.items.remove(&item.id);
+                touched.push((item.id, true));
+                continue;
+            }
+            let node = DeltaNode {
+                parent: item.parent_reference.and_then(|v| v.id),
+                name: item.name.unwrap_or_default(),
+                is_dir: item.folder.is_some(),
+                size: item.size,
+                etag: item.e_tag,
+                modified_time: item
+                    .last_modified_date_time
+                    .as_deref()
+                    .and_then(parse_rfc3339_time)
+                    .map(|v| v.as_millis() as u64),
+                content_type: item.file.and_then(|v| v.mime_type),
+            };
+            let is_dir = node.is_dir;
+            if let Some(count) = item
+                .folder
+                .as_ref()
+                .and_then(|v| v.get("childCount"))
+                .and_then(|v| v.as_u64())
+            {
+                child_counts.push((item.id.clone(), count));
+            }
+            new.items.insert(item.id.clone(), node);
+            touched.push((item.id, is_dir));
+        }
+
+        if !reset {
+            let moved_in = child_counts.iter().any(|(id, count)| {
+                !old.items.contains_key(id)
+                    && new.path_of(id).is_some_and(|p| is_under(&p, dir))
+                    && new.child_count(id) < *count
+            });
+            if moved_in {
+                return Ok(None);
+            }
+        }
+
+        // Removals go first, so that a path both vacated and refilled ends up upserted
+        let mut removed: Vec<String> = Default::default();
+        let mut upserted: BTreeMap<String, Entry> = Default::default();
+        for (id, is_dir) in touched {
+            let old_path = old.path_of(&id);
+            let new_path = new.path_of(&id);
+            if old_path.is_some() && old_path != new_path {
+                removed.extend(old_path);
+            }
+            let Some(new_path) = new_path else {
+                continue;
+            };
+            if !is_dir {
+                upserted.extend(new.entry_of(&id, new_path.clone()).map(|v| (new_path, v)));
+            } else if old.path_of(&id).is_some_and(|v| v != new_path) {
+                // Children of a moved folder are not part of the feed
+                for child in new.items.keys() {
+                    let Some(path) = new.path_of(child) else {
+                        continue;
+                    };
+                    if !new.items[child].is_dir && is_under(&new_path, &path) {
+                        upserted.extend(new.entry_of(child, path.clone()).map(|v| (path, v)));
+                    }
+                }
+            }
+        }
+
+        let mut changes: Vec<StorageChange> = removed
+            .into_iter()
+            .filter(|v| is_removal_in(dir, v))
+            .map(StorageChange::Removed)
+            .collect();
+        changes.extend(
+            upserted
+                .into_values()
+                .filter(|v| is_child(dir, &v.path))
+                .map(StorageChange::Upsert),
+        );
+        new.retain_for(dir);
+        let cursor = DELTA_CURSOR_PREFIX.to_string() + &serde_json::to_string(&new)?;
+        Ok(Some(StorageChanges {
+            changes,
+            cursor,
+            reset,
+        }))
+    }
+
+    async fn changes_since_impl(
+        &self,
+        dir: &str,
+        cursor: Option<&str>,
+    ) -> StorageBackendResult<StorageChanges> {
+        if cursor.is_some_and(is_snapshot_cursor) {
+            return snapshot_changes(self, dir, cursor).await;
+        }
+        match self.delta_changes(dir, cursor).await {
+            // Some drives, e.g. folders shared from a business account, have no delta feed
+            Err(e)
+                if e.status()
+                    .is_some_and(|v| v.is_client_error() && v != StatusCode::UNAUTHORIZED) =>
+            {
+                tracing::warn!("onedrive delta fail, fallback to listing: {e:?}");
+                snapshot_changes(self, dir, None).await
+            }
+            r => r,
+        }
+    }
+
+    async fn changes_since_with_retry_impl(
+        &self,
+        dir: String,
+        cursor: Option<String>,
+    ) -> StorageBackendResult<StorageChanges> {
+        self.try_ensure_refresh_token_by_refresh_token().await?;
+        let r = self.changes_since_impl(&dir, cursor.as_deref()).await;
+        if !is_auth_error(&r) {
+            return r;
+        }
+        self.refresh_token_by_refresh_token().await?;
+        self.changes_since_impl(&dir, cursor.as_deref()).await
+    }
+
+    async fn mkdir_with_retry_impl(&self, dir: String) -> StorageBackendResult<()> {
+        self.try_ensure_refresh_token_by_refresh_token().await?;
+        let r = self.mkdir_impl(dir.as_str()).await;
+        if !is_auth_error(&r) {
+            return r;
+        }
+        self.refresh_token_by_refresh_token().await?;
+        self.mkdir_impl(dir.as_str()).await
+    }
+
+    async fn delete_with_retry_impl(&self, p: String) -> StorageBackendResult<()> {
+        self.try_ensure_refresh_token_by_refresh_token().await?;
+        let r = self.delete_impl(p.as_str()).await;
+        if !is_auth_error(&r) {
+            return r;
+        }
+        self.refresh_token_by_refresh_token().await?;
+        self.delete_impl(p.as_str()).await
+    }
+
+    async fn rename_with_retry_impl(&self, from: String, to: String) -> StorageBackendResult<()> {
+        self.try_ensure_refresh_token_by_refresh_token().await?;
+        let r = self.rename_impl(from.as_str(), to.as_str()).await;
+        if !is_auth_error(&r) {
+            return r;
+        }
+        self.refresh_token_by_refresh_token().await?;
+        self.rename_impl(from.as_str(), to.as_str()).await
     }
 }
 
@@ -351,14 +1373,890 @@ impl StorageBackend for OneDriveBackend {
         Box::pin(self.list_with_retry_impl(dir))
     }
 
+    fn stat(&self, p: String) -> BoxFuture<StorageBackendResult<Entry>> {
+        Box::pin(self.stat_with_retry_impl(p))
+    }
+
     fn get(&self, p: String, byte_offset: u64) -> BoxFuture<StorageBackendResult<StreamFile>> {
-        Box::pin(self.get_with_retry_impl(p, byte_offset))
+        Box::pin(self.get_with_retry_impl(p, byte_offset, None))
+    }
+
+    fn get_range(
+        &self,
+        p: String,
+        start: u64,
+        end: u64,
+    ) -> BoxFuture<StorageBackendResult<StreamFile>> {
+        Box::pin(self.get_with_retry_impl(p, start, Some(end)))
+    }
+
+    fn put(&self, p: String, file: UploadFile) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async move {
+            let r = self.put_with_retry_impl(p, file).await;
+            self.check_write_scope(r).await
+        })
+    }
+
+    fn mkdir(&self, dir: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async move {
+            let r = self.mkdir_with_retry_impl(dir).await;
+            self.check_write_scope(r).await
+        })
+    }
+
+    fn delete(&self, p: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async move {
+            let r = self.delete_with_retry_impl(p).await;
+            self.check_write_scope(r).await
+        })
+    }
+
+    fn rename(&self, from: String, to: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async move {
+            let r = self.rename_with_retry_impl(from, to).await;
+            self.check_write_scope(r).await
+        })
+    }
+
+    fn changes_since(
+        &self,
+        dir: String,
+        cursor: Option<String>,
+    ) -> BoxFuture<StorageBackendResult<StorageChanges>> {
+        Box::pin(self.changes_since_with_retry_impl(dir, cursor))
     }
 }
 
 impl OneDriveBackend {
-    pub async fn request_refresh_token(code: String) -> StorageBackendResult<String> {
-        let authed = refresh_token_by_code_impl(code).await?;
+    /// Redeems the code from the sign-in redirect, `code_verifier` is the one whose challenge
+    /// went into [`OneDriveAppConfig::authorize_url`]. Goes through the same network settings
+    /// as the backend built from the token afterwards.
+    pub async fn request_refresh_token(
+        app: &OneDriveAppConfig,
+        code: String,
+        code_verifier: String,
+        connect_timeout: Duration,
+        network: HttpNetworkConfig,
+    ) -> StorageBackendResult<String> {
+        let client = SharedHttpClient::new(connect_timeout, Default::default(), network);
+        let authed = refresh_token_by_code_impl(&client, app, &code, &code_verifier).await?;
         Ok(authed.refresh_token)
     }
+
+    /// The drives a storage can be rooted at: the personal one, folders shared with the
+    /// user and the document libraries of SharePoint sites they can reach.
+    pub async fn list_drives(&self) -> StorageBackendResult<Vec<OneDriveDriveInfo>> {
+        self.list_drives_with_retry_impl().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use hyper::{Body, Request, Response};
+    use tokio::task::JoinHandle;
+
+    use bytes::Bytes;
+
+    use crate::{StorageBackend, StorageBackendError, StorageChange, StorageChanges, UploadFile};
+
+    use super::{
+        BuildOneDriveArg, DeltaCursor, OneDriveAppConfig, OneDriveBackend, OneDriveDrive,
+        OneDriveDriveInfo, OneDriveDriveKind, OneDrivePkce, DELTA_CURSOR_PREFIX,
+        ONEDRIVE_SIMPLE_UPLOAD_LIMIT, ONEDRIVE_UPLOAD_FRAGMENT_SIZE,
+    };
+
+    struct SetupServerRes {
+        addr: String,
+        handle: JoinHandle<()>,
+    }
+    impl Drop for SetupServerRes {
+        fn drop(&mut self) {
+            self.handle.abort();
+        }
+    }
+
+    #[derive(Default)]
+    struct TokenServer {
+        received: Mutex<Vec<String>>,
+    }
+
+    /// Answers every refresh with `r{n + 1}` for an incoming `r{n}`, except `stable` which
+    /// is handed back unchanged.
+    async fn handle(server: Arc<TokenServer>, req: Request<Body>) -> Response<Body> {
+        assert_eq!(req.uri().path(), "/token");
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("grant_type=refresh_token"));
+        let refresh_token = body
+            .split('&')
+            .find_map(|kv| kv.strip_prefix("refresh_token="))
+            .unwrap()
+            .to_string();
+        server.received.lock().unwrap().push(refresh_token.clone());
+
+        let next = match refresh_token.strip_prefix('r') {
+            Some(n) => format!("r{}", n.parse::<u32>().unwrap() + 1),
+            None => refresh_token,
+        };
+        Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(format!(
+                r#"{{"access_token":"a-{next}","refresh_token":"{next}"}}"#
+            )))
+            .unwrap()
+    }
+
+    async fn setup_server(token_server: Arc<TokenServer>) -> SetupServerRes {
+        let make_svc = hyper::service::make_service_fn(move |_conn| {
+            let token_server = token_server.clone();
+            async move {
+                Ok::<_, Infallible>(hyper::service::service_fn(move |req: Request<Body>| {
+                    let token_server = token_server.clone();
+                    async move { Ok::<_, Infallible>(handle(token_server, req).await) }
+                }))
+            }
+        });
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let server = hyper::Server::bind(&addr).serve(make_svc);
+        let addr = format!("http://{}", server.local_addr());
+        let handle = tokio::spawn(async move {
+            let _ = server.await;
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        SetupServerRes { addr, handle }
+    }
+
+    fn build_backend(
+        server: &SetupServerRes,
+        code: &str,
+        rotated: Arc<Mutex<Vec<String>>>,
+    ) -> OneDriveBackend {
+        OneDriveBackend::new(BuildOneDriveArg {
+            code: code.to_string(),
+            app: OneDriveAppConfig {
+                login_api: server.addr.clone(),
+                ..Default::default()
+            },
+            drive: Default::default(),
+            connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+            retry_policy: Default::default(),
+            on_refresh_token: Some(Arc::new(move |token| {
+                rotated.lock().unwrap().push(token);
+            })),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_rotated_refresh_token_is_reported_and_reused() {
+        let token_server: Arc<TokenServer> = Default::default();
+        let server = setup_server(token_server.clone()).await;
+        let rotated: Arc<Mutex<Vec<String>>> = Default::default();
+        let backend = build_backend(&server, "r0", rotated.clone());
+
+        backend
+            .try_ensure_refresh_token_by_refresh_token()
+            .await
+            .unwrap();
+        // Already signed in, nothing to refresh
+        backend
+            .try_ensure_refresh_token_by_refresh_token()
+            .await
+            .unwrap();
+        backend.refresh_token_by_refresh_token().await.unwrap();
+        backend.refresh_token_by_refresh_token().await.unwrap();
+
+        assert_eq!(
+            *token_server.received.lock().unwrap(),
+            vec!["r0", "r1", "r2"]
+        );
+        assert_eq!(*rotated.lock().unwrap(), vec!["r1", "r2", "r3"]);
+        let r = backend.auth.read().await;
+        assert_eq!(r.as_ref().unwrap().access_token, "a-r3");
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_refresh_token_is_not_reported() {
+        let token_server: Arc<TokenServer> = Default::default();
+        let server = setup_server(token_server.clone()).await;
+        let rotated: Arc<Mutex<Vec<String>>> = Default::default();
+        let backend = build_backend(&server, "stable", rotated.clone());
+
+        backend.refresh_token_by_refresh_token().await.unwrap();
+        backend.refresh_token_by_refresh_token().await.unwrap();
+
+        assert_eq!(
+            *token_server.received.lock().unwrap(),
+            vec!["stable", "stable"]
+        );
+        assert!(rotated.lock().unwrap().is_empty());
+    }
+
+    const CLIENT_ID: &str = "test-client";
+    const REDIRECT_URI: &str = "easem://oauth2redirect/";
+    const AUTH_CODE: &str = "auth-code";
+    const SONG: &[u8] = b"0123456789";
+    const LYRIC: &[u8] = b"[00:01.00]hello";
+
+    /// Plays both login.microsoftonline.com and graph.microsoft.com.
+    #[derive(Default)]
+    struct FakeMicrosoft {
+        code_challenge: Mutex<Option<String>>,
+        access_token: Mutex<Option<String>>,
+        lyric: Mutex<Vec<u8>>,
+        content_ranges: Mutex<Vec<String>>,
+        uploaded: Mutex<Vec<u8>>,
+        /// Listed in the token responses when set, writes are refused unless it can write.
+        granted_scope: Mutex<Option<String>>,
+    }
+
+    fn parse_form(text: &str) -> std::collections::HashMap<String, String> {
+        text.split('&')
+            .filter_map(|kv| kv.split_once('='))
+            .map(|(k, v)| (k.to_string(), urlencoding::decode(v).unwrap().to_string()))
+            .collect()
+    }
+
+    fn status(code: hyper::StatusCode) -> Response<Body> {
+        Response::builder()
+            .status(code)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn json(body: String) -> Response<Body> {
+        Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    async fn handle_fake_microsoft(fake: Arc<FakeMicrosoft>, req: Request<Body>) -> Response<Body> {
+        let path = req.uri().path().to_string();
+        match path.as_str() {
+            "/common/oauth2/v2.0/authorize" => {
+                let query = parse_form(req.uri().query().unwrap_or_default());
+                assert_eq!(query["client_id"], CLIENT_ID);
+                assert_eq!(query["redirect_uri"], REDIRECT_URI);
+                assert_eq!(query["response_type"], "code");
+                assert_eq!(query["code_challenge_method"], "S256");
+                *fake.code_challenge.lock().unwrap() = Some(query["code_challenge"].clone());
+                Response::builder()
+                    .status(hyper::StatusCode::FOUND)
+                    .header(
+                        hyper::header::LOCATION,
+                        format!("{REDIRECT_URI}?code={AUTH_CODE}"),
+                    )
+                    .body(Body::empty())
+                    .unwrap()
+            }
+            "/common/oauth2/v2.0/token" => {
+                let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                let form = parse_form(std::str::from_utf8(&body).unwrap());
+                assert_eq!(form["client_id"], CLIENT_ID);
+                let (access_token, refresh_token) = match form["grant_type"].as_str() {
+                    "authorization_code" => {
+                        let challenge = fake.code_challenge.lock().unwrap().clone();
+                        let verified = OneDrivePkce::from_verifier(form["code_verifier"].clone())
+                            .code_challenge;
+                        if form["code"] != AUTH_CODE || challenge != Some(verified) {
+                            return status(hyper::StatusCode::BAD_REQUEST);
+                        }
+                        ("access-1".to_string(), "refresh-1".to_string())
+                    }
+                    "refresh_token" => match form["refresh_token"]
+                        .strip_prefix("refresh-")
+                        .and_then(|n| n.parse::<u32>().ok())
+                    {
+                        Some(n) => (format!("access-{}", n + 1), format!("refresh-{}", n + 1)),
+                        None => return status(hyper::StatusCode::BAD_REQUEST),
+                    },
+                    _ => return status(hyper::StatusCode::BAD_REQUEST),
+                };
+                *fake.access_token.lock().unwrap() = Some(access_token.clone());
+                let scope = match fake.granted_scope.lock().unwrap().as_ref() {
+                    Some(scope) => format!(r#","scope":"{scope}""#),
+                    None => String::new(),
+                };
+                json(format!(
+                    r#"{{"access_token":"{access_token}","refresh_token":"{refresh_token}"{scope}}}"#
+                ))
+            }
+            "/upload/big" => {
+                // Pre-authenticated, a bearer token here is rejected by the real service too
+                assert_eq!(req.method(), hyper::Method::PUT);
+                if req.headers().contains_key(hyper::header::AUTHORIZATION) {
+                    return status(hyper::StatusCode::UNAUTHORIZED);
+                }
+                let range = req.headers()[hyper::header::CONTENT_RANGE]
+                    .to_str()
+                    .unwrap()
+                    .to_string();
+                fake.content_ranges.lock().unwrap().push(range);
+                let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                fake.uploaded.lock().unwrap().extend_from_slice(&body);
+                status(hyper::StatusCode::ACCEPTED)
+            }
+            _ => {
+                let authorization = req
+                    .headers()
+                    .get(hyper::header::AUTHORIZATION)
+                    .and_then(|v| v.to_str().ok())
+                    .map(|v| v.to_string());
+                let expected = fake
+                    .access_token
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .map(|v| format!("bearer {v}"));
+                if expected.is_none() || authorization != expected {
+                    return status(hyper::StatusCode::UNAUTHORIZED);
+                }
+                match path.as_str() {
+                    "/v1.0/me/drive/root/children" => json(
+                        r#"{"value":[
+                            {"name":"Music","folder":{"childCount":1}},
+                            {"name":"a.mp3","size":10,"file":{"mimeType":"audio/mpeg"},
+                             "eTag":"e1","lastModifiedDateTime":"2024-05-01T08:00:00Z"}
+                        ]}"#
+                        .to_string(),
+                    ),
+                    "/v1.0/me/drive/root:/a.mp3:/content" => Response::builder()
+                        .header(hyper::header::CONTENT_TYPE, "audio/mpeg")
+                        .header(hyper::header::CONTENT_LENGTH, SONG.len())
+                        .body(Body::from(SONG))
+                        .unwrap(),
+                    "/v1.0/me/drive/root:/a.lrc:/content" => {
+                        assert_eq!(req.method(), hyper::Method::PUT);
+                        let granted = fake.granted_scope.lock().unwrap().clone();
+                        if granted.is_some_and(|scope| !scope.contains("ReadWrite")) {
+                            return status(hyper::StatusCode::FORBIDDEN);
+                        }
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        *fake.lyric.lock().unwrap() = body.to_vec();
+                        status(hyper::StatusCode::CREATED)
+                    }
+                    "/v1.0/me/drive/root:/locked.lrc:/content" => {
+                        status(hyper::StatusCode::FORBIDDEN)
+                    }
+                    "/v1.0/me/drive/root:/big.flac:/createUploadSession" => {
+                        assert_eq!(req.method(), hyper::Method::POST);
+                        let host = req.headers()[hyper::header::HOST].to_str().unwrap();
+                        json(format!(r#"{{"uploadUrl":"http://{host}/upload/big"}}"#))
+                    }
+                    "/v1.0/me/drive/sharedWithMe" => json(
+                        r#"{"value":[
+                            {"name":"Band","remoteItem":{"id":"i-shared","folder":{"childCount":2},
+                             "parentReference":{"driveId":"d-shared"}}},
+                            {"name":"demo.mp3","remoteItem":{"id":"i-file","file":{},
+                             "parentReference":{"driveId":"d-shared"}}}
+                        ]}"#
+                        .to_string(),
+                    ),
+                    "/v1.0/sites" => {
+                        assert_eq!(req.uri().query(), Some("search=*"));
+                        json(r#"{"value":[{"id":"s1","displayName":"Team"}]}"#.to_string())
+                    }
+                    "/v1.0/sites/s1/drives" => json(
+                        r#"{"value":[
+                            {"id":"d-lib","name":"Documents","driveType":"documentLibrary"},
+                            {"id":"d-other","name":"Preservation","driveType":"business"}
+                        ]}"#
+                        .to_string(),
+                    ),
+                    "/v1.0/drives/d-lib/root/children" => json(
+                        r#"{"value":[{"name":"library.flac","size":10,"file":{}}]}"#.to_string(),
+                    ),
+                    "/v1.0/me/drive/root/delta" => {
+                        let host = req.headers()[hyper::header::HOST].to_str().unwrap();
+                        let link = format!("http://{host}/v1.0/me/drive/root/delta");
+                        match req.uri().query() {
+                            None => json(format!(
+                                r#"{{"value":[
+                                    {{"id":"root","name":"root","root":{{}},"folder":{{"childCount":2}}}},
+                                    {{"id":"m","name":"Music","folder":{{"childCount":1}},
+                                     "parentReference":{{"id":"root"}}}},
+                                    {{"id":"f1","name":"a.mp3","size":10,"eTag":"e1",
+                                     "file":{{"mimeType":"audio/mpeg"}},"parentReference":{{"id":"m"}}}}
+                                ],"@odata.nextLink":"{link}?page=2"}}"#
+                            )),
+                            Some("page=2") => json(format!(
+                                r#"{{"value":[
+                                    {{"id":"f2","name":"b.mp3","size":20,"eTag":"e2","file":{{}},
+                                     "parentReference":{{"id":"root"}}}}
+                                ],"@odata.deltaLink":"{link}?token=t1"}}"#
+                            )),
+                            Some("token=t1") => json(format!(
+                                r#"{{"value":[
+                                    {{"id":"m","name":"Songs","folder":{{"childCount":2}},
+                                     "parentReference":{{"id":"root"}}}},
+                                    {{"id":"f2","deleted":{{}},"parentReference":{{"id":"root"}}}},
+                                    {{"id":"f3","name":"c.mp3","size":30,"eTag":"e3","file":{{}},
+                                     "parentReference":{{"id":"m"}}}}
+                                ],"@odata.deltaLink":"{link}?token=t2"}}"#
+                            )),
+                            _ => status(hyper::StatusCode::GONE),
+                        }
+                    }
+                    "/v1.0/drives/d-shared/items/i-shared:/Live:/children" => {
+                        json(r#"{"value":[{"name":"live.flac","size":10,"file":{}}]}"#.to_string())
+                    }
+                    _ => status(hyper::StatusCode::NOT_FOUND),
+                }
+            }
+        }
+    }
+
+    async fn setup_fake_microsoft(fake: Arc<FakeMicrosoft>) -> SetupServerRes {
+        let make_svc = hyper::service::make_service_fn(move |_conn| {
+            let fake = fake.clone();
+            async move {
+                Ok::<_, Infallible>(hyper::service::service_fn(move |req: Request<Body>| {
+                    let fake = fake.clone();
+                    async move { Ok::<_, Infallible>(handle_fake_microsoft(fake, req).await) }
+                }))
+            }
+        });
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let server = hyper::Server::bind(&addr).serve(make_svc);
+        let addr = format!("http://{}", server.local_addr());
+        let handle = tokio::spawn(async move {
+            let _ = server.await;
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        SetupServerRes { addr, handle }
+    }
+
+    #[test]
+    fn test_pkce_challenge() {
+        // The example from RFC 7636 appendix B
+        let pkce =
+            OneDrivePkce::from_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_string());
+        assert_eq!(
+            pkce.code_challenge,
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+
+        let a = OneDrivePkce::generate();
+        let b = OneDrivePkce::generate();
+        assert_eq!(a.code_verifier.len(), 43);
+        assert_ne!(a.code_verifier, b.code_verifier);
+    }
+
+    fn fake_app(server: &SetupServerRes) -> OneDriveAppConfig {
+        OneDriveAppConfig {
+            graph_api: format!("{}/v1.0", server.addr),
+            login_api: format!("{}/common/oauth2/v2.0", server.addr),
+            client_id: CLIENT_ID.to_string(),
+            redirect_uri: REDIRECT_URI.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The browser part, follows the sign-in page to the app redirect and returns the code.
+    async fn authorize(app: &OneDriveAppConfig, pkce: &OneDrivePkce) -> String {
+        let resp = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(app.authorize_url(pkce))
+            .send()
+            .await
+            .unwrap();
+        let location = resp.headers()[reqwest::header::LOCATION].to_str().unwrap();
+        location
+            .strip_prefix(&format!("{REDIRECT_URI}?code="))
+            .unwrap()
+            .to_string()
+    }
+
+    fn build_fake_backend(
+        app: OneDriveAppConfig,
+        refresh_token: String,
+        drive: OneDriveDrive,
+    ) -> OneDriveBackend {
+        OneDriveBackend::new(BuildOneDriveArg {
+            code: refresh_token,
+            app,
+            drive,
+            connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+            retry_policy: Default::default(),
+            on_refresh_token: None,
+        })
+    }
+
+    fn is_bad_request(e: StorageBackendError) -> bool {
+        match e {
+            StorageBackendError::RequestFail(e) => {
+                e.status() == Some(reqwest::StatusCode::BAD_REQUEST)
+            }
+            _ => false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pkce_sign_in_and_list() {
+        let fake: Arc<FakeMicrosoft> = Default::default();
+        let server = setup_fake_microsoft(fake.clone()).await;
+        let app = fake_app(&server);
+
+        let pkce = OneDrivePkce::generate();
+        let code = authorize(&app, &pkce).await;
+
+        // Rejected with `invalid_grant`, reported as the HTTP error rather than a bad body
+        let wrong = OneDrivePkce::generate();
+        let res = OneDriveBackend::request_refresh_token(
+            &app,
+            code.clone(),
+            wrong.code_verifier,
+            Duration::from_secs(10),
+            Default::default(),
+        )
+        .await;
+        assert!(res.is_err_and(is_bad_request));
+        let res = OneDriveBackend::request_refresh_token(
+            &app,
+            "expired-code".to_string(),
+            pkce.code_verifier.clone(),
+            Duration::from_secs(10),
+            Default::default(),
+        )
+        .await;
+        assert!(res.is_err_and(is_bad_request));
+
+        let refresh_token = OneDriveBackend::request_refresh_token(
+            &app,
+            code,
+            pkce.code_verifier,
+            Duration::from_secs(10),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(refresh_token, "refresh-1");
+
+        let backend = build_fake_backend(app, refresh_token, OneDriveDrive::Personal);
+        let list = backend.list("/".to_string()).await.unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(list[0].is_dir);
+        assert_eq!(list[0].name, "Music");
+        assert_eq!(list[1].name, "a.mp3");
+        assert_eq!(list[1].size, Some(10));
+        assert_eq!(list[1].etag.as_deref(), Some("e1"));
+
+        let file = backend.get("/a.mp3".to_string(), 0).await.unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), SONG);
+    }
+
+    #[tokio::test]
+    async fn test_put_after_token_revoked() {
+        let fake: Arc<FakeMicrosoft> = Default::default();
+        let server = setup_fake_microsoft(fake.clone()).await;
+        let app = fake_app(&server);
+        let pkce = OneDrivePkce::generate();
+        let code = authorize(&app, &pkce).await;
+        let refresh_token = OneDriveBackend::request_refresh_token(
+            &app,
+            code,
+            pkce.code_verifier,
+            Duration::from_secs(10),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let backend = build_fake_backend(app, refresh_token, OneDriveDrive::Personal);
+        backend
+            .try_ensure_refresh_token_by_refresh_token()
+            .await
+            .unwrap();
+
+        *fake.access_token.lock().unwrap() = Some("revoked".to_string());
+        backend
+            .put(
+                "/a.lrc".to_string(),
+                UploadFile::new_from_bytes(Bytes::from_static(LYRIC)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(*fake.lyric.lock().unwrap(), LYRIC);
+
+        // Above the simple upload limit and spanning two fragments, fed in uneven chunks
+        let total = ONEDRIVE_UPLOAD_FRAGMENT_SIZE + 1000;
+        assert!(total as u64 > ONEDRIVE_SIMPLE_UPLOAD_LIMIT);
+        let data: Vec<u8> = (0..total).map(|i| (i % 251) as u8).collect();
+        let (tx, rx) = async_channel::unbounded();
+        for chunk in data.chunks(100_000) {
+            tx.try_send(Ok(Bytes::copy_from_slice(chunk))).unwrap();
+        }
+        tx.close();
+
+        *fake.access_token.lock().unwrap() = Some("revoked".to_string());
+        backend
+            .put("/big.flac".to_string(), UploadFile::new(rx, total as u64))
+            .await
+            .unwrap();
+        assert_eq!(
+            *fake.content_ranges.lock().unwrap(),
+            vec![
+                format!("bytes 0-{}/{total}", ONEDRIVE_UPLOAD_FRAGMENT_SIZE - 1),
+                format!(
+                    "bytes {ONEDRIVE_UPLOAD_FRAGMENT_SIZE}-{}/{total}",
+                    total - 1
+                ),
+            ]
+        );
+        assert!(*fake.uploaded.lock().unwrap() == data);
+    }
+
+    #[tokio::test]
+    async fn test_put_with_read_only_consent() {
+        let fake: Arc<FakeMicrosoft> = Default::default();
+        let server = setup_fake_microsoft(fake.clone()).await;
+        let app = fake_app(&server);
+        // What installs from before writes were asked for still get on refresh
+        *fake.granted_scope.lock().unwrap() = Some(
+            "https://graph.microsoft.com/Files.Read.All https://graph.microsoft.com/Sites.Read.All"
+                .to_string(),
+        );
+        let pkce = OneDrivePkce::generate();
+        let code = authorize(&app, &pkce).await;
+        let refresh_token = OneDriveBackend::request_refresh_token(
+            &app,
+            code,
+            pkce.code_verifier,
+            Duration::from_secs(10),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let backend = build_fake_backend(app.clone(), refresh_token, OneDriveDrive::Personal);
+        let lyric = || UploadFile::new_from_bytes(Bytes::from_static(LYRIC));
+
+        // Reads still work, writes ask for a new sign-in
+        let list = backend.list("/".to_string()).await.unwrap();
+        assert_eq!(list.len(), 2);
+        let err = backend
+            .put("/a.lrc".to_string(), lyric())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageBackendError::ReauthorizationRequired));
+        assert!(err.is_unauthorized());
+
+        // Signed in again with the write scope
+        *fake.granted_scope.lock().unwrap() = Some(super::ONEDRIVE_SCOPE.to_string());
+        let pkce = OneDrivePkce::generate();
+        let code = authorize(&app, &pkce).await;
+        let refresh_token = OneDriveBackend::request_refresh_token(
+            &app,
+            code,
+            pkce.code_verifier,
+            Duration::from_secs(10),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let backend = build_fake_backend(app, refresh_token, OneDriveDrive::Personal);
+        backend.put("/a.lrc".to_string(), lyric()).await.unwrap();
+        assert_eq!(*fake.lyric.lock().unwrap(), LYRIC);
+        // A write refused to a token that can write is the file's own
+        let err = backend
+            .put("/locked.lrc".to_string(), lyric())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StorageBackendError::Forbidden));
+    }
+
+    #[test]
+    fn test_drive_addr() {
+        let drives = [
+            OneDriveDrive::Personal,
+            OneDriveDrive::Drive {
+                drive_id: "b!Xy-9".to_string(),
+            },
+            OneDriveDrive::Item {
+                drive_id: "d1".to_string(),
+                item_id: "01ABC".to_string(),
+            },
+        ];
+        for drive in drives {
+            assert_eq!(OneDriveDrive::from_addr(&drive.to_addr()), drive);
+        }
+        assert_eq!(OneDriveDrive::from_addr(""), OneDriveDrive::Personal);
+        assert_eq!(
+            OneDriveDrive::from_addr("/drives/d1/"),
+            OneDriveDrive::Drive {
+                drive_id: "d1".to_string()
+            }
+        );
+        assert_eq!(
+            OneDriveDrive::from_addr("drives/d1/items"),
+            OneDriveDrive::Personal
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_and_open_drives() {
+        let fake: Arc<FakeMicrosoft> = Default::default();
+        let server = setup_fake_microsoft(fake.clone()).await;
+        let app = fake_app(&server);
+        let pkce = OneDrivePkce::generate();
+        let code = authorize(&app, &pkce).await;
+        let refresh_token = OneDriveBackend::request_refresh_token(
+            &app,
+            code,
+            pkce.code_verifier,
+            Duration::from_secs(10),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let backend =
+            build_fake_backend(app.clone(), refresh_token.clone(), OneDriveDrive::Personal);
+        let drives = backend.list_drives().await.unwrap();
+        assert_eq!(
+            drives,
+            vec![
+                OneDriveDriveInfo {
+                    name: "OneDrive".to_string(),
+                    kind: OneDriveDriveKind::Personal,
+                    drive: OneDriveDrive::Personal,
+                },
+                OneDriveDriveInfo {
+                    name: "Band".to_string(),
+                    kind: OneDriveDriveKind::SharedWithMe,
+                    drive: OneDriveDrive::Item {
+                        drive_id: "d-shared".to_string(),
+                        item_id: "i-shared".to_string(),
+                    },
+                },
+                OneDriveDriveInfo {
+                    name: "Team / Documents".to_string(),
+                    kind: OneDriveDriveKind::SharePoint,
+                    drive: OneDriveDrive::Drive {
+                        drive_id: "d-lib".to_string(),
+                    },
+                },
+            ]
+        );
+
+        let library =
+            build_fake_backend(app.clone(), refresh_token.clone(), drives[2].drive.clone());
+        let list = library.list("/".to_string()).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, "library.flac");
+
+        let shared = build_fake_backend(app, refresh_token, drives[1].drive.clone());
+        let list = shared.list("/Live".to_string()).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].path, "/Live/live.flac");
+    }
+
+    #[tokio::test]
+    async fn test_delta_changes() {
+        let fake: Arc<FakeMicrosoft> = Default::default();
+        let server = setup_fake_microsoft(fake.clone()).await;
+        let app = fake_app(&server);
+        let pkce = OneDrivePkce::generate();
+        let code = authorize(&app, &pkce).await;
+        let refresh_token = OneDriveBackend::request_refresh_token(
+            &app,
+            code,
+            pkce.code_verifier,
+            Duration::from_secs(10),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let backend = build_fake_backend(app, refresh_token, OneDriveDrive::Personal);
+
+        let paths = |changes: &StorageChanges| -> Vec<String> {
+            changes
+                .changes
+                .iter()
+                .map(|v| match v {
+                    StorageChange::Upsert(entry) => format!("+{}", entry.path),
+                    StorageChange::Removed(p) => format!("-{p}"),
+                })
+                .collect()
+        };
+
+        // Only the files right in the folder, not the ones below
+        let first = backend.changes_since("/".to_string(), None).await.unwrap();
+        assert!(first.reset);
+        assert_eq!(paths(&first), vec!["+/b.mp3"]);
+
+        // The folder rename is seen from the root, the new Songs/c.mp3 isn't in it
+        let second = backend
+            .changes_since("/".to_string(), Some(first.cursor))
+            .await
+            .unwrap();
+        assert!(!second.reset);
+        assert_eq!(paths(&second), vec!["-/Music", "-/b.mp3"]);
+
+        // An expired delta link starts over
+        let mut expired: DeltaCursor =
+            serde_json::from_str(second.cursor.strip_prefix(DELTA_CURSOR_PREFIX).unwrap()).unwrap();
+        expired.delta_link = expired.delta_link.replace("token=t2", "token=old");
+        let expired = DELTA_CURSOR_PREFIX.to_string() + &serde_json::to_string(&expired).unwrap();
+        let third = backend
+            .changes_since("/".to_string(), Some(expired))
+            .await
+            .unwrap();
+        assert!(third.reset);
+        assert_eq!(paths(&third), vec!["+/b.mp3"]);
+
+        let music = backend
+            .changes_since("/Music".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(paths(&music), vec!["+/Music/a.mp3"]);
+        let StorageChange::Upsert(entry) = &music.changes[0] else {
+            unreachable!()
+        };
+        assert_eq!(entry.name, "a.mp3");
+        assert_eq!(entry.size, Some(10));
+        assert_eq!(entry.etag.as_deref(), Some("e1"));
+        assert_eq!(entry.content_type.as_deref(), Some("audio/mpeg"));
+
+        // Only the synced folder, its files and the way to it go into the cursor
+        let cursor: DeltaCursor =
+            serde_json::from_str(music.cursor.strip_prefix(DELTA_CURSOR_PREFIX).unwrap()).unwrap();
+        let mut ids: Vec<&str> = cursor.items.keys().map(|v| v.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["f1", "m"]);
+
+        // Renaming the synced folder takes its files away
+        let renamed = backend
+            .changes_since("/Music".to_string(), Some(music.cursor))
+            .await
+            .unwrap();
+        assert_eq!(paths(&renamed), vec!["-/Music"]);
+
+        // Songs is the renamed Music, whose a.mp3 wasn't kept for a `/Songs` cursor
+        let songs = backend
+            .changes_since("/Songs".to_string(), None)
+            .await
+            .unwrap();
+        assert!(songs.changes.is_empty());
+        let moved_in = backend
+            .changes_since("/Songs".to_string(), Some(songs.cursor))
+            .await
+            .unwrap();
+        assert!(moved_in.reset);
+    }
 }
@@ -0,0 +1,584 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ease_client_tokio::tokio_runtime;
+use futures_util::future::BoxFuture;
+use md5::{Digest, Md5};
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+
+use crate::changes::{snapshot_changes, StorageChanges};
+use crate::http_client::{
+    with_read_timeout, HttpNetworkConfig, HttpPoolConfig, ResponseExt, SharedHttpClient,
+};
+use crate::{
+    Entry, StorageBackend, StorageBackendError, StorageBackendResult, StreamFile, UploadFile,
+};
+
+const SUBSONIC_API_VERSION: &str = "1.16.1";
+const SUBSONIC_CLIENT_NAME: &str = "ease";
+const SUBSONIC_ERROR_NOT_FOUND: u32 = 70;
+
+pub struct BuildSubsonicArg {
+    /// Server root, e.g. `https://navidrome.local`. `/rest/...` is appended to it.
+    pub addr: String,
+    pub username: String,
+    pub password: String,
+    pub connect_timeout: Duration,
+    pub pool: HttpPoolConfig,
+    pub network: HttpNetworkConfig,
+}
+
+/// Exposes a Subsonic compatible server (Navidrome, Airsonic, ...) as a read-only tree.
+///
+/// Paths are built from Subsonic ids: `/{artist id}/{album id}/{song id}.{suffix}`. Only the
+/// last segment is needed to resolve an entry, the rest keeps the hierarchy browsable.
+pub struct SubsonicBackend {
+    addr: String,
+    username: String,
+    password: String,
+    client: SharedHttpClient,
+    salt_counter: AtomicU64,
+}
+
+mod subsonic_types {
+    use serde::{Deserialize, Deserializer};
+    use serde_with::{serde_as, OneOrMany};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawId {
+        String(String),
+        Number(i64),
+    }
+
+    /// Older servers encode ids as JSON numbers.
+    fn de_id<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+        Ok(match RawId::deserialize(d)? {
+            RawId::String(v) => v,
+            RawId::Number(v) => v.to_string(),
+        })
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Root {
+        #[serde(rename = "subsonic-response")]
+        pub subsonic_response: Response,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Response {
+        pub status: String,
+        pub error: Option<Error>,
+        pub indexes: Option<Indexes>,
+        pub directory: Option<Directory>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Error {
+        pub code: u32,
+        #[serde(default)]
+        pub message: String,
+    }
+
+    // Subsonic's JSON serializer emits a single element list as a bare object.
+    #[serde_as]
+    #[derive(Deserialize, Debug)]
+    pub struct Indexes {
+        #[serde_as(as = "OneOrMany<_>")]
+        #[serde(default)]
+        pub index: Vec<Index>,
+        #[serde_as(as = "OneOrMany<_>")]
+        #[serde(default)]
+        pub child: Vec<Child>,
+    }
+
+    #[serde_as]
+    #[derive(Deserialize, Debug)]
+    pub struct Index {
+        #[serde_as(as = "OneOrMany<_>")]
+        #[serde(default)]
+        pub artist: Vec<Artist>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Artist {
+        #[serde(deserialize_with = "de_id")]
+        pub id: String,
+        pub name: String,
+    }
+
+    #[serde_as]
+    #[derive(Deserialize, Debug)]
+    pub struct Directory {
+        #[serde_as(as = "OneOrMany<_>")]
+        #[serde(default)]
+        pub child: Vec<Child>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Child {
+        #[serde(deserialize_with = "de_id")]
+        pub id: String,
+        pub title: String,
+        #[serde(default)]
+        pub is_dir: bool,
+        pub suffix: Option<String>,
+        pub size: Option<u64>,
+        pub path: Option<String>,
+        pub content_type: Option<String>,
+    }
+}
+
+fn build_entry(dir: &str, child: subsonic_types::Child) -> Entry {
+    let id = urlencoding::encode(&child.id);
+    if child.is_dir {
+        return Entry {
+            name: child.title,
+            path: format!("{}/{}", dir, id),
+            is_dir: true,
+            ..Default::default()
+        };
+    }
+
+    let suffix = child.suffix.unwrap_or_default();
+    let name = child
+        .path
+        .as_deref()
+        .and_then(|p| p.split('/').next_back())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| format!("{}.{}", child.title, suffix));
+    Entry {
+        name,
+        path: format!("{}/{}.{}", dir, id, suffix),
+        size: child.size.map(|v| v as usize),
+        is_dir: false,
+        content_type: child.content_type,
+        ..Default::default()
+    }
+}
+
+fn decode_id(id: &str) -> StorageBackendResult<String> {
+    let id = urlencoding::decode(id)
+        .map_err(|e| StorageBackendError::UrlParseError(e.to_string()))?
+        .to_string();
+    Ok(id)
+}
+
+fn last_segment(p: &str) -> &str {
+    p.trim_end_matches('/')
+        .split('/')
+        .next_back()
+        .unwrap_or_default()
+}
+
+/// Returns the Subsonic id and the suffix of a file path.
+fn parse_file_path(p: &str) -> StorageBackendResult<(String, Option<&str>)> {
+    let last = last_segment(p);
+    match last.rsplit_once('.') {
+        Some((id, suffix)) => Ok((decode_id(id)?, Some(suffix))),
+        None => Ok((decode_id(last)?, None)),
+    }
+}
+
+fn check_response(resp: subsonic_types::Root) -> StorageBackendResult<subsonic_types::Response> {
+    let resp = resp.subsonic_response;
+    if resp.status == "ok" {
+        return Ok(resp);
+    }
+    let (code, message) = resp.error.map(|e| (e.code, e.message)).unwrap_or_default();
+    Err(StorageBackendError::SubsonicError { code, message })
+}
+
+impl SubsonicBackend {
+    pub fn new(arg: BuildSubsonicArg) -> Self {
+        Self {
+            addr: arg.addr,
+            username: arg.username,
+            password: arg.password,
+            client: SharedHttpClient::new(arg.connect_timeout, arg.pool, arg.network),
+            salt_counter: Default::default(),
+        }
+    }
+
+    fn build_client(&self) -> StorageBackendResult<reqwest::Client> {
+        self.client.get()
+    }
+
+    /// A fresh salt per request, so a captured token cannot be replayed.
+    fn next_salt(&self) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let counter = self.salt_counter.fetch_add(1, Ordering::Relaxed);
+        let digest = Md5::digest(format!("{nanos}:{counter}:{}", std::process::id()));
+        hex::encode(&digest[..6])
+    }
+
+    fn get_url(&self, method: &str, params: &[(&str, &str)]) -> StorageBackendResult<Url> {
+        let addr = self.addr.trim_end_matches('/').to_string() + "/rest/" + method + ".view";
+        let mut url =
+            Url::parse(&addr).map_err(|e| StorageBackendError::UrlParseError(e.to_string()))?;
+
+        let salt = self.next_salt();
+        let token = hex::encode(Md5::digest(self.password.clone() + salt.as_str()));
+        url.query_pairs_mut()
+            .append_pair("u", &self.username)
+            .append_pair("t", &token)
+            .append_pair("s", &salt)
+            .append_pair("v", SUBSONIC_API_VERSION)
+            .append_pair("c", SUBSONIC_CLIENT_NAME)
+            .append_pair("f", "json")
+            .extend_pairs(params);
+        Ok(url)
+    }
+
+    async fn call(
+        &self,
+        method: &str,
+        params: &[(&str, &str)],
+    ) -> StorageBackendResult<subsonic_types::Response> {
+        let url = self.get_url(method, params)?;
+        let client = self.build_client()?;
+        let read_timeout = self.client.read_timeout();
+
+        let text = tokio_runtime()
+            .spawn(async move {
+                let resp = with_read_timeout(read_timeout, client.get(url).send())
+                    .await??
+                    .check_status()?;
+                Ok::<_, StorageBackendError>(with_read_timeout(read_timeout, resp.text()).await??)
+            })
+            .await??;
+        let resp: subsonic_types::Root = serde_json::from_str(&text)?;
+        check_response(resp)
+    }
+
+    async fn list_impl(&self, dir: String) -> StorageBackendResult<Vec<Entry>> {
+        let dir = dir.trim_end_matches('/').to_string();
+
+        // Server order is kept, it follows track numbers within an album.
+        if dir.is_empty() {
+            let resp = self.call("getIndexes", &[]).await?;
+            let Some(indexes) = resp.indexes else {
+                return Ok(Default::default());
+            };
+
+            let mut ret: Vec<Entry> = Default::default();
+            for artist in indexes.index.into_iter().flat_map(|v| v.artist) {
+                ret.push(Entry {
+                    name: artist.name,
+                    path: format!("/{}", urlencoding::encode(&artist.id)),
+                    is_dir: true,
+                    ..Default::default()
+                });
+            }
+            for child in indexes.child {
+                ret.push(build_entry("", child));
+            }
+            Ok(ret)
+        } else {
+            let id = decode_id(last_segment(&dir))?;
+            let resp = self.call("getMusicDirectory", &[("id", &id)]).await?;
+            let children = resp.directory.map(|v| v.child).unwrap_or_default();
+            Ok(children
+                .into_iter()
+                .map(|child| build_entry(&dir, child))
+                .collect())
+        }
+    }
+
+    async fn get_impl(&self, p: String, byte_offset: u64) -> StorageBackendResult<StreamFile> {
+        let (id, suffix) = parse_file_path(&p)?;
+        // Lyric sidecars are probed by swapping the extension, they never exist here.
+        if suffix == Some("lrc") {
+            return Err(StorageBackendError::SubsonicError {
+                code: SUBSONIC_ERROR_NOT_FOUND,
+                message: "lyric sidecar files are not supported".to_string(),
+            });
+        }
+
+        let url = self.get_url("stream", &[("id", &id), ("format", "raw")])?;
+        let client = self.build_client()?;
+        let range = HeaderValue::from_str(format!("bytes={byte_offset}-").as_str()).unwrap();
+        let read_timeout = self.client.read_timeout();
+
+        let resp = tokio_runtime()
+            .spawn(with_read_timeout(
+                read_timeout,
+                client.get(url).header(reqwest::header::RANGE, range).send(),
+            ))
+            .await???
+            .check_status()?;
+
+        // Failures are reported as a regular API response instead of the media body.
+        let is_api_response = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("json") || v.contains("xml"));
+        if is_api_response {
+            let text = tokio_runtime()
+                .spawn(with_read_timeout(read_timeout, resp.text()))
+                .await???;
+            let resp: subsonic_types::Root = serde_json::from_str(&text)?;
+            check_response(resp)?;
+            return Err(StorageBackendError::SubsonicError {
+                code: 0,
+                message: "unexpected response".to_string(),
+            });
+        }
+
+        let byte_offset = if resp.headers().get(reqwest::header::CONTENT_RANGE).is_some() {
+            0
+        } else {
+            byte_offset
+        };
+        Ok(StreamFile::new(resp, byte_offset).with_read_timeout(read_timeout))
+    }
+}
+
+impl StorageBackend for SubsonicBackend {
+    fn list(&self, dir: String) -> BoxFuture<StorageBackendResult<Vec<Entry>>> {
+        Box::pin(self.list_impl(dir))
+    }
+
+    fn get(&self, p: String, byte_offset: u64) -> BoxFuture<StorageBackendResult<StreamFile>> {
+        Box::pin(self.get_impl(p, byte_offset))
+    }
+
+    fn put(&self, _p: String, _file: UploadFile) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn mkdir(&self, _dir: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn delete(&self, _p: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn rename(&self, _from: String, _to: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(async { Err(StorageBackendError::Unsupported) })
+    }
+
+    fn changes_since(
+        &self,
+        dir: String,
+        cursor: Option<String>,
+    ) -> BoxFuture<StorageBackendResult<StorageChanges>> {
+        Box::pin(async move { snapshot_changes(self, &dir, cursor.as_deref()).await })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, convert::Infallible, net::SocketAddr, time::Duration};
+
+    use hyper::{Body, Request, Response};
+    use md5::{Digest, Md5};
+    use tokio::task::JoinHandle;
+
+    use crate::StorageBackend;
+
+    use super::{BuildSubsonicArg, SubsonicBackend};
+
+    const USERNAME: &str = "ease";
+    const PASSWORD: &str = "sesame";
+    const SONG: &[u8] = b"0123456789";
+
+    struct SetupServerRes {
+        addr: String,
+        handle: JoinHandle<()>,
+    }
+    impl Drop for SetupServerRes {
+        fn drop(&mut self) {
+            self.handle.abort();
+        }
+    }
+
+    fn ok_response(body: &str) -> String {
+        format!(r#"{{"subsonic-response":{{"status":"ok","version":"1.16.1",{body}}}}}"#)
+    }
+
+    fn error_response(code: u32, message: &str) -> String {
+        format!(
+            r#"{{"subsonic-response":{{"status":"failed","version":"1.16.1","error":{{"code":{code},"message":"{message}"}}}}}}"#
+        )
+    }
+
+    fn json(body: String) -> Response<Body> {
+        Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn handle(req: Request<Body>) -> Response<Body> {
+        let query: HashMap<String, String> = req
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|kv| kv.split_once('='))
+            .map(|(k, v)| (k.to_string(), urlencoding::decode(v).unwrap().to_string()))
+            .collect();
+
+        let salt = query.get("s").cloned().unwrap_or_default();
+        let token = hex::encode(Md5::digest(PASSWORD.to_string() + salt.as_str()));
+        if query.get("u").map(|v| v.as_str()) != Some(USERNAME)
+            || query.get("t") != Some(&token)
+            || salt.is_empty()
+        {
+            return json(error_response(40, "Wrong username or password"));
+        }
+
+        let id = query.get("id").map(|v| v.as_str()).unwrap_or_default();
+        match (req.uri().path(), id) {
+            ("/rest/getIndexes.view", _) => json(ok_response(
+                r#""indexes":{"lastModified":0,"ignoredArticles":"The","index":[
+                    {"name":"A","artist":{"id":"ar-1","name":"ABBA"}},
+                    {"name":"B","artist":[{"id":"ar-2","name":"Bee Gees"}]}
+                ]}"#,
+            )),
+            ("/rest/getMusicDirectory.view", "ar-1") => json(ok_response(
+                r#""directory":{"id":"ar-1","name":"ABBA","child":[
+                    {"id":"al-1","parent":"ar-1","title":"Arrival","isDir":true}
+                ]}"#,
+            )),
+            ("/rest/getMusicDirectory.view", "al-1") => json(ok_response(
+                r#""directory":{"id":"al-1","name":"Arrival","child":[
+                    {"id":101,"parent":"al-1","title":"Dancing Queen","isDir":false,
+                     "suffix":"mp3","size":10,"contentType":"audio/mpeg",
+                     "path":"ABBA/Arrival/01 - Dancing Queen.mp3"},
+                    {"id":102,"parent":"al-1","title":"Knowing Me, Knowing You","isDir":false,
+                     "suffix":"flac","size":20}
+                ]}"#,
+            )),
+            ("/rest/stream.view", "101") => {
+                let start: usize = req
+                    .headers()
+                    .get(hyper::header::RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.strip_prefix("bytes="))
+                    .and_then(|v| v.trim_end_matches('-').parse().ok())
+                    .unwrap_or_default();
+                Response::builder()
+                    .status(hyper::StatusCode::PARTIAL_CONTENT)
+                    .header(hyper::header::CONTENT_TYPE, "audio/mpeg")
+                    .header(
+                        hyper::header::CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", start, SONG.len() - 1, SONG.len()),
+                    )
+                    .header(hyper::header::CONTENT_LENGTH, SONG.len() - start)
+                    .body(Body::from(&SONG[start..]))
+                    .unwrap()
+            }
+            _ => json(error_response(70, "The requested data was not found")),
+        }
+    }
+
+    async fn setup_server() -> SetupServerRes {
+        let make_svc = hyper::service::make_service_fn(|_conn| async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(
+                |req: Request<Body>| async move { Ok::<_, Infallible>(handle(req)) },
+            ))
+        });
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let server = hyper::Server::bind(&addr).serve(make_svc);
+        let addr = format!("http://{}", server.local_addr());
+        let handle = tokio::spawn(async move {
+            let _ = server.await;
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        SetupServerRes { addr, handle }
+    }
+
+    fn build_backend(server: &SetupServerRes, password: &str) -> SubsonicBackend {
+        SubsonicBackend::new(BuildSubsonicArg {
+            addr: server.addr.clone(),
+            username: USERNAME.to_string(),
+            password: password.to_string(),
+            connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_list() {
+        let server = setup_server().await;
+        let backend = build_backend(&server, PASSWORD);
+
+        let list = backend.list("/".to_string()).await.unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].name, "ABBA");
+        assert_eq!(list[0].path, "/ar-1");
+        assert!(list[0].is_dir);
+        assert_eq!(list[1].name, "Bee Gees");
+
+        let list = backend.list("/ar-1".to_string()).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, "Arrival");
+        assert_eq!(list[0].path, "/ar-1/al-1");
+        assert!(list[0].is_dir);
+
+        let list = backend.list("/ar-1/al-1".to_string()).await.unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].name, "01 - Dancing Queen.mp3");
+        assert_eq!(list[0].path, "/ar-1/al-1/101.mp3");
+        assert_eq!(list[0].size, Some(10));
+        assert!(!list[0].is_dir);
+        assert_eq!(list[1].name, "Knowing Me, Knowing You.flac");
+        assert_eq!(list[1].path, "/ar-1/al-1/102.flac");
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let server = setup_server().await;
+        let backend = build_backend(&server, PASSWORD);
+
+        let file = backend
+            .get("/ar-1/al-1/101.mp3".to_string(), 0)
+            .await
+            .unwrap();
+        assert_eq!(file.size(), Some(10));
+        assert_eq!(file.content_type(), Some("audio/mpeg"));
+
+        let file = backend
+            .get("/ar-1/al-1/101.mp3".to_string(), 6)
+            .await
+            .unwrap();
+        assert_eq!(file.size(), Some(4));
+        let bytes = file.bytes().await.unwrap();
+        assert_eq!(bytes.as_ref(), b"6789");
+    }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        let server = setup_server().await;
+        let backend = build_backend(&server, PASSWORD);
+
+        let res = backend.get("/ar-1/al-1/999.mp3".to_string(), 0).await;
+        assert!(res.is_err_and(|e| e.is_not_found()));
+        let res = backend.get("/ar-1/al-1/101.lrc".to_string(), 0).await;
+        assert!(res.is_err_and(|e| e.is_not_found()));
+        let res = backend.list("/missing".to_string()).await;
+        assert!(res.is_err_and(|e| e.is_not_found()));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_password() {
+        let server = setup_server().await;
+        let backend = build_backend(&server, "wrong");
+
+        let res = backend.list("/".to_string()).await;
+        assert!(res.is_err_and(|e| e.is_unauthorized()));
+    }
+}
@@ -1,23 +1,39 @@
-use crate::backend::{Entry, StorageBackend, StorageBackendResult, StreamFile};
-use crate::StorageBackendError;
-
-use ease_client_tokio::tokio_runtime;
+use crate::backend::{
+    format_range, parse_http_time, Entry, StorageBackend, StorageBackendResult, StreamFile,
+    UploadFile,
+};
+use crate::changes::{
+    is_child, is_removal_in, is_snapshot_cursor, snapshot_changes, StorageChange, StorageChanges,
+};
+use crate::http_client::{HttpNetworkConfig, HttpPoolConfig, ResponseExt, SharedHttpClient};
+use crate::retry::send_with_retry;
+use crate::{RetryPolicy, StorageBackendError};
+
+use super::webdav_multistatus::{
+    parse_multistatus, parse_sync_collection, sync_collection_body, PropfindResponse,
+    SyncCollection, PROPFIND_BODY,
+};
+
+use bytes::Bytes;
 use futures_util::future::BoxFuture;
 use reqwest::header::HeaderValue;
 use reqwest::{StatusCode, Url};
 
 use std::cmp::Ordering;
 
-use std::sync::RwLock;
+use std::sync::Mutex;
 use std::time::Duration;
 
 pub struct Webdav {
     addr: String,
     username: String,
     password: String,
-    _is_anonymous: bool,
-    last_www_authenticate: RwLock<Option<String>>,
-    connect_timeout: Duration,
+    is_anonymous: bool,
+    preemptive_basic_auth: bool,
+    /// Kept across requests so that Digest nonce counts keep going up.
+    auth_client: Mutex<Option<http_auth::PasswordClient>>,
+    client: SharedHttpClient,
+    retry_policy: RetryPolicy,
 }
 
 pub struct BuildWebdavArg {
@@ -25,42 +41,25 @@ pub struct BuildWebdavArg {
     pub username: String,
     pub password: String,
     pub is_anonymous: bool,
+    /// Sends Basic credentials before the server asks for them, saving a round trip.
+    /// Only meant for servers known to use Basic, over https.
+    pub preemptive_basic_auth: bool,
     pub connect_timeout: Duration,
+    pub pool: HttpPoolConfig,
+    pub network: HttpNetworkConfig,
+    pub retry_policy: RetryPolicy,
 }
 
-mod webdav_list_types {
-    use serde::Deserialize;
-
-    #[derive(Deserialize, Debug)]
-    pub struct Collection {}
-
-    #[derive(Deserialize, Debug)]
-    pub struct ResourceType {
-        pub collection: Option<Collection>,
-    }
-
-    #[derive(Deserialize, Debug)]
-    pub struct Prop {
-        pub displayname: Option<String>,
-        pub resourcetype: ResourceType,
-        pub getcontentlength: Option<usize>,
-    }
+const DAV_SYNC_CURSOR_PREFIX: &str = "dav-sync:";
+/// Uploads up to this size are kept in memory, so they can be sent again after a 401.
+const WEBDAV_BUFFERED_UPLOAD_LIMIT: u64 = 4 << 20;
 
-    #[derive(Deserialize, Debug)]
-    pub struct PropStat {
-        pub prop: Prop,
-    }
-
-    #[derive(Deserialize, Debug)]
-    pub struct Response {
-        pub href: String,
-        pub propstat: PropStat,
-    }
-
-    #[derive(Deserialize, Debug)]
-    pub struct Root {
-        pub response: Vec<Response>,
-    }
+enum SyncReport {
+    Done(SyncCollection),
+    /// The server no longer knows the token and wants a fresh initial sync.
+    InvalidToken,
+    /// No `sync-collection` support, the tree has to be diffed by hand.
+    Unsupported,
 }
 
 fn normalize_path(p: String) -> String {
@@ -71,27 +70,28 @@ fn normalize_path(p: String) -> String {
     }
 }
 
-fn build_authorization_header_value(
-    www_authenticate: &str,
-    username: &str,
-    password: &str,
-    uri: &str,
-    method: &str,
-) -> Option<String> {
-    if www_authenticate.is_empty() {
-        return None;
-    }
-    let mut pw_client = http_auth::PasswordClient::try_from(www_authenticate).unwrap();
-    let ret = pw_client
-        .respond(&http_auth::PasswordParams {
-            username,
-            password,
-            uri,
-            method,
-            body: Some(&[]),
-        })
-        .unwrap();
-    Some(ret)
+/// Picks the strongest scheme offered across all `WWW-Authenticate` headers, so a server
+/// offering e.g. `Negotiate` next to `Basic` still works.
+fn build_password_client(
+    headers: &reqwest::header::HeaderMap,
+) -> StorageBackendResult<http_auth::PasswordClient> {
+    let mut builder = http_auth::PasswordClient::builder();
+    for v in headers.get_all(reqwest::header::WWW_AUTHENTICATE) {
+        if let Ok(v) = v.to_str() {
+            builder = builder.challenges(v);
+        }
+    }
+    builder
+        .build()
+        .map_err(StorageBackendError::UnsupportedAuth)
+}
+
+/// Digest hashes the request target, not the absolute url.
+fn digest_uri(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
 }
 
 fn is_auth_error<T>(r: &StorageBackendResult<T>) -> bool {
@@ -111,31 +111,74 @@ impl Webdav {
             addr: arg.addr,
             username: arg.username,
             password: arg.password,
-            _is_anonymous: arg.is_anonymous,
-            last_www_authenticate: Default::default(),
-            connect_timeout: arg.connect_timeout,
+            is_anonymous: arg.is_anonymous,
+            preemptive_basic_auth: arg.preemptive_basic_auth,
+            auth_client: Default::default(),
+            client: SharedHttpClient::new(arg.connect_timeout, arg.pool, arg.network),
+            retry_policy: arg.retry_policy,
         }
     }
 
-    fn post_handle_response(&self, resp: &reqwest::Response) {
-        let headers = resp.headers();
-        let www_authenticate = headers.get(reqwest::header::WWW_AUTHENTICATE);
-        if let Some(www_authenticate) = www_authenticate {
-            let www_authenticate = www_authenticate.to_str();
-            if let Ok(www_authenticate) = www_authenticate {
-                {
-                    let mut writter = self.last_www_authenticate.write().unwrap();
-                    *writter = Some(www_authenticate.to_string());
-                }
+    /// A 401 carries a fresh challenge, which replaces the client and restarts the nonce count.
+    fn post_handle_response(&self, resp: &reqwest::Response) -> StorageBackendResult<()> {
+        if self.is_anonymous || resp.status() != StatusCode::UNAUTHORIZED {
+            return Ok(());
+        }
+        if resp
+            .headers()
+            .get(reqwest::header::WWW_AUTHENTICATE)
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        let client = build_password_client(resp.headers());
+        let mut w = self.auth_client.lock().unwrap();
+        match client {
+            Ok(client) => {
+                *w = Some(client);
+                Ok(())
+            }
+            Err(e) => {
+                *w = None;
+                Err(e)
             }
         }
     }
 
+    fn build_authorization_header_value(
+        &self,
+        method: &reqwest::Method,
+        url: &Url,
+    ) -> StorageBackendResult<Option<String>> {
+        if self.is_anonymous {
+            return Ok(None);
+        }
+        let mut w = self.auth_client.lock().unwrap();
+        match w.as_mut() {
+            Some(client) => client
+                .respond(&http_auth::PasswordParams {
+                    username: &self.username,
+                    password: &self.password,
+                    uri: &digest_uri(url),
+                    method: method.as_str(),
+                    body: None,
+                })
+                .map(Some)
+                .map_err(StorageBackendError::UnsupportedAuth),
+            None if self.preemptive_basic_auth => Ok(Some(http_auth::basic::encode_credentials(
+                &self.username,
+                &self.password,
+            ))),
+            None => Ok(None),
+        }
+    }
+
     fn build_base_header_map(
         &self,
         method: reqwest::Method,
         uri: &reqwest::Url,
-    ) -> reqwest::header::HeaderMap {
+    ) -> StorageBackendResult<reqwest::header::HeaderMap> {
         let mut header_map = reqwest::header::HeaderMap::new();
         header_map.append(
             reqwest::header::CONTENT_TYPE,
@@ -145,25 +188,13 @@ impl Webdav {
             reqwest::header::ACCEPT,
             HeaderValue::from_bytes(b"application/xml").unwrap(),
         );
-        {
-            let www_authenticate = self.last_www_authenticate.read().unwrap().clone();
-            if www_authenticate.is_some() {
-                let www_authenticate = www_authenticate.unwrap();
-                let auth = build_authorization_header_value(
-                    &www_authenticate,
-                    &self.username,
-                    &self.password,
-                    uri.as_str(),
-                    method.as_str(),
-                );
-                if auth.is_some() {
-                    let mut val = HeaderValue::from_str(auth.as_ref().unwrap()).unwrap();
-                    val.set_sensitive(true);
-                    header_map.append(reqwest::header::AUTHORIZATION, val);
-                }
-            }
+        if let Some(auth) = self.build_authorization_header_value(&method, uri)? {
+            let mut val = HeaderValue::from_str(&auth)
+                .map_err(|e| StorageBackendError::UnsupportedAuth(e.to_string()))?;
+            val.set_sensitive(true);
+            header_map.append(reqwest::header::AUTHORIZATION, val);
         }
-        header_map
+        Ok(header_map)
     }
 
     fn get_url<const IS_DIR: bool>(&self, p: &str) -> StorageBackendResult<Url> {
@@ -187,78 +218,73 @@ impl Webdav {
         Ok(normalize_path(dir.trim_start_matches(base.as_str()).into()))
     }
 
+    async fn propfind_core(&self, url: Url, depth: u32) -> StorageBackendResult<reqwest::Response> {
+        let method = reqwest::Method::from_bytes(b"PROPFIND").unwrap();
+        let mut headers = self.build_base_header_map(method.clone(), &url)?;
+        headers.insert("Depth", HeaderValue::from(depth));
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/xml; charset=utf-8"),
+        );
+        let body = reqwest::Body::from(PROPFIND_BODY);
+
+        self.send_core(method, url, headers, Some(body)).await
+    }
+
     async fn list_core(&self, dir: &str) -> StorageBackendResult<reqwest::Response> {
         let url = self.get_url::<true>(dir)?;
+        self.propfind_core(url, 1).await
+    }
 
-        let method = reqwest::Method::from_bytes(b"PROPFIND").unwrap();
-        let resp = {
-            let client = self.build_client()?;
-            let headers = self.build_base_header_map(method.clone(), &url);
-
-            tokio_runtime()
-                .spawn(async move {
-                    client
-                        .request(method.clone(), url.clone())
-                        .headers(headers)
-                        .header("Depth", 1)
-                        .body(
-                            r#"<?xml version="1.0" ?>
-                <D:propfind xmlns:D="DAV:">
-                <D:allprop/>
-                </D:propfind>"#,
-                        )
-                        .send()
-                        .await
-                })
-                .await??
-        };
-        self.post_handle_response(&resp);
+    fn build_entry(&self, item: PropfindResponse) -> StorageBackendResult<Entry> {
+        let prop = item.props;
+        let mut name = prop.displayname.unwrap_or(Default::default());
+        let mut path = self.get_href(item.href.as_str())?;
 
-        Ok(resp)
+        if path.len() > 1 && path.ends_with("/") {
+            path.pop();
+        }
+        if name.is_empty() {
+            let splited: Vec<&str> = path.split("/").collect();
+            if !splited.is_empty() {
+                name = splited.last().unwrap().to_string();
+            }
+        }
+        name = urlencoding::decode(name.as_str())
+            .map(|v| v.to_string())
+            .unwrap_or(name);
+
+        Ok(Entry {
+            name,
+            path,
+            size: prop.getcontentlength,
+            is_dir: prop.is_collection,
+            modified_time: prop.getlastmodified.as_deref().and_then(parse_http_time),
+            etag: prop.getetag,
+            content_type: prop.getcontenttype,
+        })
     }
 
-    async fn list_impl(&self, dir: &str) -> StorageBackendResult<Vec<Entry>> {
-        let resp = self.list_core(dir).await?.error_for_status()?;
-        let text: String = resp.text().await?;
-        let obj: webdav_list_types::Root = quick_xml::de::from_str(&text).map_err(|e| {
+    async fn propfind_entries(&self, resp: reqwest::Response) -> StorageBackendResult<Vec<Entry>> {
+        let text: String = resp.check_status()?.text().await?;
+        let responses = parse_multistatus(&text).map_err(|e| {
             tracing::error!("webdav list resp: {text}");
             e
         })?;
 
-        let mut ret: Vec<Entry> = Default::default();
-        for item in obj.response {
-            let path = item.href;
-            let mut name = item.propstat.prop.displayname.unwrap_or(Default::default());
-            let is_dir = item.propstat.prop.resourcetype.collection.is_some();
-            let size = item.propstat.prop.getcontentlength;
-            let mut path = self.get_href(path.as_str())?;
-
-            if path == "/" {
-                continue;
-            }
-            if path.ends_with("/") {
-                path.pop();
-            }
-            if path == dir || (dir.ends_with('/') && dir[0..dir.len() - 1] == path) {
-                continue;
-            }
-            if name.is_empty() {
-                let splited: Vec<&str> = path.split("/").collect();
-                if !splited.is_empty() {
-                    name = splited.last().unwrap().to_string();
-                }
-            }
-            name = urlencoding::decode(name.as_str())
-                .map(|v| v.to_string())
-                .unwrap_or(name);
+        responses
+            .into_iter()
+            .map(|item| self.build_entry(item))
+            .collect()
+    }
 
-            ret.push(Entry {
-                name,
-                path,
-                size,
-                is_dir,
-            });
-        }
+    async fn list_impl(&self, dir: &str) -> StorageBackendResult<Vec<Entry>> {
+        let resp = self.list_core(dir).await?;
+        let mut ret = self.propfind_entries(resp).await?;
+        ret.retain(|entry| {
+            let path = entry.path.as_str();
+            path != "/" && path != dir && !(dir.ends_with('/') && dir[0..dir.len() - 1] == *path)
+        });
 
         ret.sort_by(|lhs, rhs| {
             if lhs.is_dir ^ rhs.is_dir {
@@ -286,52 +312,283 @@ impl Webdav {
         return self.list_impl(dir.as_str()).await;
     }
 
-    async fn get_impl(&self, p: &str, byte_offset: u64) -> StorageBackendResult<StreamFile> {
+    async fn stat_impl(&self, p: &str) -> StorageBackendResult<Entry> {
+        let url = self.get_url::<false>(p)?;
+        let resp = self.propfind_core(url, 0).await?;
+        let entry = self.propfind_entries(resp).await?.into_iter().next();
+        entry.ok_or(StorageBackendError::ParseXMLFail)
+    }
+
+    async fn stat_with_retry_impl(&self, p: String) -> StorageBackendResult<Entry> {
+        let r = self.stat_impl(p.as_str()).await;
+        if !is_auth_error(&r) {
+            return r;
+        }
+        return self.stat_impl(p.as_str()).await;
+    }
+
+    async fn get_impl(
+        &self,
+        p: &str,
+        byte_offset: u64,
+        end: Option<u64>,
+    ) -> StorageBackendResult<StreamFile> {
         let url = self.get_url::<false>(p)?;
 
-        let mut headers = self.build_base_header_map(reqwest::Method::GET, &url);
+        let mut headers = self.build_base_header_map(reqwest::Method::GET, &url)?;
         headers.insert(
             reqwest::header::RANGE,
-            HeaderValue::from_str(format!("bytes={byte_offset}-").as_str()).unwrap(),
+            HeaderValue::from_str(format_range(byte_offset, end).as_str()).unwrap(),
         );
 
-        let resp = {
-            let client = self.build_client()?;
-            tokio_runtime()
-                .spawn(async move { client.get(url.clone()).headers(headers).send().await })
-                .await??
-        };
+        let resp = self
+            .send_core(reqwest::Method::GET, url, headers, None)
+            .await?;
         let byte_offset = if resp.headers().get(reqwest::header::CONTENT_RANGE).is_some() {
             0
         } else {
             byte_offset
         };
-        self.post_handle_response(&resp);
 
-        let res = resp
-            .error_for_status()
-            .map(|resp| StreamFile::new(resp, byte_offset))?;
-        Ok(res)
+        let res = resp.check_status().map(|resp| {
+            StreamFile::new(resp, byte_offset).with_read_timeout(self.client.read_timeout())
+        })?;
+        match end {
+            Some(end) => Ok(res.take(end.saturating_sub(byte_offset))),
+            None => Ok(res),
+        }
     }
 
     async fn get_with_retry_impl(
         &self,
         p: String,
         byte_offset: u64,
+        end: Option<u64>,
     ) -> StorageBackendResult<StreamFile> {
-        let r = self.get_impl(p.as_str(), byte_offset).await;
+        let r = self.get_impl(p.as_str(), byte_offset, end).await;
         if !is_auth_error(&r) {
             return r;
         }
-        return self.get_impl(p.as_str(), byte_offset).await;
+        return self.get_impl(p.as_str(), byte_offset, end).await;
     }
 
-    fn build_client(&self) -> StorageBackendResult<reqwest::Client> {
-        let client = reqwest::Client::builder()
-            .connect_timeout(self.connect_timeout)
-            .no_proxy()
-            .build()?;
-        Ok(client)
+    async fn sync_collection_core(&self, sync_token: &str) -> StorageBackendResult<SyncReport> {
+        let url = self.get_url::<true>("/")?;
+        let method = reqwest::Method::from_bytes(b"REPORT").unwrap();
+        let mut headers = self.build_base_header_map(method.clone(), &url)?;
+        headers.insert("Depth", HeaderValue::from(0));
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/xml; charset=utf-8"),
+        );
+        let body = reqwest::Body::from(sync_collection_body(sync_token));
+
+        let resp = self.send_core(method, url, headers, Some(body)).await?;
+        let status = resp.status();
+        if status == StatusCode::MULTI_STATUS {
+            let text = resp.text().await?;
+            return match parse_sync_collection(&text) {
+                Ok(sync) if sync.sync_token.is_some() => Ok(SyncReport::Done(sync)),
+                _ => Ok(SyncReport::Unsupported),
+            };
+        }
+        if status == StatusCode::UNAUTHORIZED {
+            resp.check_status()?;
+        }
+        // RFC 6578 answers a stale token with 403 `valid-sync-token`, some servers use 409
+        if !sync_token.is_empty()
+            && (status == StatusCode::FORBIDDEN || status == StatusCode::CONFLICT)
+        {
+            return Ok(SyncReport::InvalidToken);
+        }
+        Ok(SyncReport::Unsupported)
+    }
+
+    async fn changes_since_impl(
+        &self,
+        dir: &str,
+        cursor: Option<&str>,
+    ) -> StorageBackendResult<StorageChanges> {
+        if cursor.is_some_and(is_snapshot_cursor) {
+            return snapshot_changes(self, dir, cursor).await;
+        }
+        let sync_token = cursor.and_then(|v| v.strip_prefix(DAV_SYNC_CURSOR_PREFIX));
+        let mut reset = sync_token.is_none();
+        let mut report = self.sync_collection_core(sync_token.unwrap_or("")).await?;
+        if let SyncReport::InvalidToken = report {
+            reset = true;
+            report = self.sync_collection_core("").await?;
+        }
+        let sync = match report {
+            SyncReport::Done(sync) => sync,
+            _ => return snapshot_changes(self, dir, None).await,
+        };
+
+        let mut changes: Vec<StorageChange> = Default::default();
+        for item in sync.changed {
+            let entry = self.build_entry(item)?;
+            if !entry.is_dir && is_child(dir, &entry.path) {
+                changes.push(StorageChange::Upsert(entry));
+            }
+        }
+        for href in sync.removed {
+            let mut path = self.get_href(&href)?;
+            if path.len() > 1 && path.ends_with('/') {
+                path.pop();
+            }
+            if is_removal_in(dir, &path) {
+                changes.push(StorageChange::Removed(path));
+            }
+        }
+        Ok(StorageChanges {
+            changes,
+            cursor: DAV_SYNC_CURSOR_PREFIX.to_string() + sync.sync_token.as_deref().unwrap_or(""),
+            reset,
+        })
+    }
+
+    async fn changes_since_with_retry_impl(
+        &self,
+        dir: String,
+        cursor: Option<String>,
+    ) -> StorageBackendResult<StorageChanges> {
+        let r = self.changes_since_impl(&dir, cursor.as_deref()).await;
+        if !is_auth_error(&r) {
+            return r;
+        }
+        self.changes_since_impl(&dir, cursor.as_deref()).await
+    }
+
+    async fn send_core(
+        &self,
+        method: reqwest::Method,
+        url: Url,
+        headers: reqwest::header::HeaderMap,
+        body: Option<reqwest::Body>,
+    ) -> StorageBackendResult<reqwest::Response> {
+        let resp =
+            send_with_retry(&self.retry_policy, &self.client, method, url, headers, body).await?;
+        self.post_handle_response(&resp)?;
+
+        Ok(resp)
+    }
+
+    /// A streamed body cannot be replayed after a 401, so get a fresh challenge before
+    /// uploading. PROPFIND is guarded wherever PUT is, unlike OPTIONS on some servers, and
+    /// a stale Digest nonce is answered with a new one.
+    async fn refresh_www_authenticate(&self, url: &Url) -> StorageBackendResult<()> {
+        if self.is_anonymous || self.preemptive_basic_auth {
+            return Ok(());
+        }
+        let resp = self.propfind_core(url.clone(), 0).await?;
+        if resp.status() != StatusCode::UNAUTHORIZED {
+            return Ok(());
+        }
+        let resp = self.propfind_core(url.clone(), 0).await?;
+        if resp.status() == StatusCode::UNAUTHORIZED {
+            resp.check_status()?;
+        }
+        Ok(())
+    }
+
+    async fn put_impl(&self, p: &str, size: u64, body: reqwest::Body) -> StorageBackendResult<()> {
+        let url = self.get_url::<false>(p)?;
+        let mut headers = self.build_base_header_map(reqwest::Method::PUT, &url)?;
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/octet-stream"),
+        );
+        headers.insert(reqwest::header::CONTENT_LENGTH, HeaderValue::from(size));
+
+        self.send_core(reqwest::Method::PUT, url, headers, Some(body))
+            .await?
+            .check_status()?;
+        Ok(())
+    }
+
+    async fn put_with_retry_impl(&self, p: String, file: UploadFile) -> StorageBackendResult<()> {
+        let size = file.size();
+        if size <= WEBDAV_BUFFERED_UPLOAD_LIMIT {
+            let rx = file.into_rx();
+            let mut buf: Vec<u8> = Default::default();
+            while let Ok(chunk) = rx.recv().await {
+                buf.extend_from_slice(&chunk?);
+            }
+            let buf = Bytes::from(buf);
+
+            let r = self.put_impl(p.as_str(), size, buf.clone().into()).await;
+            if !is_auth_error(&r) {
+                return r;
+            }
+            return self.put_impl(p.as_str(), size, buf.into()).await;
+        }
+
+        let url = self.get_url::<false>(p.as_str())?;
+        self.refresh_www_authenticate(&url).await?;
+        let body = reqwest::Body::wrap_stream(file.into_rx());
+        self.put_impl(p.as_str(), size, body).await
+    }
+
+    async fn mkdir_impl(&self, dir: &str) -> StorageBackendResult<()> {
+        let url = self.get_url::<true>(dir)?;
+        let method = reqwest::Method::from_bytes(b"MKCOL").unwrap();
+        let headers = self.build_base_header_map(method.clone(), &url)?;
+
+        self.send_core(method, url, headers, None)
+            .await?
+            .check_status()?;
+        Ok(())
+    }
+
+    async fn delete_impl(&self, p: &str) -> StorageBackendResult<()> {
+        let url = self.get_url::<false>(p)?;
+        let headers = self.build_base_header_map(reqwest::Method::DELETE, &url)?;
+
+        self.send_core(reqwest::Method::DELETE, url, headers, None)
+            .await?
+            .check_status()?;
+        Ok(())
+    }
+
+    async fn rename_impl(&self, from: &str, to: &str) -> StorageBackendResult<()> {
+        let url = self.get_url::<false>(from)?;
+        let destination = self.get_url::<false>(to)?;
+        let method = reqwest::Method::from_bytes(b"MOVE").unwrap();
+        let mut headers = self.build_base_header_map(method.clone(), &url)?;
+        headers.insert(
+            "Destination",
+            HeaderValue::from_str(destination.as_str()).unwrap(),
+        );
+        headers.insert("Overwrite", HeaderValue::from_static("F"));
+
+        self.send_core(method, url, headers, None)
+            .await?
+            .check_status()?;
+        Ok(())
+    }
+
+    async fn mkdir_with_retry_impl(&self, dir: String) -> StorageBackendResult<()> {
+        let r = self.mkdir_impl(dir.as_str()).await;
+        if !is_auth_error(&r) {
+            return r;
+        }
+        self.mkdir_impl(dir.as_str()).await
+    }
+
+    async fn delete_with_retry_impl(&self, p: String) -> StorageBackendResult<()> {
+        let r = self.delete_impl(p.as_str()).await;
+        if !is_auth_error(&r) {
+            return r;
+        }
+        self.delete_impl(p.as_str()).await
+    }
+
+    async fn rename_with_retry_impl(&self, from: String, to: String) -> StorageBackendResult<()> {
+        let r = self.rename_impl(from.as_str(), to.as_str()).await;
+        if !is_auth_error(&r) {
+            return r;
+        }
+        self.rename_impl(from.as_str(), to.as_str()).await
     }
 }
 
@@ -340,22 +597,231 @@ impl StorageBackend for Webdav {
         Box::pin(self.list_with_retry_impl(dir))
     }
 
+    fn stat(&self, p: String) -> BoxFuture<StorageBackendResult<Entry>> {
+        Box::pin(self.stat_with_retry_impl(p))
+    }
+
     fn get(&self, p: String, byte_offset: u64) -> BoxFuture<StorageBackendResult<StreamFile>> {
-        Box::pin(self.get_with_retry_impl(p, byte_offset))
+        Box::pin(self.get_with_retry_impl(p, byte_offset, None))
+    }
+
+    fn get_range(
+        &self,
+        p: String,
+        start: u64,
+        end: u64,
+    ) -> BoxFuture<StorageBackendResult<StreamFile>> {
+        Box::pin(self.get_with_retry_impl(p, start, Some(end)))
+    }
+
+    fn put(&self, p: String, file: UploadFile) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.put_with_retry_impl(p, file))
+    }
+
+    fn mkdir(&self, dir: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.mkdir_with_retry_impl(dir))
+    }
+
+    fn delete(&self, p: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.delete_with_retry_impl(p))
+    }
+
+    fn rename(&self, from: String, to: String) -> BoxFuture<StorageBackendResult<()>> {
+        Box::pin(self.rename_with_retry_impl(from, to))
+    }
+
+    fn changes_since(
+        &self,
+        dir: String,
+        cursor: Option<String>,
+    ) -> BoxFuture<StorageBackendResult<StorageChanges>> {
+        Box::pin(self.changes_since_with_retry_impl(dir, cursor))
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::{convert::Infallible, net::SocketAddr, time::Duration};
-
-    use dav_server::{fakels::FakeLs, localfs::LocalFs, DavHandler};
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use dav_server::{
+        fakels::FakeLs, fs::DavFileSystem, localfs::LocalFs, memfs::MemFs, DavHandler,
+    };
     use tokio::task::JoinHandle;
 
-    use crate::backend::StorageBackend;
+    use crate::backend::{StorageBackend, StorageBackendError, UploadFile};
+    use crate::StorageChange;
 
     use super::{BuildWebdavArg, Webdav};
 
+    const TEST_USERNAME: &str = "ease";
+    const TEST_PASSWORD: &str = "p@ss:word";
+    const TEST_REALM: &str = "ease-test";
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum TestAuthScheme {
+        Basic,
+        Digest,
+        /// Sends `Negotiate`, `Digest` and `Basic` as separate headers.
+        Multi,
+        NegotiateOnly,
+    }
+
+    struct TestAuth {
+        scheme: TestAuthScheme,
+        /// Answers OPTIONS without asking for credentials.
+        public_options: bool,
+        unauthorized_count: AtomicUsize,
+        /// Last nonce count seen for each nonce.
+        nonce_counts: Mutex<HashMap<String, u32>>,
+        next_nonce: AtomicUsize,
+    }
+
+    impl TestAuth {
+        fn new(scheme: TestAuthScheme) -> Arc<Self> {
+            Self::build(scheme, false)
+        }
+
+        fn with_public_options(scheme: TestAuthScheme) -> Arc<Self> {
+            Self::build(scheme, true)
+        }
+
+        fn build(scheme: TestAuthScheme, public_options: bool) -> Arc<Self> {
+            Arc::new(Self {
+                scheme,
+                public_options,
+                unauthorized_count: Default::default(),
+                nonce_counts: Default::default(),
+                next_nonce: Default::default(),
+            })
+        }
+
+        fn unauthorized_count(&self) -> usize {
+            self.unauthorized_count.load(Ordering::SeqCst)
+        }
+
+        /// Forgets every nonce handed out, like a server whose nonce lifetime ran out.
+        fn expire_nonces(&self) {
+            self.nonce_counts.lock().unwrap().clear();
+        }
+
+        fn unauthorized_response(&self) -> hyper::Response<dav_server::body::Body> {
+            self.unauthorized_count.fetch_add(1, Ordering::SeqCst);
+            let nonce = format!("nonce{}", self.next_nonce.fetch_add(1, Ordering::SeqCst));
+            self.nonce_counts.lock().unwrap().insert(nonce.clone(), 0);
+
+            let basic = format!("Basic realm=\"{TEST_REALM}\"");
+            let digest = format!("Digest realm=\"{TEST_REALM}\", qop=\"auth\", nonce=\"{nonce}\"");
+            let challenges = match self.scheme {
+                TestAuthScheme::Basic => vec![basic],
+                TestAuthScheme::Digest => vec![digest],
+                TestAuthScheme::Multi => vec!["Negotiate".to_string(), digest, basic],
+                TestAuthScheme::NegotiateOnly => vec!["Negotiate".to_string()],
+            };
+
+            let mut builder = hyper::Response::builder().status(401);
+            for challenge in challenges {
+                builder = builder.header(hyper::header::WWW_AUTHENTICATE, challenge);
+            }
+            builder.body(dav_server::body::Body::empty()).unwrap()
+        }
+
+        fn check(&self, req: &hyper::Request<hyper::Body>) -> bool {
+            if self.public_options && req.method() == hyper::Method::OPTIONS {
+                return true;
+            }
+            let Some(auth) = req
+                .headers()
+                .get(hyper::header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+            else {
+                return false;
+            };
+
+            if auth.starts_with("Basic ") {
+                return self.scheme == TestAuthScheme::Basic
+                    && auth == http_auth::basic::encode_credentials(TEST_USERNAME, TEST_PASSWORD);
+            }
+            if !matches!(self.scheme, TestAuthScheme::Digest | TestAuthScheme::Multi) {
+                return false;
+            }
+            let Some(params) = http_auth::parse_challenges(auth)
+                .ok()
+                .and_then(|v| v.into_iter().next())
+                .filter(|v| v.scheme.eq_ignore_ascii_case("Digest"))
+                .map(|v| {
+                    v.params
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v.to_unescaped()))
+                        .collect::<HashMap<_, _>>()
+                })
+            else {
+                return false;
+            };
+            let param = |k: &str| params.get(k).cloned().unwrap_or_default();
+
+            let uri = match req.uri().query() {
+                Some(query) => format!("{}?{}", req.uri().path(), query),
+                None => req.uri().path().to_string(),
+            };
+            if param("username") != TEST_USERNAME || param("uri") != uri {
+                return false;
+            }
+
+            let nonce = param("nonce");
+            let Ok(nc) = u32::from_str_radix(&param("nc"), 16) else {
+                return false;
+            };
+            {
+                let mut nonce_counts = self.nonce_counts.lock().unwrap();
+                let Some(last_nc) = nonce_counts.get_mut(&nonce) else {
+                    return false;
+                };
+                if nc <= *last_nc {
+                    return false;
+                }
+                *last_nc = nc;
+            }
+
+            let ha1 = md5_hex(&format!("{TEST_USERNAME}:{TEST_REALM}:{TEST_PASSWORD}"));
+            let ha2 = md5_hex(&format!("{}:{uri}", req.method()));
+            let expected = md5_hex(&format!(
+                "{ha1}:{nonce}:{}:{}:{}:{ha2}",
+                param("nc"),
+                param("cnonce"),
+                param("qop")
+            ));
+            param("response") == expected
+        }
+    }
+
+    fn md5_hex(s: &str) -> String {
+        use md5::{Digest, Md5};
+        hex::encode(Md5::digest(s.as_bytes()))
+    }
+
+    fn build_auth_backend(server: &SetupServerRes, password: &str, preemptive: bool) -> Webdav {
+        Webdav::new(BuildWebdavArg {
+            addr: server.addr(),
+            username: TEST_USERNAME.to_string(),
+            password: password.to_string(),
+            is_anonymous: false,
+            connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+            preemptive_basic_auth: preemptive,
+            retry_policy: Default::default(),
+        })
+    }
+
     struct SetupServerRes {
         addr: String,
         handle: JoinHandle<()>,
@@ -372,8 +838,23 @@ mod test {
     }
 
     async fn setup_server(p: &str) -> SetupServerRes {
+        setup_server_with_fs(LocalFs::new(p, false, false, false)).await
+    }
+
+    async fn setup_memfs_server() -> SetupServerRes {
+        setup_server_with_fs(MemFs::new()).await
+    }
+
+    async fn setup_server_with_fs(fs: Box<dyn DavFileSystem>) -> SetupServerRes {
+        setup_server_with_auth(fs, None).await
+    }
+
+    async fn setup_server_with_auth(
+        fs: Box<dyn DavFileSystem>,
+        auth: Option<Arc<TestAuth>>,
+    ) -> SetupServerRes {
         let dav_server = DavHandler::builder()
-            .filesystem(LocalFs::new(p, false, false, false))
+            .filesystem(fs)
             .locksystem(FakeLs::new())
             .autoindex(true)
             .build_handler();
@@ -381,10 +862,19 @@ mod test {
         let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
         let make_service = hyper::service::make_service_fn(move |_| {
             let dav_server = dav_server.clone();
+            let auth = auth.clone();
             async move {
-                let func = move |req| {
+                let func = move |req: hyper::Request<hyper::Body>| {
                     let dav_server = dav_server.clone();
-                    async move { Ok::<_, Infallible>(dav_server.handle(req).await) }
+                    let auth = auth.clone();
+                    async move {
+                        if let Some(auth) = auth {
+                            if !auth.check(&req) {
+                                return Ok::<_, Infallible>(auth.unauthorized_response());
+                            }
+                        }
+                        Ok::<_, Infallible>(dav_server.handle(req).await)
+                    }
                 };
                 Ok::<_, Infallible>(hyper::service::service_fn(func))
             }
@@ -414,6 +904,10 @@ mod test {
             password: Default::default(),
             is_anonymous: true,
             connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+            preemptive_basic_auth: false,
+            retry_policy: Default::default(),
         });
         let list = backend.list("/".to_string()).await.unwrap();
         assert_eq!(list.len(), 2);
@@ -431,6 +925,10 @@ mod test {
             password: Default::default(),
             is_anonymous: true,
             connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+            preemptive_basic_auth: false,
+            retry_policy: Default::default(),
         });
         let mut list = backend.list("/".to_string()).await.unwrap();
         assert_eq!(list.len(), 1);
@@ -459,6 +957,10 @@ mod test {
             password: Default::default(),
             is_anonymous: true,
             connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+            preemptive_basic_auth: false,
+            retry_policy: Default::default(),
         });
         let list = backend.list("/".to_string()).await.unwrap();
         assert_eq!(list.len(), 2);
@@ -495,6 +997,10 @@ mod test {
             password: Default::default(),
             is_anonymous: true,
             connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+            preemptive_basic_auth: false,
+            retry_policy: Default::default(),
         });
         let file = backend.get("/a.bin".to_string(), 2).await.unwrap();
         assert_eq!(file.size(), Some(1));
@@ -516,6 +1022,10 @@ mod test {
             password: Default::default(),
             is_anonymous: true,
             connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+            preemptive_basic_auth: false,
+            retry_policy: Default::default(),
         });
         let file = backend.get("/a.bin".to_string(), 2).await.unwrap();
         assert_eq!(file.size(), Some(1));
@@ -523,4 +1033,525 @@ mod test {
         let chunk = file.bytes().await.unwrap();
         assert_eq!(chunk.as_ref(), [51]);
     }
+
+    #[tokio::test]
+    async fn test_file_content_1_range() {
+        let server = setup_server("test/assets/case_content").await;
+
+        let backend = build_anonymous_backend(&server);
+        let file = backend.get_range("/a.bin".to_string(), 1, 2).await.unwrap();
+        assert_eq!(file.size(), Some(1));
+        assert_eq!(file.total_size(), Some(3));
+
+        let chunk = file.bytes().await.unwrap();
+        assert_eq!(chunk.as_ref(), [50]);
+    }
+
+    fn build_anonymous_backend(server: &SetupServerRes) -> Webdav {
+        Webdav::new(BuildWebdavArg {
+            addr: server.addr(),
+            username: Default::default(),
+            password: Default::default(),
+            is_anonymous: true,
+            connect_timeout: Duration::from_secs(10),
+            pool: Default::default(),
+            network: Default::default(),
+            preemptive_basic_auth: false,
+            retry_policy: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_put_and_get() {
+        let server = setup_memfs_server().await;
+        let backend = build_anonymous_backend(&server);
+
+        let (tx, rx) = async_channel::bounded(2);
+        tokio::spawn(async move {
+            tx.send(Ok(bytes::Bytes::from_static(b"[00:00.00]")))
+                .await
+                .unwrap();
+            tx.send(Ok(bytes::Bytes::from_static(b"ease")))
+                .await
+                .unwrap();
+        });
+        backend
+            .put("/a.lrc".to_string(), UploadFile::new(rx, 14))
+            .await
+            .unwrap();
+
+        let list = backend.list("/".to_string()).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].path, "/a.lrc");
+        assert_eq!(list[0].size, Some(14));
+
+        let file = backend.get("/a.lrc".to_string(), 0).await.unwrap();
+        let chunk = file.bytes().await.unwrap();
+        assert_eq!(chunk.as_ref(), b"[00:00.00]ease");
+    }
+
+    #[tokio::test]
+    async fn test_mkdir_and_delete() {
+        let server = setup_memfs_server().await;
+        let backend = build_anonymous_backend(&server);
+
+        backend.mkdir("/music".to_string()).await.unwrap();
+        backend
+            .put(
+                "/music/a.bin".to_string(),
+                UploadFile::new_from_bytes(bytes::Bytes::from_static(b"123")),
+            )
+            .await
+            .unwrap();
+
+        let list = backend.list("/".to_string()).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].path, "/music");
+        assert!(list[0].is_dir);
+
+        backend.delete("/music/a.bin".to_string()).await.unwrap();
+        let list = backend.list("/music".to_string()).await.unwrap();
+        assert!(list.is_empty());
+
+        backend.delete("/music".to_string()).await.unwrap();
+        let list = backend.list("/".to_string()).await.unwrap();
+        assert!(list.is_empty());
+
+        let r = backend.get("/music/a.bin".to_string(), 0).await;
+        assert!(r.is_err_and(|e| e.is_not_found()));
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let server = setup_memfs_server().await;
+        let backend = build_anonymous_backend(&server);
+
+        backend.mkdir("/music".to_string()).await.unwrap();
+        backend
+            .put(
+                "/a.bin".to_string(),
+                UploadFile::new_from_bytes(bytes::Bytes::from_static(b"123")),
+            )
+            .await
+            .unwrap();
+        backend
+            .rename("/a.bin".to_string(), "/music/b.bin".to_string())
+            .await
+            .unwrap();
+
+        let list = backend.list("/music".to_string()).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].path, "/music/b.bin");
+
+        let file = backend.get("/music/b.bin".to_string(), 0).await.unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), b"123");
+    }
+
+    #[tokio::test]
+    async fn test_stat() {
+        let server = setup_memfs_server().await;
+        let backend = build_anonymous_backend(&server);
+
+        backend.mkdir("/music".to_string()).await.unwrap();
+        backend
+            .put(
+                "/music/a.mp3".to_string(),
+                UploadFile::new_from_bytes(bytes::Bytes::from_static(b"123")),
+            )
+            .await
+            .unwrap();
+
+        let entry = backend.stat("/music/a.mp3".to_string()).await.unwrap();
+        assert_eq!(entry.path, "/music/a.mp3");
+        assert_eq!(entry.name, "a.mp3");
+        assert_eq!(entry.size, Some(3));
+        assert!(!entry.is_dir);
+        assert!(entry.modified_time.is_some());
+        assert!(entry.etag.is_some());
+        assert_eq!(entry.content_type.as_deref(), Some("audio/mpeg"));
+
+        let list = backend.list("/music".to_string()).await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].modified_time, entry.modified_time);
+        assert_eq!(list[0].etag, entry.etag);
+
+        let entry = backend.stat("/music".to_string()).await.unwrap();
+        assert_eq!(entry.path, "/music");
+        assert!(entry.is_dir);
+
+        let r = backend.stat("/music/b.mp3".to_string()).await;
+        assert!(r.is_err_and(|e| e.is_not_found()));
+    }
+
+    async fn upload_bytes(backend: &Webdav, p: &str, buf: &'static [u8]) {
+        backend
+            .put(
+                p.to_string(),
+                UploadFile::new_from_bytes(bytes::Bytes::from_static(buf)),
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth() {
+        let auth = TestAuth::new(TestAuthScheme::Basic);
+        let server = setup_server_with_auth(MemFs::new(), Some(auth.clone())).await;
+        let backend = build_auth_backend(&server, TEST_PASSWORD, false);
+
+        upload_bytes(&backend, "/a.lrc", b"[00:00.00]").await;
+        let file = backend.get("/a.lrc".to_string(), 0).await.unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), b"[00:00.00]");
+        assert_eq!(auth.unauthorized_count(), 1);
+
+        let backend = build_auth_backend(&server, "wrong", false);
+        let err = backend.list("/".to_string()).await.unwrap_err();
+        assert!(err.is_unauthorized());
+    }
+
+    #[tokio::test]
+    async fn test_digest_auth_nonce_count() {
+        let auth = TestAuth::new(TestAuthScheme::Digest);
+        let server = setup_server_with_auth(MemFs::new(), Some(auth.clone())).await;
+        let backend = build_auth_backend(&server, TEST_PASSWORD, false);
+
+        upload_bytes(&backend, "/a.lrc", b"[00:00.00]").await;
+        for _ in 0..10 {
+            let list = backend.list("/".to_string()).await.unwrap();
+            assert_eq!(list.len(), 1);
+        }
+        let file = backend.get("/a.lrc".to_string(), 0).await.unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), b"[00:00.00]");
+        assert_eq!(auth.unauthorized_count(), 1);
+    }
+
+    fn big_upload() -> bytes::Bytes {
+        bytes::Bytes::from(vec![
+            7u8;
+            (super::WEBDAV_BUFFERED_UPLOAD_LIMIT + 1) as usize
+        ])
+    }
+
+    async fn assert_file(backend: &Webdav, p: &str, expected: &[u8]) {
+        let file = backend.get(p.to_string(), 0).await.unwrap();
+        assert_eq!(file.bytes().await.unwrap().as_ref(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_put_with_public_options() {
+        let auth = TestAuth::with_public_options(TestAuthScheme::Digest);
+        let server = setup_server_with_auth(MemFs::new(), Some(auth.clone())).await;
+
+        let backend = build_auth_backend(&server, TEST_PASSWORD, false);
+        upload_bytes(&backend, "/a.lrc", b"[00:00.00]").await;
+        assert_file(&backend, "/a.lrc", b"[00:00.00]").await;
+        assert_eq!(auth.unauthorized_count(), 1);
+
+        let backend = build_auth_backend(&server, TEST_PASSWORD, false);
+        backend
+            .put(
+                "/a.flac".to_string(),
+                UploadFile::new_from_bytes(big_upload()),
+            )
+            .await
+            .unwrap();
+        assert_file(&backend, "/a.flac", &big_upload()).await;
+        assert_eq!(auth.unauthorized_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_put_after_nonce_expired() {
+        let auth = TestAuth::new(TestAuthScheme::Digest);
+        let server = setup_server_with_auth(MemFs::new(), Some(auth.clone())).await;
+        let backend = build_auth_backend(&server, TEST_PASSWORD, false);
+
+        upload_bytes(&backend, "/a.lrc", b"[00:00.00]").await;
+        assert_eq!(auth.unauthorized_count(), 1);
+
+        auth.expire_nonces();
+        upload_bytes(&backend, "/b.lrc", b"[00:00.00]").await;
+        assert_eq!(auth.unauthorized_count(), 2);
+
+        auth.expire_nonces();
+        backend
+            .put(
+                "/a.flac".to_string(),
+                UploadFile::new_from_bytes(big_upload()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(auth.unauthorized_count(), 3);
+
+        assert_file(&backend, "/b.lrc", b"[00:00.00]").await;
+        assert_file(&backend, "/a.flac", &big_upload()).await;
+    }
+
+    #[tokio::test]
+    async fn test_preemptive_basic_auth() {
+        let auth = TestAuth::new(TestAuthScheme::Basic);
+        let server = setup_server_with_auth(MemFs::new(), Some(auth.clone())).await;
+        let backend = build_auth_backend(&server, TEST_PASSWORD, true);
+
+        upload_bytes(&backend, "/a.lrc", b"[00:00.00]").await;
+        backend.list("/".to_string()).await.unwrap();
+        assert_eq!(auth.unauthorized_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_auth_challenge_selection() {
+        let auth = TestAuth::new(TestAuthScheme::Multi);
+        let server = setup_server_with_auth(MemFs::new(), Some(auth.clone())).await;
+        let backend = build_auth_backend(&server, TEST_PASSWORD, false);
+
+        backend.list("/".to_string()).await.unwrap();
+        backend.list("/".to_string()).await.unwrap();
+        assert_eq!(auth.unauthorized_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_auth() {
+        let auth = TestAuth::new(TestAuthScheme::NegotiateOnly);
+        let server = setup_server_with_auth(MemFs::new(), Some(auth)).await;
+        let backend = build_auth_backend(&server, TEST_PASSWORD, false);
+
+        let err = backend.list("/".to_string()).await.unwrap_err();
+        assert!(matches!(err, StorageBackendError::UnsupportedAuth(_)));
+        assert!(err.is_unauthorized());
+    }
+
+    #[tokio::test]
+    async fn test_list_multistatus_fixture() {
+        let requested_props: Arc<Mutex<String>> = Default::default();
+        let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let make_service = hyper::service::make_service_fn({
+            let requested_props = requested_props.clone();
+            move |_| {
+                let requested_props = requested_props.clone();
+                async move {
+                    let func = move |req: hyper::Request<hyper::Body>| {
+                        let requested_props = requested_props.clone();
+                        async move {
+                            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                            *requested_props.lock().unwrap() =
+                                String::from_utf8_lossy(&body).to_string();
+                            let text = std::fs::read_to_string(
+                                "test/assets/webdav_multistatus/nextcloud.xml",
+                            )
+                            .unwrap();
+                            Ok::<_, Infallible>(
+                                hyper::Response::builder()
+                                    .status(207)
+                                    .body(hyper::Body::from(text))
+                                    .unwrap(),
+                            )
+                        }
+                    };
+                    Ok::<_, Infallible>(hyper::service::service_fn(func))
+                }
+            }
+        });
+        let server = hyper::Server::bind(&addr).serve(make_service);
+        let port = server.local_addr().port();
+        let handle = tokio::spawn(async move {
+            server.await.unwrap();
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let server = SetupServerRes {
+            addr: format!("http://127.0.0.1:{port}/remote.php/dav/files/ease/Music"),
+            handle,
+        };
+
+        let backend = build_anonymous_backend(&server);
+        let list = backend.list("/".to_string()).await.unwrap();
+        assert!(requested_props.lock().unwrap().contains("<D:getetag/>"));
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].name, "Some Album");
+        assert_eq!(list[0].path, "/Some%20Album");
+        assert!(list[0].is_dir);
+        assert_eq!(list[1].name, "01 Intro.mp3");
+        assert_eq!(list[1].path, "/01%20Intro.mp3");
+        assert_eq!(list[1].size, Some(4213766));
+        assert_eq!(list[1].content_type.as_deref(), Some("audio/mpeg"));
+        assert!(list[1].modified_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_changes_since_falls_back_to_listing() {
+        let server = setup_memfs_server().await;
+        let backend = build_anonymous_backend(&server);
+        upload_bytes(&backend, "/a.lrc", b"a").await;
+
+        let first = backend.changes_since("/".to_string(), None).await.unwrap();
+        assert!(first.reset);
+        assert!(first.cursor.starts_with("snapshot:"));
+        assert_eq!(first.changes.len(), 1);
+        assert!(matches!(&first.changes[0], StorageChange::Upsert(e) if e.path == "/a.lrc"));
+
+        // The folder below isn't part of it
+        backend.mkdir("/music".to_string()).await.unwrap();
+        upload_bytes(&backend, "/music/b.lrc", b"b").await;
+        upload_bytes(&backend, "/c.lrc", b"c").await;
+        backend.delete("/a.lrc".to_string()).await.unwrap();
+        let second = backend
+            .changes_since("/".to_string(), Some(first.cursor))
+            .await
+            .unwrap();
+        assert!(!second.reset);
+        assert_eq!(second.changes.len(), 2);
+        assert!(matches!(&second.changes[0], StorageChange::Upsert(e) if e.path == "/c.lrc"));
+        assert_eq!(
+            second.changes[1],
+            StorageChange::Removed("/a.lrc".to_string())
+        );
+
+        let third = backend
+            .changes_since("/".to_string(), Some(second.cursor))
+            .await
+            .unwrap();
+        assert!(third.changes.is_empty());
+
+        let music = backend
+            .changes_since("/music".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(music.changes.len(), 1);
+        assert!(matches!(&music.changes[0], StorageChange::Upsert(e) if e.path == "/music/b.lrc"));
+    }
+
+    fn sync_collection_response(token: &str) -> hyper::Response<hyper::Body> {
+        let text = match token {
+            "" => {
+                r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:multistatus xmlns:D="DAV:">
+  <D:response>
+    <D:href>/dav/music/Some%20Album/</D:href>
+    <D:propstat>
+      <D:prop><D:resourcetype><D:collection/></D:resourcetype></D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+  <D:response>
+    <D:href>/dav/music/Some%20Album/old.mp3</D:href>
+    <D:propstat>
+      <D:prop><D:getetag>"1"</D:getetag><D:getcontentlength>10</D:getcontentlength></D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+  <D:sync-token>t1</D:sync-token>
+</D:multistatus>"#
+            }
+            "t1" => {
+                r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:multistatus xmlns:D="DAV:">
+  <D:response>
+    <D:href>/dav/music/new.mp3</D:href>
+    <D:propstat>
+      <D:prop><D:getetag>"2"</D:getetag><D:getcontentlength>20</D:getcontentlength></D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+  <D:response>
+    <D:href>/dav/music/Some%20Album/old.mp3</D:href>
+    <D:status>HTTP/1.1 404 Not Found</D:status>
+  </D:response>
+  <D:sync-token>t2</D:sync-token>
+</D:multistatus>"#
+            }
+            _ => {
+                return hyper::Response::builder()
+                    .status(403)
+                    .body(hyper::Body::empty())
+                    .unwrap()
+            }
+        };
+        hyper::Response::builder()
+            .status(207)
+            .body(hyper::Body::from(text))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_changes_since_sync_collection() {
+        let tokens: Arc<Mutex<Vec<String>>> = Default::default();
+        let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let make_service = hyper::service::make_service_fn({
+            let tokens = tokens.clone();
+            move |_| {
+                let tokens = tokens.clone();
+                async move {
+                    let func = move |req: hyper::Request<hyper::Body>| {
+                        let tokens = tokens.clone();
+                        async move {
+                            assert_eq!(req.method().as_str(), "REPORT");
+                            assert_eq!(req.uri().path(), "/dav/music/");
+                            assert_eq!(req.headers()["Depth"], "0");
+                            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                            let body = String::from_utf8_lossy(&body).to_string();
+                            let token = body
+                                .split_once("<D:sync-token>")
+                                .and_then(|(_, v)| v.split_once("</D:sync-token>"))
+                                .map(|(v, _)| v.to_string())
+                                .unwrap();
+                            tokens.lock().unwrap().push(token.clone());
+                            Ok::<_, Infallible>(sync_collection_response(&token))
+                        }
+                    };
+                    Ok::<_, Infallible>(hyper::service::service_fn(func))
+                }
+            }
+        });
+        let server = hyper::Server::bind(&addr).serve(make_service);
+        let port = server.local_addr().port();
+        let handle = tokio::spawn(async move {
+            server.await.unwrap();
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let server = SetupServerRes {
+            addr: format!("http://127.0.0.1:{port}/dav/music"),
+            handle,
+        };
+        let backend = build_anonymous_backend(&server);
+
+        let first = backend
+            .changes_since("/Some%20Album".to_string(), None)
+            .await
+            .unwrap();
+        assert!(first.reset);
+        assert_eq!(first.cursor, "dav-sync:t1");
+        assert_eq!(first.changes.len(), 1);
+        let StorageChange::Upsert(entry) = &first.changes[0] else {
+            unreachable!()
+        };
+        assert_eq!(entry.path, "/Some%20Album/old.mp3");
+        assert_eq!(entry.name, "old.mp3");
+        assert_eq!(entry.etag.as_deref(), Some("\"1\""));
+
+        let second = backend
+            .changes_since("/Some%20Album".to_string(), Some(first.cursor))
+            .await
+            .unwrap();
+        assert!(!second.reset);
+        assert_eq!(second.cursor, "dav-sync:t2");
+        assert_eq!(
+            second.changes,
+            vec![StorageChange::Removed("/Some%20Album/old.mp3".to_string())]
+        );
+
+        // Only the files right in the root, the album below is left out
+        let root = backend
+            .changes_since("/".to_string(), Some("dav-sync:t1".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(root.changes.len(), 1);
+        assert!(matches!(&root.changes[0], StorageChange::Upsert(e) if e.path == "/new.mp3"));
+
+        // A token the server forgot starts over with an initial sync
+        let stale = backend
+            .changes_since("/".to_string(), Some("dav-sync:t0".to_string()))
+            .await
+            .unwrap();
+        assert!(stale.reset);
+        assert_eq!(stale.cursor, "dav-sync:t1");
+        assert_eq!(*tokens.lock().unwrap(), vec!["", "t1", "t1", "t0", ""]);
+    }
 }
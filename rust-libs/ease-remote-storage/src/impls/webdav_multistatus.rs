@@ -0,0 +1,440 @@
+use quick_xml::{
+    events::Event,
+    name::{Namespace, ResolveResult},
+    NsReader,
+};
+
+/// Asks only for what `Entry` needs. Servers answer unknown or missing props with a
+/// separate 404 propstat, which `parse_multistatus` drops.
+pub(crate) const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propfind xmlns:D="DAV:">
+  <D:prop>
+    <D:displayname/>
+    <D:resourcetype/>
+    <D:getcontentlength/>
+    <D:getlastmodified/>
+    <D:getetag/>
+    <D:getcontenttype/>
+  </D:prop>
+</D:propfind>"#;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct PropfindProps {
+    pub displayname: Option<String>,
+    pub is_collection: bool,
+    pub getcontentlength: Option<usize>,
+    pub getlastmodified: Option<String>,
+    pub getetag: Option<String>,
+    pub getcontenttype: Option<String>,
+}
+
+impl PropfindProps {
+    fn merge(&mut self, other: PropfindProps) {
+        self.is_collection |= other.is_collection;
+        if other.displayname.is_some() {
+            self.displayname = other.displayname;
+        }
+        if other.getcontentlength.is_some() {
+            self.getcontentlength = other.getcontentlength;
+        }
+        if other.getlastmodified.is_some() {
+            self.getlastmodified = other.getlastmodified;
+        }
+        if other.getetag.is_some() {
+            self.getetag = other.getetag;
+        }
+        if other.getcontenttype.is_some() {
+            self.getcontenttype = other.getcontenttype;
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct PropfindResponse {
+    /// The path part of the href, still percent-encoded as the server sent it.
+    pub href: String,
+    /// Props from the successful propstats only.
+    pub props: PropfindProps,
+}
+
+/// A `sync-collection` REPORT (RFC 6578) asking for everything changed since `sync_token`,
+/// or for the whole collection with an empty token.
+pub(crate) fn sync_collection_body(sync_token: &str) -> String {
+    let sync_token = quick_xml::escape::escape(sync_token);
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:sync-collection xmlns:D="DAV:">
+  <D:sync-token>{sync_token}</D:sync-token>
+  <D:sync-level>infinite</D:sync-level>
+  <D:prop>
+    <D:displayname/>
+    <D:resourcetype/>
+    <D:getcontentlength/>
+    <D:getlastmodified/>
+    <D:getetag/>
+    <D:getcontenttype/>
+  </D:prop>
+</D:sync-collection>"#
+    )
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct SyncCollection {
+    /// Members added or changed since the token.
+    pub changed: Vec<PropfindResponse>,
+    /// Hrefs of removed members, as they were reported.
+    pub removed: Vec<String>,
+    pub sync_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tag {
+    Multistatus,
+    SyncToken,
+    Response,
+    Href,
+    Status,
+    PropStat,
+    Prop,
+    ResourceType,
+    Collection,
+    DisplayName,
+    GetContentLength,
+    GetLastModified,
+    GetEtag,
+    GetContentType,
+    Other,
+}
+
+impl Tag {
+    /// Elements in the `DAV:` namespace, whatever the prefix. Unbound names are accepted too,
+    /// since a few servers forget to declare the namespace at all.
+    fn classify(ns: &ResolveResult, local_name: &[u8]) -> Self {
+        match ns {
+            ResolveResult::Bound(Namespace(b"DAV:")) | ResolveResult::Unbound => {}
+            _ => return Tag::Other,
+        }
+        match local_name {
+            b"multistatus" => Tag::Multistatus,
+            b"sync-token" => Tag::SyncToken,
+            b"response" => Tag::Response,
+            b"href" => Tag::Href,
+            b"status" => Tag::Status,
+            b"propstat" => Tag::PropStat,
+            b"prop" => Tag::Prop,
+            b"resourcetype" => Tag::ResourceType,
+            b"collection" => Tag::Collection,
+            b"displayname" => Tag::DisplayName,
+            b"getcontentlength" => Tag::GetContentLength,
+            b"getlastmodified" => Tag::GetLastModified,
+            b"getetag" => Tag::GetEtag,
+            b"getcontenttype" => Tag::GetContentType,
+            _ => Tag::Other,
+        }
+    }
+}
+
+/// `HTTP/1.1 404 Not Found` -> `404`.
+fn parse_status_line(line: &str) -> Option<u16> {
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn is_success(status: Option<u16>) -> bool {
+    status.is_none_or(|status| (200..300).contains(&status))
+}
+
+/// Absolute hrefs are cut down to their path, relative ones are kept as they are.
+fn href_path(href: &str) -> String {
+    match reqwest::Url::parse(href) {
+        Ok(url) if url.has_host() => url.path().to_string(),
+        _ => href.to_string(),
+    }
+}
+
+fn non_empty(text: String) -> Option<String> {
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+#[derive(Default)]
+struct ResponseState {
+    href: Option<String>,
+    status: Option<u16>,
+    props: PropfindProps,
+}
+
+#[derive(Default)]
+struct PropStatState {
+    status: Option<u16>,
+    props: PropfindProps,
+}
+
+/// Parses a `207 Multi-Status` body into one item per successful `response`.
+///
+/// Every `propstat` is read and only the props of those with a 2xx status are kept, and a
+/// `response` with a failing top level status is dropped.
+pub(crate) fn parse_multistatus(text: &str) -> Result<Vec<PropfindResponse>, quick_xml::Error> {
+    let (responses, _) = parse_multistatus_impl(text)?;
+    Ok(responses
+        .into_iter()
+        .filter(|(_, status)| is_success(*status))
+        .map(|(response, _)| response)
+        .collect())
+}
+
+/// Same as [`parse_multistatus`] for a `sync-collection` answer, where a `404` response
+/// marks a removed member.
+pub(crate) fn parse_sync_collection(text: &str) -> Result<SyncCollection, quick_xml::Error> {
+    let (responses, sync_token) = parse_multistatus_impl(text)?;
+    let mut ret = SyncCollection {
+        sync_token,
+        ..Default::default()
+    };
+    for (response, status) in responses {
+        if status == Some(404) {
+            ret.removed.push(response.href);
+        } else if is_success(status) {
+            ret.changed.push(response);
+        }
+    }
+    Ok(ret)
+}
+
+type ResponseWithStatus = (PropfindResponse, Option<u16>);
+
+fn parse_multistatus_impl(
+    text: &str,
+) -> Result<(Vec<ResponseWithStatus>, Option<String>), quick_xml::Error> {
+    let mut reader = NsReader::from_str(text);
+    let mut sync_token: Option<String> = None;
+    let mut stack: Vec<Tag> = Default::default();
+    let mut text_buf = String::new();
+    let mut response: Option<ResponseState> = None;
+    let mut propstat: Option<PropStatState> = None;
+    let mut ret: Vec<ResponseWithStatus> = Default::default();
+
+    loop {
+        match reader.read_resolved_event()? {
+            (ns, Event::Start(e)) => {
+                let tag = Tag::classify(&ns, e.local_name().as_ref());
+                match tag {
+                    Tag::Response => response = Some(Default::default()),
+                    Tag::PropStat => propstat = Some(Default::default()),
+                    Tag::Collection if stack.last() == Some(&Tag::ResourceType) => {
+                        if let Some(propstat) = propstat.as_mut() {
+                            propstat.props.is_collection = true;
+                        }
+                    }
+                    _ => {}
+                }
+                stack.push(tag);
+                text_buf.clear();
+            }
+            (ns, Event::Empty(e)) => {
+                let tag = Tag::classify(&ns, e.local_name().as_ref());
+                if tag == Tag::Collection && stack.last() == Some(&Tag::ResourceType) {
+                    if let Some(propstat) = propstat.as_mut() {
+                        propstat.props.is_collection = true;
+                    }
+                }
+            }
+            (_, Event::Text(e)) => text_buf.push_str(&e.unescape()?),
+            (_, Event::CData(e)) => text_buf.push_str(&String::from_utf8_lossy(&e.into_inner())),
+            (_, Event::End(_)) => {
+                let Some(tag) = stack.pop() else {
+                    continue;
+                };
+                let parent = stack.last().copied();
+                let text = std::mem::take(&mut text_buf).trim().to_string();
+
+                match (tag, parent) {
+                    (Tag::SyncToken, Some(Tag::Multistatus)) => sync_token = non_empty(text),
+                    (Tag::Href, Some(Tag::Response)) => {
+                        if let Some(response) = response.as_mut() {
+                            response.href.get_or_insert(text);
+                        }
+                    }
+                    (Tag::Status, Some(Tag::Response)) => {
+                        if let Some(response) = response.as_mut() {
+                            response.status = parse_status_line(&text);
+                        }
+                    }
+                    (Tag::Status, Some(Tag::PropStat)) => {
+                        if let Some(propstat) = propstat.as_mut() {
+                            propstat.status = parse_status_line(&text);
+                        }
+                    }
+                    (_, Some(Tag::Prop)) => {
+                        if let Some(propstat) = propstat.as_mut() {
+                            let props = &mut propstat.props;
+                            match tag {
+                                Tag::DisplayName => props.displayname = non_empty(text),
+                                Tag::GetContentLength => props.getcontentlength = text.parse().ok(),
+                                Tag::GetLastModified => props.getlastmodified = non_empty(text),
+                                Tag::GetEtag => props.getetag = non_empty(text),
+                                Tag::GetContentType => props.getcontenttype = non_empty(text),
+                                _ => {}
+                            }
+                        }
+                    }
+                    (Tag::PropStat, _) => {
+                        if let (Some(response), Some(propstat)) =
+                            (response.as_mut(), propstat.take())
+                        {
+                            if is_success(propstat.status) {
+                                response.props.merge(propstat.props);
+                            }
+                        }
+                    }
+                    (Tag::Response, _) => {
+                        if let Some(response) = response.take() {
+                            if let Some(href) = response.href {
+                                ret.push((
+                                    PropfindResponse {
+                                        href: href_path(&href),
+                                        props: response.props,
+                                    },
+                                    response.status,
+                                ));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            (_, Event::Eof) => break,
+            _ => {}
+        }
+    }
+    Ok((ret, sync_token))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_multistatus, parse_sync_collection, PropfindProps, PropfindResponse};
+
+    fn parse_fixture(name: &str) -> Vec<PropfindResponse> {
+        let text =
+            std::fs::read_to_string(format!("test/assets/webdav_multistatus/{name}")).unwrap();
+        parse_multistatus(&text).unwrap()
+    }
+
+    #[test]
+    fn test_nextcloud() {
+        let list = parse_fixture("nextcloud.xml");
+        assert_eq!(list.len(), 3);
+
+        assert_eq!(list[0].href, "/remote.php/dav/files/ease/Music/");
+        assert!(list[0].props.is_collection);
+        assert_eq!(list[0].props.getcontentlength, None);
+
+        assert_eq!(
+            list[1].href,
+            "/remote.php/dav/files/ease/Music/Some%20Album/"
+        );
+        assert!(list[1].props.is_collection);
+
+        assert_eq!(
+            list[2].href,
+            "/remote.php/dav/files/ease/Music/01%20Intro.mp3"
+        );
+        assert_eq!(
+            list[2].props,
+            PropfindProps {
+                displayname: None,
+                is_collection: false,
+                getcontentlength: Some(4213766),
+                getlastmodified: Some("Tue, 05 Mar 2024 10:12:54 GMT".to_string()),
+                getetag: Some("\"6f0b0d5a3c5a1\"".to_string()),
+                getcontenttype: Some("audio/mpeg".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_apache() {
+        let list = parse_fixture("apache.xml");
+        assert_eq!(list.len(), 3);
+
+        assert_eq!(list[0].href, "/dav/music/");
+        assert!(list[0].props.is_collection);
+        assert_eq!(
+            list[0].props.getcontenttype.as_deref(),
+            Some("httpd/unix-directory")
+        );
+
+        assert_eq!(list[1].href, "/dav/music/cover.jpg");
+        assert!(!list[1].props.is_collection);
+        assert_eq!(list[1].props.getcontentlength, Some(51234));
+        assert_eq!(
+            list[1].props.getetag.as_deref(),
+            Some("\"c822-5f1a2b3c4d5e6\"")
+        );
+        assert_eq!(list[1].props.displayname, None);
+
+        assert_eq!(list[2].href, "/dav/music/a&b.flac");
+        assert_eq!(list[2].props.getcontentlength, Some(31457280));
+    }
+
+    #[test]
+    fn test_iis() {
+        let list = parse_fixture("iis.xml");
+        assert_eq!(list.len(), 2);
+
+        assert_eq!(list[0].href, "/share/");
+        assert!(list[0].props.is_collection);
+        assert_eq!(list[0].props.displayname.as_deref(), Some("share"));
+
+        assert_eq!(list[1].href, "/share/song.m4a");
+        assert_eq!(list[1].props.displayname.as_deref(), Some("song.m4a"));
+        assert_eq!(list[1].props.getcontentlength, Some(8123456));
+        assert_eq!(list[1].props.getcontenttype.as_deref(), Some("audio/mp4"));
+    }
+
+    #[test]
+    fn test_default_namespace_and_failed_response() {
+        let list = parse_fixture("default_ns.xml");
+        assert_eq!(list.len(), 2);
+
+        assert_eq!(list[0].href, "/");
+        assert!(list[0].props.is_collection);
+
+        assert_eq!(list[1].href, "/lyrics.lrc");
+        assert_eq!(list[1].props.getcontentlength, Some(1024));
+        assert_eq!(
+            list[1].props.getlastmodified.as_deref(),
+            Some("Wed, 01 May 2024 08:00:00 GMT")
+        );
+        // The `DAV:` namespace is declared elsewhere, the custom one must not be mistaken for it.
+        assert_eq!(list[1].props.getetag.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn test_sync_collection() {
+        let text =
+            std::fs::read_to_string("test/assets/webdav_multistatus/sync_collection.xml").unwrap();
+        let sync = parse_sync_collection(&text).unwrap();
+        assert_eq!(
+            sync.sync_token.as_deref(),
+            Some("http://example.com/ns/sync/1235")
+        );
+        assert_eq!(sync.changed.len(), 1);
+        assert_eq!(sync.changed[0].href, "/dav/music/new.mp3");
+        assert_eq!(
+            sync.changed[0].props.getetag.as_deref(),
+            Some("\"33441-34321\"")
+        );
+        assert_eq!(sync.removed, vec!["/dav/music/old.mp3".to_string()]);
+
+        // The removed member must not leak into a plain PROPFIND parse
+        assert_eq!(parse_multistatus(&text).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_xml() {
+        assert!(parse_multistatus("<a></b>").is_err());
+    }
+}
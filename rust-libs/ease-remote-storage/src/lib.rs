@@ -1,8 +1,29 @@
 mod backend;
+mod cache;
+mod changes;
 mod env;
+mod http_client;
 mod impls;
+mod retry;
+mod seekable;
+mod walk;
 
-pub use backend::{Entry, StorageBackend, StorageBackendError, StorageBackendResult, StreamFile};
+pub use backend::{
+    Entry, StorageBackend, StorageBackendError, StorageBackendResult, StreamFile, UploadFile,
+};
 pub use bytes;
-pub use impls::{BuildOneDriveArg, BuildWebdavArg, LocalBackend, OneDriveBackend, Webdav};
+pub use cache::{CachedBackend, DiskCache, DiskCacheEntryState, DiskCacheUsage};
+pub use changes::{StorageChange, StorageChanges};
+pub use http_client::{HttpNetworkConfig, HttpPoolConfig};
+pub use impls::{
+    BuildHttpIndexArg, BuildJellyfinArg, BuildOneDriveArg, BuildS3Arg, BuildSftpArg,
+    BuildSubsonicArg, BuildWebdavArg, HttpIndexBackend, JellyfinBackend, LocalBackend,
+    MemoryBackend, MemoryFault, OneDriveAppConfig, OneDriveBackend, OneDriveDrive,
+    OneDriveDriveInfo, OneDriveDriveKind, OneDrivePkce, OneDriveRefreshTokenCallback, S3Backend,
+    SftpAuth, SftpBackend, SftpHostKeyCallback, SubsonicBackend, Webdav,
+};
 pub use reqwest::StatusCode;
+pub use retry::RetryPolicy;
+pub use seekable::SeekableStreamFile;
+pub use tokio_util::sync::CancellationToken;
+pub use walk::{walk, WalkOptions, WalkProgress, WalkProgressCallback};
@@ -0,0 +1,490 @@
+use std::{
+    collections::hash_map::RandomState,
+    error::Error,
+    hash::{BuildHasher, Hasher},
+    io::ErrorKind,
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use ease_client_tokio::tokio_runtime;
+use reqwest::{header::HeaderMap, StatusCode, Url};
+
+use crate::{
+    backend::parse_http_time,
+    http_client::{with_read_timeout, SharedHttpClient},
+    StorageBackendError, StorageBackendResult,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one.
+    pub max_attempts: u32,
+    /// Backoff before the second attempt, doubled for each one after.
+    pub base_delay: Duration,
+    /// Upper bound of the backoff. A `Retry-After` longer than this is not waited for.
+    pub max_delay: Duration,
+    /// Also send POST, DELETE, MOVE and the like again. Off by default, a request that timed
+    /// out may still have been applied, and a second token refresh or folder create then
+    /// fails or duplicates it.
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Exponential backoff with jitter, somewhere in the upper half of the window.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(1u32 << (attempt - 1).min(16))
+            .min(self.max_delay);
+        let jitter = RandomState::new().build_hasher().finish() % 1024;
+        exp / 2 + exp / 2 * jitter as u32 / 1024
+    }
+}
+
+/// Methods that leave the same state however often they are applied. DELETE is left out,
+/// a replay after a lost answer reports the file as missing.
+fn is_idempotent(method: &reqwest::Method) -> bool {
+    matches!(
+        method.as_str(),
+        "GET" | "HEAD" | "OPTIONS" | "PUT" | "PROPFIND"
+    )
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || (status.is_server_error() && status != StatusCode::NOT_IMPLEMENTED)
+}
+
+fn is_retryable_io(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::TimedOut
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+fn is_retryable_error(e: &reqwest::Error) -> bool {
+    // `is_request` also covers connections the server closed before answering
+    if e.is_timeout() || e.is_connect() || e.is_request() {
+        return true;
+    }
+    let mut source = e.source();
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            if is_retryable_io(e) {
+                return true;
+            }
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+pub(crate) fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let v = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(secs) = v.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = parse_http_time(v)?;
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()?;
+    Some(at.saturating_sub(now))
+}
+
+/// Sends the request, and sends it again on timeouts, dropped connections, 429 and 5xx as
+/// long as `policy` allows. A streamed body can't be replayed, so such requests go out once,
+/// and so do non-idempotent methods unless the policy opts in.
+pub(crate) async fn send_with_retry(
+    policy: &RetryPolicy,
+    client: &SharedHttpClient,
+    method: reqwest::Method,
+    url: Url,
+    headers: HeaderMap,
+    body: Option<reqwest::Body>,
+) -> StorageBackendResult<reqwest::Response> {
+    let replay: Option<Option<Bytes>> = if !policy.retry_non_idempotent && !is_idempotent(&method) {
+        None
+    } else {
+        match &body {
+            None => Some(None),
+            Some(body) => body.as_bytes().map(|v| Some(Bytes::copy_from_slice(v))),
+        }
+    };
+    let mut body = body;
+    let mut attempt: u32 = 1;
+    let read_timeout = client.read_timeout();
+    let client = client.get()?;
+
+    loop {
+        let body = match &replay {
+            Some(replay) => replay.clone().map(reqwest::Body::from),
+            None => body.take(),
+        };
+        let mut req = client
+            .request(method.clone(), url.clone())
+            .headers(headers.clone());
+        if let Some(body) = body {
+            req = req.body(body);
+        }
+        let res = tokio_runtime()
+            .spawn(with_read_timeout(read_timeout, req.send()))
+            .await?;
+
+        if replay.is_none() || attempt >= policy.max_attempts {
+            return Ok(res??);
+        }
+        let delay = match &res {
+            Ok(Ok(resp)) if is_retryable_status(resp.status()) => {
+                match parse_retry_after(resp.headers()) {
+                    Some(delay) if delay > policy.max_delay => return Ok(res??),
+                    Some(delay) => delay,
+                    None => policy.backoff(attempt),
+                }
+            }
+            Ok(Err(e)) if is_retryable_error(e) => policy.backoff(attempt),
+            Err(_) => policy.backoff(attempt),
+            _ => return Ok(res??),
+        };
+
+        tracing::warn!(
+            "{method} {url} failed on attempt {attempt}, retry in {}ms",
+            delay.as_millis()
+        );
+        tokio_runtime().spawn(tokio::time::sleep(delay)).await?;
+        attempt += 1;
+    }
+}
+
+impl StorageBackendError {
+    /// Whether the error is worth another try, once the retry policy ran out.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            StorageBackendError::RequestFail(e) => {
+                is_retryable_error(e) || e.status().is_some_and(is_retryable_status)
+            }
+            StorageBackendError::TokioIO(e) => is_retryable_io(e),
+            StorageBackendError::RateLimited { .. } => true,
+            StorageBackendError::ServerError(status) => is_retryable_status(*status),
+            // Like any failed connect, the network may be back on the next try
+            StorageBackendError::DnsError(_) | StorageBackendError::ConnectionRefused => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use hyper::{Body, Request, Response};
+    use reqwest::{header::HeaderMap, StatusCode};
+    use tokio::task::JoinHandle;
+
+    use crate::http_client::{HttpNetworkConfig, SharedHttpClient};
+
+    use super::{parse_retry_after, send_with_retry, RetryPolicy};
+
+    struct SetupServerRes {
+        addr: String,
+        hits: Arc<AtomicUsize>,
+        handle: JoinHandle<()>,
+    }
+    impl Drop for SetupServerRes {
+        fn drop(&mut self) {
+            self.handle.abort();
+        }
+    }
+
+    /// Answers the first `failures` requests with `status` and `Retry-After`, then 200 with
+    /// the request body echoed back.
+    async fn setup_server(
+        failures: usize,
+        status: StatusCode,
+        retry_after: Option<&'static str>,
+    ) -> SetupServerRes {
+        let hits: Arc<AtomicUsize> = Default::default();
+        let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let make_service = hyper::service::make_service_fn({
+            let hits = hits.clone();
+            move |_| {
+                let hits = hits.clone();
+                async move {
+                    Ok::<_, Infallible>(hyper::service::service_fn(move |req: Request<Body>| {
+                        let hit = hits.fetch_add(1, Ordering::SeqCst);
+                        async move {
+                            if hit < failures {
+                                let mut resp = Response::builder().status(status.as_u16());
+                                if let Some(retry_after) = retry_after {
+                                    resp = resp.header("Retry-After", retry_after);
+                                }
+                                return Ok::<_, Infallible>(resp.body(Body::empty()).unwrap());
+                            }
+                            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                            Ok(Response::new(Body::from(body)))
+                        }
+                    }))
+                }
+            }
+        });
+        let server = hyper::Server::bind(&addr).serve(make_service);
+        let port = server.local_addr().port();
+        let handle = tokio::spawn(async move {
+            server.await.unwrap();
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        SetupServerRes {
+            addr: format!("http://127.0.0.1:{port}/"),
+            hits,
+            handle,
+        }
+    }
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(2),
+            retry_non_idempotent: false,
+        }
+    }
+
+    async fn send(
+        server: &SetupServerRes,
+        policy: &RetryPolicy,
+        body: Option<reqwest::Body>,
+    ) -> reqwest::Response {
+        send_with_retry(
+            policy,
+            &SharedHttpClient::new(
+                Duration::from_secs(10),
+                Default::default(),
+                Default::default(),
+            ),
+            reqwest::Method::PUT,
+            server.addr.parse().unwrap(),
+            Default::default(),
+            body,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retry_server_error() {
+        let server = setup_server(2, StatusCode::SERVICE_UNAVAILABLE, None).await;
+        let resp = send(&server, &fast_policy(), Some(reqwest::Body::from("abc"))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.text().await.unwrap(), "abc");
+        assert_eq!(server.hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up() {
+        let server = setup_server(5, StatusCode::BAD_GATEWAY, None).await;
+        let resp = send(&server, &fast_policy(), None).await;
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(server.hits.load(Ordering::SeqCst), 3);
+
+        let server = setup_server(1, StatusCode::NOT_FOUND, None).await;
+        let resp = send(&server, &fast_policy(), None).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(server.hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        let server = setup_server(1, StatusCode::TOO_MANY_REQUESTS, Some("1")).await;
+        let start = Instant::now();
+        let resp = send(&server, &fast_policy(), None).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        // Longer than the policy allows, the 429 is handed back right away
+        let server = setup_server(1, StatusCode::TOO_MANY_REQUESTS, Some("3600")).await;
+        let resp = send(&server, &fast_policy(), None).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(server.hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_body_not_replayed() {
+        let server = setup_server(1, StatusCode::SERVICE_UNAVAILABLE, None).await;
+        let (tx, rx) = async_channel::bounded::<Result<bytes::Bytes, std::io::Error>>(1);
+        tx.send(Ok(bytes::Bytes::from_static(b"abc")))
+            .await
+            .unwrap();
+        tx.close();
+        let resp = send(
+            &server,
+            &fast_policy(),
+            Some(reqwest::Body::wrap_stream(rx)),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.hits.load(Ordering::SeqCst), 1);
+    }
+
+    /// Counts the requests and never answers them.
+    async fn setup_silent_server() -> SetupServerRes {
+        let hits: Arc<AtomicUsize> = Default::default();
+        let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let make_service = hyper::service::make_service_fn({
+            let hits = hits.clone();
+            move |_| {
+                let hits = hits.clone();
+                async move {
+                    Ok::<_, Infallible>(hyper::service::service_fn(move |_req: Request<Body>| {
+                        hits.fetch_add(1, Ordering::SeqCst);
+                        async move {
+                            tokio::time::sleep(Duration::from_secs(60)).await;
+                            Ok::<_, Infallible>(Response::new(Body::empty()))
+                        }
+                    }))
+                }
+            }
+        });
+        let server = hyper::Server::bind(&addr).serve(make_service);
+        let port = server.local_addr().port();
+        let handle = tokio::spawn(async move {
+            server.await.unwrap();
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        SetupServerRes {
+            addr: format!("http://127.0.0.1:{port}/"),
+            hits,
+            handle,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_post_not_replayed() {
+        let client = SharedHttpClient::new(
+            Duration::from_secs(10),
+            Default::default(),
+            HttpNetworkConfig {
+                read_timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        );
+        let post = |server: &SetupServerRes, policy: RetryPolicy| {
+            let url = server.addr.parse().unwrap();
+            let client = &client;
+            async move {
+                send_with_retry(
+                    &policy,
+                    client,
+                    reqwest::Method::POST,
+                    url,
+                    Default::default(),
+                    Some(reqwest::Body::from("grant_type=refresh_token")),
+                )
+                .await
+            }
+        };
+
+        let server = setup_silent_server().await;
+        let res = post(&server, fast_policy()).await;
+        assert!(res.is_err_and(|e| e.is_timeout()));
+        assert_eq!(server.hits.load(Ordering::SeqCst), 1);
+
+        let server = setup_silent_server().await;
+        let policy = RetryPolicy {
+            retry_non_idempotent: true,
+            ..fast_policy()
+        };
+        let res = post(&server, policy).await;
+        assert!(res.is_err_and(|e| e.is_timeout()));
+        assert_eq!(server.hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_connection_refused() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let start = Instant::now();
+        let res = send_with_retry(
+            &fast_policy(),
+            &SharedHttpClient::new(
+                Duration::from_secs(10),
+                Default::default(),
+                Default::default(),
+            ),
+            reqwest::Method::GET,
+            format!("http://127.0.0.1:{port}/").parse().unwrap(),
+            Default::default(),
+            None,
+        )
+        .await;
+        assert!(res.is_err_and(|e| e.is_retryable()));
+        // Two backoffs of at least 5ms and 10ms
+        assert!(start.elapsed() >= Duration::from_millis(15));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            ..Default::default()
+        };
+        for _ in 0..32 {
+            let d = policy.backoff(1);
+            assert!(d >= Duration::from_millis(50) && d <= Duration::from_millis(100));
+            let d = policy.backoff(3);
+            assert!(d >= Duration::from_millis(200) && d <= Duration::from_millis(400));
+            let d = policy.backoff(8);
+            assert!(d >= Duration::from_millis(500) && d <= Duration::from_millis(1000));
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+        headers.insert("Retry-After", "120".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+        headers.insert(
+            "Retry-After",
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+    }
+}
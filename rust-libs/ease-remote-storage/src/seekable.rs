@@ -0,0 +1,341 @@
+use std::{
+    io::{self, ErrorKind, SeekFrom},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use ease_client_tokio::tokio_runtime;
+use futures_util::{future::BoxFuture, FutureExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
+use crate::{StorageBackend, StorageBackendError, StorageBackendResult, StreamFile};
+
+/// Bytes kept behind the read position, seeking back into them costs nothing.
+const WINDOW_SIZE: usize = 1 << 20;
+/// Seeking forward by up to this much reads through the open stream, a new request would
+/// cost more than the bytes skipped.
+const SKIP_LIMIT: u64 = 256 << 10;
+
+type Chunks = Pin<Box<async_channel::Receiver<StorageBackendResult<Bytes>>>>;
+
+enum Source {
+    /// The ranged `get` issued after a seek out of the window.
+    Opening(BoxFuture<'static, StorageBackendResult<StreamFile>>),
+    Streaming(Chunks),
+}
+
+/// Reads a remote file as `AsyncRead + AsyncSeek`, or `Read + Seek` off the runtime, so
+/// that demuxers and tag parsers can work on it without downloading it first. Seeks that
+/// land in the bytes around the read position are served from memory, the others reissue
+/// a ranged `get` on the next read.
+pub struct SeekableStreamFile {
+    backend: Arc<dyn StorageBackend + Send + Sync>,
+    path: String,
+    len: Option<u64>,
+    pos: u64,
+    /// Bytes of the file from `buf_start` on, the source continues where they end.
+    buf: Vec<u8>,
+    buf_start: u64,
+    source: Option<Source>,
+}
+
+fn into_io_error(e: StorageBackendError) -> io::Error {
+    match e {
+        StorageBackendError::TokioIO(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+impl SeekableStreamFile {
+    /// `file` is a read of `path` from byte zero, it is used until the first seek away.
+    pub fn new(
+        backend: Arc<dyn StorageBackend + Send + Sync>,
+        path: String,
+        file: StreamFile,
+    ) -> Self {
+        let len = file
+            .total_size()
+            .or_else(|| file.size().map(|size| size as u64));
+        Self {
+            backend,
+            path,
+            len,
+            pos: 0,
+            buf: Default::default(),
+            buf_start: 0,
+            source: Some(Source::Streaming(Box::pin(file.into_rx()))),
+        }
+    }
+
+    pub async fn open(
+        backend: Arc<dyn StorageBackend + Send + Sync>,
+        path: String,
+    ) -> StorageBackendResult<Self> {
+        let file = backend.get(path.clone(), 0).await?;
+        Ok(Self::new(backend, path, file))
+    }
+
+    /// Size of the file, `None` until the end was reached when the remote didn't tell.
+    pub fn len(&self) -> Option<u64> {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == Some(0)
+    }
+
+    fn buf_end(&self) -> u64 {
+        self.buf_start + self.buf.len() as u64
+    }
+
+    fn seek_to(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let pos = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => {
+                let len = self.len.ok_or_else(|| {
+                    io::Error::new(ErrorKind::Unsupported, "size of the file is unknown")
+                })?;
+                len.checked_add_signed(delta)
+            }
+        };
+        self.pos = pos.ok_or_else(|| io::Error::from(ErrorKind::InvalidInput))?;
+        Ok(self.pos)
+    }
+
+    fn reopen(&mut self) {
+        let backend = self.backend.clone();
+        let path = self.path.clone();
+        let pos = self.pos;
+        self.buf.clear();
+        self.buf_start = pos;
+        self.source = Some(Source::Opening(
+            async move { backend.get(path, pos).await }.boxed(),
+        ));
+    }
+
+    fn push(&mut self, chunk: Bytes) {
+        self.buf.extend_from_slice(&chunk);
+        if self.buf.len() > 2 * WINDOW_SIZE {
+            // Never drop the bytes the reader is about to get
+            let unread = self.pos.saturating_sub(self.buf_start) as usize;
+            let drop = (self.buf.len() - WINDOW_SIZE).min(unread);
+            self.buf.drain(..drop);
+            self.buf_start += drop as u64;
+        }
+    }
+
+    /// Fetches more bytes from the source, `Ok(false)` once it ended.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        loop {
+            match self.source.as_mut() {
+                None => return Poll::Ready(Ok(false)),
+                Some(Source::Opening(fut)) => match fut.poll_unpin(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Ok(file)) => {
+                        if self.len.is_none() {
+                            self.len = file.total_size();
+                        }
+                        self.source = Some(Source::Streaming(Box::pin(file.into_rx())));
+                    }
+                    Poll::Ready(Err(e)) => {
+                        self.source = None;
+                        return Poll::Ready(Err(into_io_error(e)));
+                    }
+                },
+                Some(Source::Streaming(rx)) => {
+                    return match rx.poll_next_unpin(cx) {
+                        Poll::Pending => Poll::Pending,
+                        Poll::Ready(Some(Ok(chunk))) => {
+                            self.push(chunk);
+                            Poll::Ready(Ok(true))
+                        }
+                        Poll::Ready(Some(Err(e))) => {
+                            self.source = None;
+                            Poll::Ready(Err(into_io_error(e)))
+                        }
+                        Poll::Ready(None) => {
+                            self.source = None;
+                            Poll::Ready(Ok(false))
+                        }
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl AsyncRead for SeekableStreamFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.len.is_some_and(|len| this.pos >= len) || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            if this.pos >= this.buf_start && this.pos < this.buf_end() {
+                let offset = (this.pos - this.buf_start) as usize;
+                let n = buf.remaining().min(this.buf.len() - offset);
+                buf.put_slice(&this.buf[offset..offset + n]);
+                this.pos += n as u64;
+                return Poll::Ready(Ok(()));
+            }
+
+            let end = this.buf_end();
+            let ahead = this.source.is_some() && this.pos >= end && this.pos - end <= SKIP_LIMIT;
+            if !ahead {
+                this.reopen();
+            }
+            match this.poll_fill(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(true)) => {}
+                Poll::Ready(Ok(false)) => {
+                    let end = this.buf_end();
+                    match this.len {
+                        None => this.len = Some(end),
+                        Some(len) if end < len => {
+                            return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()));
+                        }
+                        Some(_) => {}
+                    }
+                    if this.pos >= end {
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl AsyncSeek for SeekableStreamFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        self.get_mut().seek_to(position).map(|_| ())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+/// Blocks on the shared runtime, so it must not be used from inside an async task.
+impl io::Read for SeekableStreamFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        tokio_runtime().block_on(std::future::poll_fn(|cx| {
+            let mut buf = ReadBuf::new(buf);
+            Pin::new(&mut *self)
+                .poll_read(cx, &mut buf)
+                .map_ok(|()| buf.filled().len())
+        }))
+    }
+}
+
+impl io::Seek for SeekableStreamFile {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.seek_to(position)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::SeekFrom, sync::Arc};
+
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    use crate::{MemoryBackend, MemoryFault, SeekableStreamFile};
+
+    fn file_data(len: u32) -> Vec<u8> {
+        (0..len).map(|v| (v % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn test_read_and_seek() {
+        let backend = Arc::new(MemoryBackend::new());
+        let data = file_data(3_000_000);
+        backend.add_file("/a.flac", data.clone());
+
+        let mut file = SeekableStreamFile::open(backend.clone(), "/a.flac".to_string())
+            .await
+            .unwrap();
+        assert_eq!(file.len(), Some(data.len() as u64));
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, data);
+        let sent = backend.sent_bytes();
+
+        // Still in the window
+        file.seek(SeekFrom::End(-100_000)).await.unwrap();
+        let mut buf = vec![0u8; 1000];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, data[2_900_000..2_901_000]);
+        assert_eq!(backend.sent_bytes(), sent);
+
+        // Out of it
+        file.seek(SeekFrom::Start(10)).await.unwrap();
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, data[10..1010]);
+        assert!(backend.sent_bytes() > sent);
+        file.seek(SeekFrom::Current(1_000_000)).await.unwrap();
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, data[1_001_010..1_002_010]);
+
+        file.seek(SeekFrom::Start(4_000_000)).await.unwrap();
+        assert_eq!(file.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_range_ignored() {
+        let backend = Arc::new(MemoryBackend::new());
+        let data = file_data(500_000);
+        backend.add_file("/a.flac", data.clone());
+        backend.inject("/", MemoryFault::IgnoreRange);
+
+        let mut file = SeekableStreamFile::open(backend.clone(), "/a.flac".to_string())
+            .await
+            .unwrap();
+        file.seek(SeekFrom::Start(400_000)).await.unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, data[400_000..]);
+    }
+
+    #[tokio::test]
+    async fn test_truncated() {
+        let backend = Arc::new(MemoryBackend::new());
+        backend.add_file("/a.flac", file_data(500_000));
+        backend.inject("/", MemoryFault::Truncate(100_000));
+
+        let mut file = SeekableStreamFile::open(backend.clone(), "/a.flac".to_string())
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        assert!(file.read_to_end(&mut buf).await.is_err());
+        assert_eq!(buf.len(), 100_000);
+    }
+
+    #[test]
+    fn test_std_read_and_seek() {
+        // Resolved by hand, the tokio extensions have the same names
+        use std::io::{Read, Seek};
+
+        let backend = Arc::new(MemoryBackend::new());
+        let data = file_data(300_000);
+        backend.add_file("/a.flac", data.clone());
+
+        let mut file = ease_client_tokio::tokio_runtime()
+            .block_on(SeekableStreamFile::open(backend, "/a.flac".to_string()))
+            .unwrap();
+        let mut buf = vec![0u8; 100];
+        Seek::seek(&mut file, SeekFrom::Start(200_000)).unwrap();
+        Read::read_exact(&mut file, &mut buf).unwrap();
+        assert_eq!(buf, data[200_000..200_100]);
+        Seek::seek(&mut file, SeekFrom::Current(-50)).unwrap();
+        Read::read_exact(&mut file, &mut buf).unwrap();
+        assert_eq!(buf, data[200_050..200_150]);
+    }
+}
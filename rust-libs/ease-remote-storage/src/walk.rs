@@ -0,0 +1,375 @@
+use std::{collections::VecDeque, path::Path, sync::Arc};
+
+use ease_client_tokio::tokio_runtime;
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use tokio_util::sync::CancellationToken;
+
+use crate::{Entry, StorageBackend, StorageBackendResult};
+
+const WALK_CHANNEL_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Default)]
+pub struct WalkProgress {
+    /// Directories listed so far, including those that failed.
+    pub listed_dirs: usize,
+    /// Directories found but not listed yet.
+    pub pending_dirs: usize,
+    /// Entries sent to the stream so far.
+    pub found_entries: usize,
+}
+
+pub type WalkProgressCallback = Arc<dyn Fn(&WalkProgress) + Send + Sync>;
+
+#[derive(Clone)]
+pub struct WalkOptions {
+    /// Entries right under the root are at depth 1. `None` walks the whole tree.
+    pub max_depth: Option<usize>,
+    /// Lower case extensions without the dot. Only files are filtered.
+    pub extensions: Option<Vec<String>>,
+    /// Skips entries whose name starts with a dot, and everything below them.
+    pub skip_hidden: bool,
+    /// Also sends the directories themselves, not only the files in them.
+    pub include_dirs: bool,
+    /// Number of `list` calls in flight at once.
+    pub concurrency: usize,
+    pub cancel: CancellationToken,
+    /// Called after every listed directory.
+    pub on_progress: Option<WalkProgressCallback>,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            extensions: None,
+            skip_hidden: true,
+            include_dirs: false,
+            concurrency: 4,
+            cancel: Default::default(),
+            on_progress: None,
+        }
+    }
+}
+
+impl WalkOptions {
+    fn matches_extension(&self, name: &str) -> bool {
+        let Some(extensions) = &self.extensions else {
+            return true;
+        };
+        let Some(ext) = Path::new(name).extension() else {
+            return false;
+        };
+        let ext = ext.to_string_lossy().to_lowercase();
+        extensions.contains(&ext)
+    }
+}
+
+/// Walks the tree under `root` breadth first. Siblings keep the order of `list`, but with
+/// more than one list in flight the directories may come back in any order.
+///
+/// A directory that fails to list is reported as an error item and the walk goes on with
+/// the rest of the tree. The stream ends when the tree is done, the token is cancelled or
+/// the receiver is dropped.
+pub fn walk(
+    backend: Arc<dyn StorageBackend + Send + Sync>,
+    root: String,
+    options: WalkOptions,
+) -> async_channel::Receiver<StorageBackendResult<Entry>> {
+    let (tx, rx) = async_channel::bounded::<StorageBackendResult<Entry>>(WALK_CHANNEL_SIZE);
+
+    let _ = tokio_runtime().spawn(async move {
+        let cancel = options.cancel.clone();
+        tokio::select! {
+            _ = cancel.cancelled() => {}
+            _ = walk_impl(backend, root, &options, &tx) => {}
+        }
+        tx.close();
+    });
+
+    rx
+}
+
+async fn walk_impl(
+    backend: Arc<dyn StorageBackend + Send + Sync>,
+    root: String,
+    options: &WalkOptions,
+    tx: &async_channel::Sender<StorageBackendResult<Entry>>,
+) {
+    let concurrency = options.concurrency.max(1);
+    let mut pending: VecDeque<(String, usize)> = VecDeque::from([(root, 0)]);
+    let mut listing = FuturesUnordered::new();
+    let mut progress = WalkProgress::default();
+
+    loop {
+        while listing.len() < concurrency {
+            let Some((dir, depth)) = pending.pop_front() else {
+                break;
+            };
+            let backend = backend.clone();
+            listing.push(async move { (backend.list(dir).await, depth + 1) });
+        }
+
+        let Some((res, depth)) = listing.next().await else {
+            break;
+        };
+        progress.listed_dirs += 1;
+
+        match res {
+            Ok(entries) => {
+                for entry in entries {
+                    if options.skip_hidden && entry.name.starts_with('.') {
+                        continue;
+                    }
+                    if entry.is_dir {
+                        if options.max_depth.is_none_or(|max| depth < max) {
+                            pending.push_back((entry.path.clone(), depth));
+                        }
+                        if !options.include_dirs {
+                            continue;
+                        }
+                    } else if !options.matches_extension(&entry.name) {
+                        continue;
+                    }
+
+                    progress.found_entries += 1;
+                    if tx.send(Ok(entry)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                tracing::warn!("walk list fail, {e:?}");
+                if tx.send(Err(e)).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        progress.pending_dirs = pending.len() + listing.len();
+        if let Some(on_progress) = &options.on_progress {
+            on_progress(&progress);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use futures_util::future::BoxFuture;
+
+    use crate::{
+        Entry, StorageBackend, StorageBackendError, StorageBackendResult, StorageChanges,
+        StreamFile, UploadFile,
+    };
+
+    use super::{walk, WalkOptions, WalkProgress};
+
+    #[derive(Default)]
+    struct TreeBackend {
+        tree: HashMap<String, Vec<Entry>>,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    impl TreeBackend {
+        fn new(paths: &[&str]) -> Self {
+            let mut tree: HashMap<String, Vec<Entry>> = Default::default();
+            for p in paths {
+                let is_dir = p.ends_with('/');
+                let p = p.trim_end_matches('/');
+                let (dir, name) = p.rsplit_once('/').unwrap();
+                let dir = if dir.is_empty() { "/" } else { dir };
+                tree.entry(dir.to_string()).or_default().push(Entry {
+                    name: name.to_string(),
+                    path: p.to_string(),
+                    is_dir,
+                    ..Default::default()
+                });
+                if is_dir {
+                    tree.entry(p.to_string()).or_default();
+                }
+            }
+            Self {
+                tree,
+                ..Default::default()
+            }
+        }
+
+        async fn list_impl(&self, dir: String) -> StorageBackendResult<Vec<Entry>> {
+            let n = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(n, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            self.tree
+                .get(&dir)
+                .cloned()
+                .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound).into())
+        }
+    }
+
+    impl StorageBackend for TreeBackend {
+        fn list(&self, dir: String) -> BoxFuture<StorageBackendResult<Vec<Entry>>> {
+            Box::pin(self.list_impl(dir))
+        }
+        fn get(
+            &self,
+            _p: String,
+            _byte_offset: u64,
+        ) -> BoxFuture<StorageBackendResult<StreamFile>> {
+            Box::pin(async { Err(StorageBackendError::Unsupported) })
+        }
+        fn put(&self, _p: String, _file: UploadFile) -> BoxFuture<StorageBackendResult<()>> {
+            Box::pin(async { Err(StorageBackendError::Unsupported) })
+        }
+        fn mkdir(&self, _dir: String) -> BoxFuture<StorageBackendResult<()>> {
+            Box::pin(async { Err(StorageBackendError::Unsupported) })
+        }
+        fn delete(&self, _p: String) -> BoxFuture<StorageBackendResult<()>> {
+            Box::pin(async { Err(StorageBackendError::Unsupported) })
+        }
+        fn rename(&self, _from: String, _to: String) -> BoxFuture<StorageBackendResult<()>> {
+            Box::pin(async { Err(StorageBackendError::Unsupported) })
+        }
+        fn changes_since(
+            &self,
+            _dir: String,
+            _cursor: Option<String>,
+        ) -> BoxFuture<StorageBackendResult<StorageChanges>> {
+            Box::pin(async { Err(StorageBackendError::Unsupported) })
+        }
+    }
+
+    fn music_tree() -> Arc<TreeBackend> {
+        Arc::new(TreeBackend::new(&[
+            "/a.mp3",
+            "/cover.jpg",
+            "/.hidden/",
+            "/.hidden/x.mp3",
+            "/artist/",
+            "/artist/b.FLAC",
+            "/artist/album/",
+            "/artist/album/c.mp3",
+            "/artist/album/c.lrc",
+            "/other/",
+            "/other/d.mp3",
+        ]))
+    }
+
+    async fn collect(
+        rx: async_channel::Receiver<StorageBackendResult<Entry>>,
+    ) -> (Vec<String>, usize) {
+        let mut paths = Vec::new();
+        let mut errors = 0;
+        while let Ok(item) = rx.recv().await {
+            match item {
+                Ok(entry) => paths.push(entry.path),
+                Err(_) => errors += 1,
+            }
+        }
+        paths.sort();
+        (paths, errors)
+    }
+
+    #[tokio::test]
+    async fn test_walk_filters() {
+        let backend = music_tree();
+        let options = WalkOptions {
+            extensions: Some(vec!["mp3".to_string(), "flac".to_string()]),
+            ..Default::default()
+        };
+        let (paths, errors) = collect(walk(backend, "/".to_string(), options)).await;
+        assert_eq!(errors, 0);
+        assert_eq!(
+            paths,
+            [
+                "/a.mp3",
+                "/artist/album/c.mp3",
+                "/artist/b.FLAC",
+                "/other/d.mp3"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_walk_max_depth_and_dirs() {
+        let backend = music_tree();
+        let options = WalkOptions {
+            max_depth: Some(2),
+            skip_hidden: false,
+            include_dirs: true,
+            ..Default::default()
+        };
+        let (paths, _) = collect(walk(backend, "/".to_string(), options)).await;
+        assert_eq!(
+            paths,
+            [
+                "/.hidden",
+                "/.hidden/x.mp3",
+                "/a.mp3",
+                "/artist",
+                "/artist/album",
+                "/artist/b.FLAC",
+                "/cover.jpg",
+                "/other",
+                "/other/d.mp3"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_walk_concurrency_and_progress() {
+        let mut paths = vec!["/root/".to_string()];
+        for i in 0..20 {
+            paths.push(format!("/root/{i}/"));
+            paths.push(format!("/root/{i}/a.mp3"));
+        }
+        let paths: Vec<&str> = paths.iter().map(|v| v.as_str()).collect();
+        let backend = Arc::new(TreeBackend::new(&paths));
+
+        let last_progress: Arc<Mutex<WalkProgress>> = Default::default();
+        let options = WalkOptions {
+            concurrency: 3,
+            on_progress: Some({
+                let last_progress = last_progress.clone();
+                Arc::new(move |progress| *last_progress.lock().unwrap() = progress.clone())
+            }),
+            ..Default::default()
+        };
+        let (paths, _) = collect(walk(backend.clone(), "/root".to_string(), options)).await;
+        assert_eq!(paths.len(), 20);
+        assert_eq!(backend.max_in_flight.load(Ordering::SeqCst), 3);
+
+        let progress = last_progress.lock().unwrap().clone();
+        assert_eq!(progress.listed_dirs, 21);
+        assert_eq!(progress.pending_dirs, 0);
+        assert_eq!(progress.found_entries, 20);
+    }
+
+    #[tokio::test]
+    async fn test_walk_error_and_cancel() {
+        let backend = music_tree();
+        let (paths, errors) = collect(walk(
+            backend.clone(),
+            "/missing".to_string(),
+            Default::default(),
+        ))
+        .await;
+        assert!(paths.is_empty());
+        assert_eq!(errors, 1);
+
+        let options = WalkOptions::default();
+        options.cancel.cancel();
+        let (paths, errors) = collect(walk(backend, "/".to_string(), options)).await;
+        assert!(paths.is_empty());
+        assert_eq!(errors, 0);
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use ease_remote_storage::{LocalBackend, StorageBackend};
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const FILE_SIZE: u64 = 256 << 20;
+const MEMORY_BUDGET: usize = 8 << 20;
+
+// Kept as the only test of this binary so that the allocator sees nothing else.
+#[tokio::test]
+async fn test_large_file_stream_memory_bounded() {
+    let p = std::env::temp_dir().join(format!("ease-local-large-{}.bin", std::process::id()));
+    let file = std::fs::File::create(&p).unwrap();
+    file.set_len(FILE_SIZE).unwrap();
+    drop(file);
+    let p = p.to_string_lossy().to_string();
+
+    let backend = LocalBackend::new();
+    // Warm up the runtime so that its own allocations are part of the baseline.
+    let _ = backend
+        .get(p.clone(), FILE_SIZE)
+        .await
+        .unwrap()
+        .bytes()
+        .await;
+
+    let baseline = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+
+    let byte_offset = 1 << 20;
+    let file = backend.get(p.clone(), byte_offset).await.unwrap();
+    assert_eq!(file.size(), Some((FILE_SIZE - byte_offset) as usize));
+
+    let rx = file.into_rx();
+    let mut received: u64 = 0;
+    while let Ok(chunk) = rx.recv().await {
+        received += chunk.unwrap().len() as u64;
+    }
+    assert_eq!(received, FILE_SIZE - byte_offset);
+
+    let peak = PEAK.load(Ordering::SeqCst) - baseline;
+    assert!(
+        peak < MEMORY_BUDGET,
+        "peak memory {peak} bytes while streaming a {FILE_SIZE} bytes file"
+    );
+
+    std::fs::remove_file(&p).unwrap();
+}